@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use crate::config::{ConfigError, server_config::{CONFIG_KEYS, env_name}};
+
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    pub overrides: Vec<(String, String)>,
+}
+
+impl CliArgs {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let mut cli_args = CliArgs {
+            config_path: None,
+            overrides: Vec::new(),
+        };
+
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(ConfigError::HelpRequested);
+            }
+
+            let flag = match arg.strip_prefix("--") {
+                Some(v) => v,
+                None => return Err(ConfigError::UnknownKey(arg)),
+            };
+
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), value.to_owned()),
+                None => match args.next() {
+                    Some(value) => (flag.to_owned(), value),
+                    None => return Err(ConfigError::MissingValue(arg)),
+                },
+            };
+
+            if flag == "config" {
+                cli_args.config_path = Some(PathBuf::from(value));
+                continue;
+            }
+
+            match CONFIG_KEYS.iter().find(|key| flag_name(key) == flag) {
+                Some(key) => cli_args.overrides.push((key.to_string(), value)),
+                None => return Err(ConfigError::UnknownKey(arg)),
+            };
+        }
+
+        Ok(cli_args)
+    }
+}
+
+pub fn flag_name(key: &str) -> String {
+    key.replace(['.', '_'], "-")
+}
+
+pub fn usage() -> String {
+    let mut usage = String::from(
+        "Usage: StockMessenger [--config <path>] [--<option> <value>]...\n\n\
+         Values are read from the config file, then from environment variables, then from flags.\n\n\
+         Options:\n",
+    );
+
+    usage.push_str(&format!("  {:<28} {}\n", "--config", env_name("config")));
+
+    for key in CONFIG_KEYS.iter() {
+        usage.push_str(&format!("  {:<28} {}\n", format!("--{}", flag_name(key)), env_name(key)));
+    }
+
+    usage
+}
@@ -0,0 +1,5 @@
+pub mod server_config;
+pub mod cli;
+
+pub use crate::config::server_config::{ServerConfig, ServerSettings, FeedSettings, CacheSettings, IntervalSettings, StorageSettings, TopMoversSettings, ConfigError};
+pub use crate::config::cli::CliArgs;
@@ -0,0 +1,546 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::{config::CliArgs, value_store::{BarFormat, BasketSpec, MoversWindow, SessionBoundary}};
+
+pub const ENV_PREFIX: &str = "STOCK_MESSENGER_";
+
+/// Dotted keys accepted by [`ServerConfig::set_value`], environment variables and flags.
+pub const CONFIG_KEYS: &[&str] = &[
+    "server.address",
+    "server.queue_capacity",
+    "server.max_subscriptions",
+    "server.max_query_bars",
+    "server.max_list_symbols",
+    "server.max_baskets",
+    "server.max_alerts",
+    "server.alert_session_ttl_secs",
+    "server.bar_format",
+    "server.ping_interval_ms",
+    "server.data_feed_tick_ms",
+    "server.admin_token",
+    "feed.enabled",
+    "feed.address",
+    "feed.reconnect_delay_ms",
+    "cache.history_size",
+    "cache.intervals",
+    "cache.data_history_size",
+    "cache.aggregate",
+    "cache.snapshot_path",
+    "cache.max_idle_secs",
+    "cache.memory_budget_bytes",
+    "cache.reference_data_path",
+    "cache.session_start",
+    "storage.enabled",
+    "storage.path",
+    "storage.segment_bytes",
+    "storage.retention_hours",
+    "storage.fsync",
+    "top_movers.size",
+    "top_movers.interval",
+    "top_movers.window",
+];
+
+/// Errors raised while loading or validating a [`ServerConfig`].
+#[derive(Debug)]
+pub enum ConfigError {
+    HelpRequested,
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownKey(String),
+    MissingValue(String),
+    InvalidValue { key: String, value: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::HelpRequested => write!(f, "help requested"),
+            ConfigError::Io(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "couldn't parse {}: {}", path.display(), e),
+            ConfigError::UnknownKey(key) => write!(f, "unknown option {:?}", key),
+            ConfigError::MissingValue(key) => write!(f, "missing value for option {:?}", key),
+            ConfigError::InvalidValue { key, value, reason } => {
+                write!(f, "invalid value {:?} for {}: {}", value, key, reason)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub address: String,
+    pub queue_capacity: usize,
+    pub max_subscriptions: usize,
+    pub max_query_bars: usize,
+    pub max_list_symbols: usize,
+    /// Most baskets, from the config file and created by clients together.
+    pub max_baskets: usize,
+    /// Most alerts one alert session may hold.
+    pub max_alerts: usize,
+    /// How long an alert session is kept after its connection closed.
+    pub alert_session_ttl_secs: u64,
+    pub bar_format: BarFormat,
+    pub ping_interval_ms: u64,
+    pub data_feed_tick_ms: u64,
+    /// Token required by admin requests. Admin requests are refused when unset.
+    pub admin_token: Option<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            address: "localhost:9002".to_owned(),
+            queue_capacity: 1000,
+            max_subscriptions: 100,
+            max_query_bars: 1000,
+            max_list_symbols: 100,
+            max_baskets: 100,
+            max_alerts: 100,
+            alert_session_ttl_secs: 3600,
+            bar_format: BarFormat::Legacy,
+            ping_interval_ms: 1000,
+            data_feed_tick_ms: 1000,
+            admin_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedSettings {
+    pub enabled: bool,
+    pub address: String,
+    pub reconnect_delay_ms: u64,
+}
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        FeedSettings {
+            enabled: true,
+            address: "localhost:9004".to_owned(),
+            reconnect_delay_ms: 1000,
+        }
+    }
+}
+
+/// Bar intervals, in seconds, kept by default.
+pub const DEFAULT_INTERVALS: [u64; 5] = [1, 10, 60, 300, 600];
+
+/// One bar interval kept in the cache.
+///
+/// In TOML either a plain number of seconds or `{ interval = 60, retention = 240 }`.
+/// On the command line and in the environment a comma separated list like `1,10,60:240`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(from = "IntervalEntry")]
+pub struct IntervalSettings {
+    pub interval: u64,
+    /// Bars kept for this interval, `cache.history_size` if unset.
+    pub retention: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum IntervalEntry {
+    Plain(u64),
+    Detailed { interval: u64, retention: Option<usize> },
+}
+
+impl From<IntervalEntry> for IntervalSettings {
+    fn from(entry: IntervalEntry) -> Self {
+        match entry {
+            IntervalEntry::Plain(interval) => IntervalSettings { interval, retention: None },
+            IntervalEntry::Detailed { interval, retention } => IntervalSettings { interval, retention },
+        }
+    }
+}
+
+impl FromStr for IntervalSettings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (interval, retention) = match s.split_once(':') {
+            Some((interval, retention)) => (interval, Some(retention)),
+            None => (s, None),
+        };
+
+        let interval = interval.trim().parse::<u64>().map_err(|e| e.to_string())?;
+        let retention = match retention {
+            Some(v) => Some(v.trim().parse::<usize>().map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        Ok(IntervalSettings { interval, retention })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    pub history_size: usize,
+    pub intervals: Vec<IntervalSettings>,
+    pub data_history_size: usize,
+    pub aggregate: bool,
+    pub snapshot_path: Option<PathBuf>,
+    /// Stocks without a new bar for this long are dropped, 0 keeps them forever.
+    pub max_idle_secs: u64,
+    /// Estimated size the cached bars may take, 0 for no limit.
+    pub memory_budget_bytes: u64,
+    /// CSV or JSON file with the reference data of the symbols, see [`crate::value_store::ReferenceData`].
+    pub reference_data_path: Option<PathBuf>,
+    /// Time of day, in UTC, at which the session VWAP and TWAP start over.
+    pub session_start: SessionBoundary,
+}
+
+impl CacheSettings {
+    /// Returns the number of bars to keep for every configured interval.
+    pub fn interval_retention(&self) -> BTreeMap<u128, usize> {
+        self.intervals
+            .iter()
+            .map(|v| (v.interval as u128, v.retention.unwrap_or(self.history_size)))
+            .collect()
+    }
+
+    pub fn max_idle_ms(&self) -> Option<u128> {
+        match self.max_idle_secs {
+            0 => None,
+            v => Some(v as u128 * 1000),
+        }
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        match self.memory_budget_bytes {
+            0 => None,
+            v => Some(v as usize),
+        }
+    }
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            history_size: 120,
+            intervals: DEFAULT_INTERVALS
+                .iter()
+                .map(|v| IntervalSettings { interval: *v, retention: None })
+                .collect(),
+            data_history_size: 120,
+            aggregate: false,
+            snapshot_path: None,
+            max_idle_secs: 0,
+            memory_budget_bytes: 0,
+            reference_data_path: None,
+            session_start: SessionBoundary::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub segment_bytes: u64,
+    pub retention_hours: u64,
+    pub fsync: bool,
+}
+
+impl StorageSettings {
+    pub fn retention_ms(&self) -> u128 {
+        self.retention_hours as u128 * 3_600_000
+    }
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            enabled: false,
+            path: PathBuf::from("data/bars"),
+            segment_bytes: 16 * 1024 * 1024,
+            retention_hours: 24 * 7,
+            fsync: true,
+        }
+    }
+}
+
+/// The leaderboard published to the subscribers of `"TopMovers"`, see [`crate::value_store::TopMovers`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopMoversSettings {
+    /// Symbols in every list.
+    pub size: usize,
+    /// Interval, in seconds, of the bars the moves are measured on, the finest configured
+    /// interval if unset.
+    pub interval: Option<u64>,
+    /// Number of bars of `interval` the moves are measured over.
+    pub window: usize,
+}
+
+impl TopMoversSettings {
+    pub fn movers_window(&self, cache: &CacheSettings) -> MoversWindow {
+        let stock_interval = match self.interval {
+            Some(v) => v,
+            None => cache.intervals.iter().map(|v| v.interval).min().unwrap_or(1),
+        };
+
+        MoversWindow {
+            stock_interval: stock_interval as u128,
+            bars: self.window,
+            size: self.size,
+        }
+    }
+}
+
+impl Default for TopMoversSettings {
+    fn default() -> Self {
+        TopMoversSettings {
+            size: 10,
+            interval: None,
+            window: 60,
+        }
+    }
+}
+
+/// Runtime settings of the server, read from a TOML file with one table per section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ServerSettings,
+    pub feed: FeedSettings,
+    pub cache: CacheSettings,
+    pub storage: StorageSettings,
+    pub top_movers: TopMoversSettings,
+    /// Baskets created at startup, one `[[baskets]]` table each. Only read from the file.
+    pub baskets: Vec<BasketSpec>,
+}
+
+impl ServerConfig {
+    /// Reads a TOML file. Missing keys keep their defaults.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Builds the configuration from the defaults, the config file, the environment
+    /// and `args`, in that order, and validates the result.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let cli_args = CliArgs::parse(args)?;
+
+        let config_path = match cli_args.config_path {
+            Some(v) => Some(v),
+            None => env::var_os(format!("{}CONFIG", ENV_PREFIX)).map(PathBuf::from),
+        };
+
+        let mut config = match config_path {
+            Some(path) => ServerConfig::from_file(&path)?,
+            None => ServerConfig::default(),
+        };
+
+        config.apply_env()?;
+
+        for (key, value) in cli_args.overrides.iter() {
+            config.set_value(key, value)?;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        for key in CONFIG_KEYS.iter() {
+            if let Ok(value) = env::var(env_name(key)) {
+                self.set_value(key, &value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets one value by its dotted key, e.g. `server.address`.
+    pub fn set_value(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "server.address" => self.server.address = value.to_owned(),
+            "server.queue_capacity" => self.server.queue_capacity = parse_value(key, value)?,
+            "server.max_subscriptions" => self.server.max_subscriptions = parse_value(key, value)?,
+            "server.max_query_bars" => self.server.max_query_bars = parse_value(key, value)?,
+            "server.max_list_symbols" => self.server.max_list_symbols = parse_value(key, value)?,
+            "server.max_baskets" => self.server.max_baskets = parse_value(key, value)?,
+            "server.max_alerts" => self.server.max_alerts = parse_value(key, value)?,
+            "server.alert_session_ttl_secs" => self.server.alert_session_ttl_secs = parse_value(key, value)?,
+            "server.bar_format" => self.server.bar_format = parse_value(key, value)?,
+            "server.ping_interval_ms" => self.server.ping_interval_ms = parse_value(key, value)?,
+            "server.data_feed_tick_ms" => self.server.data_feed_tick_ms = parse_value(key, value)?,
+            "server.admin_token" => self.server.admin_token = match value.trim() {
+                "" => None,
+                v => Some(v.to_owned()),
+            },
+            "feed.enabled" => self.feed.enabled = parse_value(key, value)?,
+            "feed.address" => self.feed.address = value.to_owned(),
+            "feed.reconnect_delay_ms" => self.feed.reconnect_delay_ms = parse_value(key, value)?,
+            "cache.history_size" => self.cache.history_size = parse_value(key, value)?,
+            "cache.intervals" => {
+                self.cache.intervals = value
+                    .split(',')
+                    .filter(|v| !v.trim().is_empty())
+                    .map(|v| parse_value(key, v))
+                    .collect::<Result<Vec<IntervalSettings>, ConfigError>>()?;
+            },
+            "cache.data_history_size" => self.cache.data_history_size = parse_value(key, value)?,
+            "cache.aggregate" => self.cache.aggregate = parse_value(key, value)?,
+            "cache.snapshot_path" => self.cache.snapshot_path = match value.trim() {
+                "" => None,
+                v => Some(PathBuf::from(v)),
+            },
+            "cache.max_idle_secs" => self.cache.max_idle_secs = parse_value(key, value)?,
+            "cache.memory_budget_bytes" => self.cache.memory_budget_bytes = parse_value(key, value)?,
+            "cache.reference_data_path" => self.cache.reference_data_path = match value.trim() {
+                "" => None,
+                v => Some(PathBuf::from(v)),
+            },
+            "cache.session_start" => self.cache.session_start = parse_value(key, value)?,
+            "storage.enabled" => self.storage.enabled = parse_value(key, value)?,
+            "storage.path" => self.storage.path = PathBuf::from(value),
+            "storage.segment_bytes" => self.storage.segment_bytes = parse_value(key, value)?,
+            "storage.retention_hours" => self.storage.retention_hours = parse_value(key, value)?,
+            "storage.fsync" => self.storage.fsync = parse_value(key, value)?,
+            "top_movers.size" => self.top_movers.size = parse_value(key, value)?,
+            "top_movers.interval" => self.top_movers.interval = match value.trim() {
+                "" => None,
+                v => Some(parse_value(key, v)?),
+            },
+            "top_movers.window" => self.top_movers.window = parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_owned())),
+        };
+
+        Ok(())
+    }
+
+    /// Checks addresses and that every size and period is usable.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        validate_address("server.address", &self.server.address)?;
+        validate_address("feed.address", &self.feed.address)?;
+
+        validate_non_zero("server.queue_capacity", self.server.queue_capacity as u64)?;
+        validate_non_zero("server.max_subscriptions", self.server.max_subscriptions as u64)?;
+        validate_non_zero("server.max_query_bars", self.server.max_query_bars as u64)?;
+        validate_non_zero("server.max_list_symbols", self.server.max_list_symbols as u64)?;
+        validate_non_zero("server.max_baskets", self.server.max_baskets as u64)?;
+        validate_non_zero("server.max_alerts", self.server.max_alerts as u64)?;
+        validate_non_zero("server.alert_session_ttl_secs", self.server.alert_session_ttl_secs)?;
+        validate_non_zero("server.data_feed_tick_ms", self.server.data_feed_tick_ms)?;
+        validate_non_zero("feed.reconnect_delay_ms", self.feed.reconnect_delay_ms)?;
+        validate_non_zero("cache.history_size", self.cache.history_size as u64)?;
+        validate_non_zero("cache.data_history_size", self.cache.data_history_size as u64)?;
+        validate_intervals(&self.cache.intervals)?;
+        validate_baskets(&self.baskets, self.server.max_baskets)?;
+        validate_non_zero("storage.segment_bytes", self.storage.segment_bytes)?;
+        validate_non_zero("storage.retention_hours", self.storage.retention_hours)?;
+        validate_non_zero("top_movers.size", self.top_movers.size as u64)?;
+        validate_non_zero("top_movers.window", self.top_movers.window as u64)?;
+
+        if let Some(v) = self.top_movers.interval.filter(|v| !self.cache.intervals.iter().any(|settings| settings.interval == *v)) {
+            return Err(invalid_value("top_movers.interval", &v.to_string(), "isn't one of cache.intervals"));
+        }
+
+        if self.server.ping_interval_ms < 10 {
+            return Err(invalid_value(
+                "server.ping_interval_ms",
+                &self.server.ping_interval_ms.to_string(),
+                "must be at least 10",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value.trim().parse::<T>().map_err(|e| invalid_value(key, value, &e.to_string()))
+}
+
+fn invalid_value(key: &str, value: &str, reason: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        reason: reason.to_owned(),
+    }
+}
+
+fn validate_non_zero(key: &str, value: u64) -> Result<(), ConfigError> {
+    match value {
+        0 => Err(invalid_value(key, "0", "must be greater than 0")),
+        _ => Ok(()),
+    }
+}
+
+fn validate_intervals(intervals: &[IntervalSettings]) -> Result<(), ConfigError> {
+    if intervals.is_empty() {
+        return Err(invalid_value("cache.intervals", "[]", "at least one interval is required"));
+    }
+
+    for (i, settings) in intervals.iter().enumerate() {
+        validate_non_zero("cache.intervals", settings.interval)?;
+
+        if settings.retention == Some(0) {
+            return Err(invalid_value("cache.intervals", &format!("{}:0", settings.interval), "retention must be greater than 0"));
+        }
+
+        if intervals[..i].iter().any(|v| v.interval == settings.interval) {
+            return Err(invalid_value("cache.intervals", &settings.interval.to_string(), "interval is listed twice"));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_baskets(baskets: &[BasketSpec], max_baskets: usize) -> Result<(), ConfigError> {
+    if baskets.len() > max_baskets {
+        return Err(invalid_value("baskets", &baskets.len().to_string(), "more baskets than server.max_baskets"));
+    }
+
+    for (i, spec) in baskets.iter().enumerate() {
+        spec.validate().map_err(|e| invalid_value("baskets", &spec.name, &e))?;
+
+        if baskets[..i].iter().any(|v| v.name == spec.name) {
+            return Err(invalid_value("baskets", &spec.name, "basket name is used twice"));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_address(key: &str, address: &str) -> Result<(), ConfigError> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some(v) => v,
+        None => return Err(invalid_value(key, address, "expected host:port")),
+    };
+
+    if host.is_empty() {
+        return Err(invalid_value(key, address, "host is empty"));
+    }
+
+    match port.parse::<u16>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(invalid_value(key, address, "port is not a number between 0 and 65535")),
+    }
+}
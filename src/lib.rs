@@ -0,0 +1,40 @@
+//! StockMessenger reads OHLC bars from an upstream websocket feed, keeps a short
+//! history per stock and interval, and forwards bars to subscribed websocket clients.
+//!
+//! The quickest way to embed it is [`WebSocketServer::builder`]:
+//!
+//! ```no_run
+//! use stock_messenger::{OHLCModel, WebSocketServer};
+//!
+//! let server = WebSocketServer::builder()
+//!     .server_address("0.0.0.0:9002")
+//!     .without_feed()
+//!     .build()
+//!     .unwrap();
+//!
+//! let connection_service = server.connection_service();
+//!
+//! std::thread::spawn(move || server.run());
+//!
+//! connection_service.publish_ohlc(OHLCModel {
+//!     stock_name: "AAPL".to_owned(),
+//!     price_close: 231.5,
+//!     stock_interval: 1,
+//!     ..OHLCModel::default()
+//! }).unwrap();
+//! ```
+
+pub mod config;
+pub mod value_store;
+pub mod websockets;
+
+pub use crate::config::{ConfigError, ServerConfig};
+pub use crate::value_store::{OHLCModel, StockInformationCacheInterface};
+pub use crate::websockets::{
+    ConnectionService,
+    ShutdownHandle,
+    NotificationClient,
+    NotificationServer,
+    WebSocketServer,
+    WebSocketServerBuilder,
+};
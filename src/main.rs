@@ -1,9 +1,60 @@
-mod value_store;
-mod websockets;
+use std::{env, future::Future, process};
 
-use crate::websockets::websocket_server::WebSocketServer;
+use stock_messenger::{
+    config::cli,
+    ConfigError,
+    ServerConfig,
+    WebSocketServer,
+};
 
-fn main() {
-    let websocket_server = WebSocketServer::new("localhost:9002", "localhost:9004");
-    websocket_server.start_server();
+#[tokio::main]
+async fn main() {
+    let config = match ServerConfig::from_args(env::args().skip(1)) {
+        Ok(v) => v,
+        Err(ConfigError::HelpRequested) => {
+            print!("{}", cli::usage());
+            return;
+        },
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(2);
+        },
+    };
+
+    let websocket_server = WebSocketServer::new(config);
+    let shutdown = websocket_server.shutdown_handle();
+
+    let sigterm = terminate_signal();
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm => println!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => println!("Received Ctrl-C, shutting down"),
+        };
+
+        shutdown.shutdown();
+    });
+
+    if let Err(e) = websocket_server.start_server().await {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Resolves on SIGTERM. Registered right away, so a failure shows at startup.
+#[cfg(unix)]
+fn terminate_signal() -> impl Future<Output = ()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Couldn't register signal handlers");
+
+    async move {
+        sigterm.recv().await;
+    }
+}
+
+/// There is no SIGTERM outside unix, only Ctrl-C stops the server.
+#[cfg(not(unix))]
+fn terminate_signal() -> impl Future<Output = ()> {
+    std::future::pending()
 }
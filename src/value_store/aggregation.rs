@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::value_store::OHLCModel;
+
+/// Rolls the finest bars of each stock up into the coarser configured intervals.
+///
+/// The source interval of a stock is the finest interval it was seen with. A coarser bar
+/// covers `[start, start + interval)` with `start` aligned to a multiple of the interval and
+/// is stamped with `start`. It is completed when a source bar reaches its end, or when a
+/// source bar of a later period arrives. Target intervals that aren't a multiple of the
+/// source interval are never built, and neither are intervals the upstream sends itself.
+///
+/// The source bars of every unfinished coarser bar are kept, so a corrected source bar
+/// rebuilds it exactly. A source bar arriving after its coarser bar was completed is left
+/// to the caller to add, see [`adjust_bar`]; no unfinished bar is opened behind it.
+pub struct BarAggregator {
+    intervals: Vec<u128>,
+    stocks: HashMap<String, StockAggregation>,
+}
+
+struct StockAggregation {
+    source_interval: u128,
+    native_intervals: Vec<u128>,
+    pending: BTreeMap<u128, PendingBar>,
+    /// Start of the last completed bar of every interval.
+    completed: BTreeMap<u128, u128>,
+}
+
+struct PendingBar {
+    start: u128,
+    sources: Vec<OHLCModel>,
+}
+
+impl StockAggregation {
+    /// Takes the unfinished bar of `interval` out and returns it merged.
+    fn complete(&mut self, interval: u128) -> Option<OHLCModel> {
+        let pending = self.pending.remove(&interval)?;
+        self.completed.insert(interval, pending.start);
+
+        Some(pending.merge(interval))
+    }
+
+    fn targets<'a>(&'a self, intervals: &'a [u128]) -> impl Iterator<Item = u128> + 'a {
+        intervals.iter().cloned().filter(|v| {
+            *v > self.source_interval && v % self.source_interval == 0 && !self.native_intervals.contains(v)
+        })
+    }
+}
+
+impl PendingBar {
+    fn merge(&self, stock_interval: u128) -> OHLCModel {
+        let mut bar = OHLCModel {
+            timestamp: self.start,
+            stock_interval,
+            ..self.sources[0].clone()
+        };
+
+        for ohlc_model in self.sources[1..].iter() {
+            merge(&mut bar, ohlc_model);
+        }
+
+        bar
+    }
+}
+
+impl BarAggregator {
+    /// Creates an aggregator building bars for `intervals`, in seconds.
+    pub fn new(intervals: Vec<u128>) -> Self {
+        BarAggregator {
+            intervals,
+            stocks: HashMap::new(),
+        }
+    }
+
+    /// Feeds one stored bar and returns the coarser bars it completed, finest first, and
+    /// the interval and start of every coarser bar that was completed before the bar
+    /// arrived. Those have to be adjusted by the caller, see [`adjust_bar`].
+    pub fn add_ohlc(&mut self, ohlc_model: &OHLCModel) -> (Vec<OHLCModel>, Vec<(u128, u128)>) {
+        let stock = learn_intervals(&mut self.stocks, ohlc_model);
+
+        if ohlc_model.stock_interval > stock.source_interval {
+            return (Vec::new(), Vec::new());
+        }
+
+        let mut completed = Vec::new();
+        let mut late = Vec::new();
+        let targets: Vec<u128> = stock.targets(&self.intervals).collect();
+
+        for interval in targets.into_iter() {
+            let period = interval * 1000;
+            let start = ohlc_model.timestamp - ohlc_model.timestamp % period;
+
+            if stock.completed.get(&interval).is_some_and(|v| start <= *v) {
+                late.push((interval, start));
+                continue;
+            }
+
+            if let Some(v) = stock.pending.get(&interval) {
+                if v.start < start {
+                    completed.extend(stock.complete(interval));
+                } else if v.start > start {
+                    late.push((interval, start));
+                    continue;
+                }
+            }
+
+            let pending = stock.pending
+                .entry(interval)
+                .or_insert_with(|| PendingBar { start, sources: Vec::new() });
+
+            let position = pending.sources.partition_point(|v| v.timestamp < ohlc_model.timestamp);
+            pending.sources.insert(position, ohlc_model.clone());
+
+            if start + period <= ohlc_model.timestamp + stock.source_interval * 1000 {
+                completed.extend(stock.complete(interval));
+            }
+        }
+
+        (completed, late)
+    }
+
+    /// Forgets `name` and its unfinished coarser bars, used when it is dropped from the cache.
+    pub fn remove_stock(&mut self, name: &String) {
+        self.stocks.remove(name);
+    }
+
+    /// Replaces a source bar that was already fed with its corrected version.
+    ///
+    /// Unfinished coarser bars are rebuilt with the correction. Returns the interval and
+    /// start of every coarser bar containing it that was already completed; those have
+    /// to be adjusted by the caller, see [`adjust_bar`].
+    pub fn correct_ohlc(&mut self, ohlc_model: &OHLCModel) -> Vec<(u128, u128)> {
+        let stock = learn_intervals(&mut self.stocks, ohlc_model);
+
+        if ohlc_model.stock_interval != stock.source_interval {
+            return Vec::new();
+        }
+
+        let mut completed = Vec::new();
+        let targets: Vec<u128> = stock.targets(&self.intervals).collect();
+
+        for interval in targets.into_iter() {
+            let start = ohlc_model.timestamp - ohlc_model.timestamp % (interval * 1000);
+
+            let source = stock.pending
+                .get_mut(&interval)
+                .filter(|v| v.start == start)
+                .and_then(|v| v.sources.iter_mut().find(|v| v.timestamp == ohlc_model.timestamp));
+
+            match source {
+                Some(v) => *v = ohlc_model.clone(),
+                None => completed.push((interval, start)),
+            };
+        }
+
+        completed
+    }
+}
+
+/// Returns the aggregation of the stock of `ohlc_model`, a bar the upstream sent, after
+/// learning its interval: a finer one becomes the source, a coarser one is native and no
+/// longer built.
+fn learn_intervals<'a>(stocks: &'a mut HashMap<String, StockAggregation>, ohlc_model: &OHLCModel) -> &'a mut StockAggregation {
+    let stock = stocks
+        .entry(ohlc_model.stock_name.clone())
+        .or_insert_with(|| StockAggregation {
+            source_interval: ohlc_model.stock_interval,
+            native_intervals: Vec::new(),
+            pending: BTreeMap::new(),
+            completed: BTreeMap::new(),
+        });
+
+    if ohlc_model.stock_interval < stock.source_interval {
+        stock.native_intervals.push(stock.source_interval);
+        stock.source_interval = ohlc_model.stock_interval;
+        stock.pending.clear();
+    }
+
+    if ohlc_model.stock_interval > stock.source_interval && !stock.native_intervals.contains(&ohlc_model.stock_interval) {
+        stock.native_intervals.push(ohlc_model.stock_interval);
+        stock.pending.remove(&ohlc_model.stock_interval);
+    }
+
+    stock
+}
+
+/// Applies the correction of one of its source bars from `previous` to `corrected` to a
+/// completed coarser `bar`. A source bar that arrived late is added with a `previous` of
+/// no volume and no trades, see [`late_source`].
+///
+/// Volume, trades and the open price are exact, and so is the close price unless the bar
+/// was completed early by a gap. The source bars aren't kept any more, so the price range
+/// can only widen.
+pub fn adjust_bar(bar: &mut OHLCModel, previous: &OHLCModel, corrected: &OHLCModel) {
+    if previous.timestamp == bar.timestamp {
+        bar.price_open = corrected.price_open;
+    }
+
+    if previous.timestamp + previous.stock_interval * 1000 == bar.timestamp + bar.stock_interval * 1000 {
+        bar.price_close = corrected.price_close;
+    }
+
+    bar.min_price = bar.min_price.min(corrected.min_price);
+    bar.max_price = bar.max_price.max(corrected.max_price);
+    bar.volume += corrected.volume - previous.volume;
+    bar.trades += corrected.trades - previous.trades;
+}
+
+/// Returns the empty bar a late source bar `ohlc_model` replaces, for [`adjust_bar`].
+pub fn late_source(ohlc_model: &OHLCModel) -> OHLCModel {
+    OHLCModel {
+        volume: 0.0,
+        trades: 0,
+        ..ohlc_model.clone()
+    }
+}
+
+fn merge(bar: &mut OHLCModel, ohlc_model: &OHLCModel) {
+    bar.price_close = ohlc_model.price_close;
+    bar.min_price = bar.min_price.min(ohlc_model.min_price);
+    bar.max_price = bar.max_price.max(ohlc_model.max_price);
+    bar.volume += ohlc_model.volume;
+    bar.trades += ohlc_model.trades;
+}
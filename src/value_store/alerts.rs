@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use crate::value_store::{OHLCModel, StockInformationCacheInterface};
+
+/// Longest window, in seconds, a [`AlertRule::PercentMove`] may look back.
+pub const MAX_ALERT_WINDOW_SECS: u64 = 7 * 24 * 3600;
+
+/// Side a price has to cross a level from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossDirection {
+    Above,
+    Below,
+}
+
+/// A condition on the bars of one stock, checked on every new or corrected bar.
+///
+/// In requests an object tagged by `kind`, e.g.
+/// `{"kind": "price_cross", "symbol": "AAPL", "direction": "above", "level": 200}`,
+/// `{"kind": "percent_move", "symbol": "MSFT", "percent": 2, "window_secs": 300}` or
+/// `{"kind": "volume_above", "symbol": "AAPL", "volume": 100000}`. `interval` selects the
+/// bars the rule is checked on, the finest configured interval if left out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AlertRule {
+    /// The close goes from one side of `level` to the other, compared to the previous bar.
+    PriceCross {
+        symbol: String,
+        direction: CrossDirection,
+        level: f64,
+        #[serde(default)]
+        interval: Option<u64>,
+    },
+    /// The close is at least `percent` away, up or down, from the open of the first bar of
+    /// the last `window_secs` seconds.
+    PercentMove {
+        symbol: String,
+        percent: f64,
+        window_secs: u64,
+        #[serde(default)]
+        interval: Option<u64>,
+    },
+    /// The volume of one bar is above `volume`.
+    VolumeAbove {
+        symbol: String,
+        volume: f64,
+        #[serde(default)]
+        interval: Option<u64>,
+    },
+}
+
+impl AlertRule {
+    pub fn symbol(&self) -> &String {
+        match self {
+            AlertRule::PriceCross { symbol, .. }
+            | AlertRule::PercentMove { symbol, .. }
+            | AlertRule::VolumeAbove { symbol, .. } => symbol,
+        }
+    }
+
+    pub fn interval(&self) -> Option<u64> {
+        match self {
+            AlertRule::PriceCross { interval, .. }
+            | AlertRule::PercentMove { interval, .. }
+            | AlertRule::VolumeAbove { interval, .. } => *interval,
+        }
+    }
+
+    /// Returns the rule with `interval` set if it was left out.
+    pub fn with_default_interval(mut self, default_interval: u64) -> Self {
+        match &mut self {
+            AlertRule::PriceCross { interval, .. }
+            | AlertRule::PercentMove { interval, .. }
+            | AlertRule::VolumeAbove { interval, .. } => {
+                interval.get_or_insert(default_interval);
+            },
+        };
+
+        self
+    }
+
+    /// Checks the parameters, returning the reason they are invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval() == Some(0) {
+            return Err("interval 0 is invalid".to_owned());
+        }
+
+        match self {
+            AlertRule::PriceCross { level, .. } if !level.is_finite() => Err("level must be a number".to_owned()),
+            AlertRule::PercentMove { percent, .. } if !percent.is_finite() || *percent <= 0.0 => {
+                Err("percent must be greater than 0".to_owned())
+            },
+            AlertRule::PercentMove { window_secs, .. } if *window_secs == 0 || *window_secs > MAX_ALERT_WINDOW_SECS => {
+                Err(format!("window_secs must be between 1 and {}", MAX_ALERT_WINDOW_SECS))
+            },
+            AlertRule::VolumeAbove { volume, .. } if !volume.is_finite() || *volume < 0.0 => {
+                Err("volume must be at least 0".to_owned())
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An [`AlertRule`] with its interval resolved, checked bar by bar.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    rule: AlertRule,
+    stock_interval: u128,
+}
+
+impl Alert {
+    /// Creates the alert. The interval of `rule` must be set, see [`AlertRule::with_default_interval`].
+    pub fn new(rule: AlertRule) -> Self {
+        Alert {
+            stock_interval: rule.interval().unwrap_or_default() as u128,
+            rule,
+        }
+    }
+
+    pub fn rule(&self) -> &AlertRule {
+        &self.rule
+    }
+
+    /// Returns true if `ohlc_model` is a bar the rule is checked on.
+    pub fn watches(&self, ohlc_model: &OHLCModel) -> bool {
+        &ohlc_model.stock_name == self.rule.symbol() && ohlc_model.stock_interval == self.stock_interval
+    }
+
+    /// Checks the rule against `ohlc_model`, a bar it [`Alert::watches`] that is already
+    /// stored. Returns the value that triggered it: the close, the move in percent or the volume.
+    ///
+    /// Only the cached history is looked at, so a window reaching past it starts at the
+    /// oldest cached bar.
+    pub fn check(&self, ohlc_model: &OHLCModel, stock_cache: &StockInformationCacheInterface) -> Option<f64> {
+        match &self.rule {
+            AlertRule::PriceCross { direction, level, .. } => {
+                let previous = stock_cache.get_previous(&ohlc_model.stock_name, self.stock_interval, ohlc_model.timestamp)?;
+
+                let crossed = match direction {
+                    CrossDirection::Above => previous.price_close <= *level && ohlc_model.price_close > *level,
+                    CrossDirection::Below => previous.price_close >= *level && ohlc_model.price_close < *level,
+                };
+
+                crossed.then_some(ohlc_model.price_close)
+            },
+            AlertRule::PercentMove { percent, window_secs, .. } => {
+                let from = ohlc_model.timestamp.saturating_sub(*window_secs as u128 * 1000);
+                let first = stock_cache
+                    .get_first_since(&ohlc_model.stock_name, self.stock_interval, from)
+                    .filter(|v| v.timestamp <= ohlc_model.timestamp && v.price_open > 0.0)?;
+                let change = (ohlc_model.price_close - first.price_open) / first.price_open * 100.0;
+
+                (change.abs() >= *percent).then_some(change)
+            },
+            AlertRule::VolumeAbove { volume, .. } => (ohlc_model.volume > *volume).then_some(ohlc_model.volume),
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::value_store::OHLCModel;
+
+const SEGMENT_EXTENSION: &str = "seg";
+const HEADER_LEN: usize = 8;
+
+/// Append-only log of ingested bars, split into numbered segment files.
+///
+/// Every record is `<length: u32 LE><crc32: u32 LE><bar as JSON>`. A record cut short by a
+/// crash, or one whose checksum doesn't match, ends its segment: on open, the newest segment
+/// is truncated to its last complete record and new bars are appended after it.
+///
+/// Whole segments are deleted once their newest bar is older than the retention, measured
+/// from the newest stored bar.
+pub struct BarStore {
+    path: PathBuf,
+    segment_bytes: u64,
+    retention_ms: u128,
+    fsync: bool,
+    segments: BTreeMap<u64, SegmentInfo>,
+    current: File,
+}
+
+/// The segments of a [`BarStore`] at one point in time, to query them without holding the
+/// store. Segments deleted since are skipped, bars appended since may or may not be read.
+#[derive(Clone)]
+pub struct BarStoreReader {
+    path: PathBuf,
+    segments: BTreeMap<u64, SegmentInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SegmentInfo {
+    size: u64,
+    first_timestamp: Option<u128>,
+    last_timestamp: Option<u128>,
+}
+
+impl SegmentInfo {
+    fn add_timestamp(&mut self, timestamp: u128) {
+        self.first_timestamp = Some(self.first_timestamp.map_or(timestamp, |v| v.min(timestamp)));
+        self.last_timestamp = Some(self.last_timestamp.map_or(timestamp, |v| v.max(timestamp)));
+    }
+
+    fn overlaps(&self, from: u128, to: u128) -> bool {
+        match (self.first_timestamp, self.last_timestamp) {
+            (Some(first), Some(last)) => first <= to && last >= from,
+            _ => false,
+        }
+    }
+}
+
+impl BarStore {
+    /// Opens the store in `path`, creating the directory if needed, and passes every stored
+    /// bar to `on_bar`, oldest segment first.
+    pub fn open(
+        path: &Path,
+        segment_bytes: u64,
+        retention_ms: u128,
+        fsync: bool,
+        mut on_bar: impl FnMut(OHLCModel),
+    ) -> io::Result<Self> {
+        fs::create_dir_all(path)?;
+
+        let mut ids: Vec<u64> = fs::read_dir(path)?
+            .filter_map(|v| v.ok())
+            .filter_map(|v| segment_id(&v.path()))
+            .collect();
+
+        ids.sort();
+
+        let mut segments = BTreeMap::new();
+
+        for (i, id) in ids.iter().enumerate() {
+            let segment_path = segment_path(path, *id);
+            let (info, valid_len, file_len) = scan_segment(&segment_path, &mut on_bar)?;
+
+            if valid_len < file_len {
+                println!(
+                    "Bar store segment {:?} has {} bytes of incomplete or corrupt data",
+                    segment_path,
+                    file_len - valid_len,
+                );
+
+                if i == ids.len() - 1 {
+                    OpenOptions::new().write(true).open(&segment_path)?.set_len(valid_len)?;
+                }
+            }
+
+            segments.insert(*id, info);
+        }
+
+        let current_id = match segments.keys().next_back() {
+            Some(v) => *v,
+            None => {
+                segments.insert(0, SegmentInfo::default());
+                0
+            },
+        };
+
+        let mut bar_store = BarStore {
+            current: open_segment(path, current_id)?,
+            path: path.to_owned(),
+            segment_bytes,
+            retention_ms,
+            fsync,
+            segments,
+        };
+
+        bar_store.apply_retention()?;
+
+        Ok(bar_store)
+    }
+
+    /// Appends `bars` to the current segment, starting a new one when it is full.
+    pub fn append(&mut self, bars: &[OHLCModel]) -> io::Result<()> {
+        let mut buffer = Vec::new();
+
+        for ohlc_model in bars.iter() {
+            let record = encode_record(ohlc_model);
+            let (current_id, info) = self.segments.iter_mut().next_back().expect("A segment is always open");
+
+            if info.size > 0 && info.size + (buffer.len() + record.len()) as u64 > self.segment_bytes {
+                let next_id = current_id + 1;
+
+                self.write(&buffer)?;
+                buffer.clear();
+                self.rotate(next_id)?;
+            }
+
+            buffer.extend_from_slice(&record);
+
+            let (_, info) = self.segments.iter_mut().next_back().expect("A segment is always open");
+            info.add_timestamp(ohlc_model.timestamp);
+        }
+
+        self.write(&buffer)
+    }
+
+    /// Reads the latest `limit` stored bars of `name` for `stock_interval` with a timestamp
+    /// between `from` and `to`, see [`BarStoreReader::query`].
+    pub fn query(&self, name: &String, stock_interval: u128, from: u128, to: u128, limit: usize) -> io::Result<Vec<OHLCModel>> {
+        self.reader().query(name, stock_interval, from, to, limit)
+    }
+
+    /// Returns a reader of the segments stored so far.
+    pub fn reader(&self) -> BarStoreReader {
+        BarStoreReader {
+            path: self.path.clone(),
+            segments: self.segments.clone(),
+        }
+    }
+
+    /// Returns the number of segment files.
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let (_, info) = self.segments.iter_mut().next_back().expect("A segment is always open");
+
+        if let Err(e) = self.current.write_all(buffer) {
+            let _ = self.current.set_len(info.size);
+            return Err(e);
+        }
+
+        if self.fsync {
+            self.current.sync_data()?;
+        }
+
+        info.size += buffer.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self, id: u64) -> io::Result<()> {
+        self.current.sync_all()?;
+        self.current = open_segment(&self.path, id)?;
+        self.segments.insert(id, SegmentInfo::default());
+
+        self.apply_retention()
+    }
+
+    fn apply_retention(&mut self) -> io::Result<()> {
+        let newest = match self.segments.values().filter_map(|v| v.last_timestamp).max() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let current_id = *self.segments.keys().next_back().expect("A segment is always open");
+
+        let expired: Vec<u64> = self.segments
+            .iter()
+            .filter(|(id, info)| **id != current_id && info.last_timestamp.is_none_or(|v| v + self.retention_ms < newest))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired.iter() {
+            fs::remove_file(segment_path(&self.path, *id))?;
+            self.segments.remove(id);
+        }
+
+        Ok(())
+    }
+}
+
+impl BarStoreReader {
+    /// Reads the latest `limit` stored bars of `name` for `stock_interval` with a timestamp
+    /// between `from` and `to`, both inclusive, oldest first. A bar stored again, e.g. after a
+    /// correction, is returned as last stored.
+    ///
+    /// Segments are read newest first and no more than `limit` bars are held at a time;
+    /// older segments are skipped once they can't hold a later bar than those found.
+    pub fn query(&self, name: &String, stock_interval: u128, from: u128, to: u128, limit: usize) -> io::Result<Vec<OHLCModel>> {
+        let mut bars: BTreeMap<u128, OHLCModel> = BTreeMap::new();
+
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        for (id, info) in self.segments.iter().rev() {
+            if !info.overlaps(from, to) {
+                continue;
+            }
+
+            let oldest = bars.first_key_value().map(|(k, _)| *k);
+
+            if bars.len() >= limit && info.last_timestamp.zip(oldest).is_some_and(|(last, oldest)| last < oldest) {
+                continue;
+            }
+
+            let mut segment_bars: BTreeMap<u128, OHLCModel> = BTreeMap::new();
+
+            let scanned = scan_segment(&segment_path(&self.path, *id), &mut |ohlc_model| {
+                if &ohlc_model.stock_name == name
+                    && ohlc_model.stock_interval == stock_interval
+                    && ohlc_model.timestamp >= from
+                    && ohlc_model.timestamp <= to
+                {
+                    segment_bars.insert(ohlc_model.timestamp, ohlc_model);
+
+                    if segment_bars.len() > limit {
+                        segment_bars.pop_first();
+                    }
+                }
+            });
+
+            match scanned {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                v => v?,
+            };
+
+            for (timestamp, ohlc_model) in segment_bars.into_iter() {
+                bars.entry(timestamp).or_insert(ohlc_model);
+            }
+
+            while bars.len() > limit {
+                bars.pop_first();
+            }
+        }
+
+        Ok(bars.into_values().collect())
+    }
+}
+
+fn segment_path(path: &Path, id: u64) -> PathBuf {
+    path.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+}
+
+fn segment_id(path: &Path) -> Option<u64> {
+    if path.extension()? != SEGMENT_EXTENSION {
+        return None;
+    }
+
+    path.file_stem()?.to_str()?.parse::<u64>().ok()
+}
+
+fn open_segment(path: &Path, id: u64) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(segment_path(path, id))
+}
+
+fn encode_record(ohlc_model: &OHLCModel) -> Vec<u8> {
+    let payload = serde_json::to_vec(ohlc_model).expect("Bars always serialize");
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+
+    record
+}
+
+/// Reads the records of one segment until the first incomplete or corrupt one.
+/// Returns the segment's info, the length of its valid prefix and its file length.
+fn scan_segment(path: &Path, on_bar: &mut impl FnMut(OHLCModel)) -> io::Result<(SegmentInfo, u64, u64)> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut info = SegmentInfo::default();
+    let mut offset = 0;
+
+    while offset + HEADER_LEN <= data.len() {
+        let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap());
+        let end = offset + HEADER_LEN + len;
+
+        if end > data.len() || crc32fast::hash(&data[offset + HEADER_LEN..end]) != crc {
+            break;
+        }
+
+        let ohlc_model = match serde_json::from_slice::<OHLCModel>(&data[offset + HEADER_LEN..end]) {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+
+        info.add_timestamp(ohlc_model.timestamp);
+        on_bar(ohlc_model);
+
+        offset = end;
+    }
+
+    info.size = offset as u64;
+
+    Ok((info, offset as u64, data.len() as u64))
+}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::value_store::OHLCModel;
+
+/// Prefix of the name a basket is subscribed by, e.g. `basket:TECH`.
+pub const BASKET_TOPIC_PREFIX: &str = "basket:";
+
+/// Most symbols one basket may hold.
+pub const MAX_BASKET_SYMBOLS: usize = 500;
+
+/// Longest basket name, in characters.
+pub const MAX_BASKET_NAME_LEN: usize = 64;
+
+/// Level of every basket index when it is created.
+pub const BASKET_BASE_LEVEL: f64 = 100.0;
+
+/// How the price moves of the symbols of a basket are weighted in its index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BasketWeighting {
+    /// Every symbol counts the same.
+    #[default]
+    Equal,
+    /// Symbols count by their price, like the sum of the prices.
+    Price,
+    /// Symbols count by the value, close times volume, they traded in the period.
+    Value,
+}
+
+impl BasketWeighting {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BasketWeighting::Equal => "equal",
+            BasketWeighting::Price => "price",
+            BasketWeighting::Value => "value",
+        }
+    }
+}
+
+/// A named list of symbols aggregated together.
+///
+/// In the config file a `[[baskets]]` table:
+///
+/// ```toml
+/// [[baskets]]
+/// name = "TECH"
+/// symbols = ["AAPL", "MSFT", "NVDA"]
+/// weighting = "value"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasketSpec {
+    pub name: String,
+    pub symbols: Vec<String>,
+    #[serde(default)]
+    pub weighting: BasketWeighting,
+}
+
+impl BasketSpec {
+    /// Returns the name subscribers use for the basket, see [`BASKET_TOPIC_PREFIX`].
+    pub fn topic(&self) -> String {
+        basket_topic(&self.name)
+    }
+
+    /// Checks the name and symbols, returning the reason they are invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("basket name is empty".to_owned());
+        }
+
+        if self.name.chars().count() > MAX_BASKET_NAME_LEN {
+            return Err("basket name is too long".to_owned());
+        }
+
+        if self.symbols.is_empty() || self.symbols.len() > MAX_BASKET_SYMBOLS {
+            return Err(format!("a basket needs between 1 and {} symbols", MAX_BASKET_SYMBOLS));
+        }
+
+        for (i, symbol) in self.symbols.iter().enumerate() {
+            if symbol.trim().is_empty() {
+                return Err("symbol is empty".to_owned());
+            }
+
+            if self.symbols[..i].contains(symbol) {
+                return Err(format!("symbol {:?} is listed twice", symbol));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the name the basket `name` is subscribed by.
+pub fn basket_topic(name: &str) -> String {
+    format!("{}{}", BASKET_TOPIC_PREFIX, name)
+}
+
+/// Aggregate and index of one basket, closed once per DataFeed period like [`crate::value_store::AnalysisInfo`].
+///
+/// The index starts at [`BASKET_BASE_LEVEL`] and is chain-linked: at the end of every period it
+/// moves by the weighted average of the price changes, since the previous period, of the
+/// symbols priced in both. A symbol joins the index the period after its first bar.
+pub struct Basket {
+    spec: BasketSpec,
+    trades: i64,
+    volume: f64,
+    market_value: f64,
+    /// End of the latest bar and its close, per symbol.
+    prices: HashMap<String, (u128, f64)>,
+    /// Close of every symbol at the end of the previous period.
+    previous_prices: HashMap<String, f64>,
+    /// Value traded by every symbol in the current period.
+    traded_value: HashMap<String, f64>,
+    level: f64,
+    history_size: usize,
+    data_history: VecDeque<String>,
+    /// Start of the current period, the timestamp of the last reset.
+    period_start: u128,
+}
+
+impl Basket {
+    pub fn new(spec: BasketSpec, history_size: usize) -> Self {
+        Basket {
+            spec,
+            trades: 0,
+            volume: 0.0,
+            market_value: 0.0,
+            prices: HashMap::new(),
+            previous_prices: HashMap::new(),
+            traded_value: HashMap::new(),
+            level: BASKET_BASE_LEVEL,
+            history_size,
+            data_history: VecDeque::new(),
+            period_start: 0,
+        }
+    }
+
+    pub fn spec(&self) -> &BasketSpec {
+        &self.spec
+    }
+
+    /// Returns the index level at the end of the last period.
+    pub fn level(&self) -> f64 {
+        self.level
+    }
+
+    pub fn contains(&self, symbol: &String) -> bool {
+        self.spec.symbols.contains(symbol)
+    }
+
+    /// Returns true if `ohlc_model` starts in the current period, so that its counters
+    /// include it.
+    pub fn in_period(&self, ohlc_model: &OHLCModel) -> bool {
+        ohlc_model.timestamp >= self.period_start
+    }
+
+    pub fn add_ohlc(&mut self, ohlc_model: &OHLCModel) {
+        let value = ohlc_model.price_close * ohlc_model.volume;
+        let end = ohlc_model.timestamp + ohlc_model.stock_interval * 1000;
+
+        self.trades += ohlc_model.trades;
+        self.volume += ohlc_model.volume;
+        self.market_value += value;
+        *self.traded_value.entry(ohlc_model.stock_name.clone()).or_insert(0.0) += value;
+
+        match self.prices.get(&ohlc_model.stock_name) {
+            Some((v, _)) if *v > end => (),
+            _ => {
+                self.prices.insert(ohlc_model.stock_name.clone(), (end, ohlc_model.price_close));
+            },
+        };
+    }
+
+    /// Takes a bar back out of the counters of the current period, used when it is corrected.
+    pub fn remove_ohlc(&mut self, ohlc_model: &OHLCModel) {
+        let value = ohlc_model.price_close * ohlc_model.volume;
+
+        self.trades -= ohlc_model.trades;
+        self.volume -= ohlc_model.volume;
+        self.market_value -= value;
+
+        if let Some(v) = self.traded_value.get_mut(&ohlc_model.stock_name) {
+            *v -= value;
+        }
+    }
+
+    /// Forgets the price of `symbol`, used when it is dropped from the cache.
+    pub fn remove_symbol(&mut self, symbol: &String) {
+        self.prices.remove(symbol);
+        self.previous_prices.remove(symbol);
+        self.traded_value.remove(symbol);
+    }
+
+    /// Moves the index, closes the current period at `timestamp` and returns its summary.
+    pub fn reset(&mut self, timestamp: u128) -> String {
+        let mut weighted_change = 0.0;
+        let mut total_weight = 0.0;
+
+        for (symbol, (_, price)) in self.prices.iter() {
+            let previous_price = match self.previous_prices.get(symbol) {
+                Some(v) if *v > 0.0 => *v,
+                _ => continue,
+            };
+
+            let weight = match self.spec.weighting {
+                BasketWeighting::Equal => 1.0,
+                BasketWeighting::Price => previous_price,
+                BasketWeighting::Value => self.traded_value.get(symbol).copied().unwrap_or(0.0).max(0.0),
+            };
+
+            weighted_change += weight * price / previous_price;
+            total_weight += weight;
+        }
+
+        if total_weight > 0.0 {
+            self.level *= weighted_change / total_weight;
+        }
+
+        let basket_info = json!({
+            "basket": self.spec.name,
+            "weighting": self.spec.weighting.as_str(),
+            "stock_n": self.prices.len(),
+            "trade_n": self.trades,
+            "volume_n": self.volume,
+            "market_v_n": self.market_value,
+            "level": self.level,
+            "timestamp": timestamp,
+        }).to_string();
+
+        self.trades = 0;
+        self.volume = 0.0;
+        self.market_value = 0.0;
+        self.traded_value.clear();
+        self.period_start = timestamp;
+        self.previous_prices = self.prices.iter().map(|(k, (_, v))| (k.clone(), *v)).collect();
+
+        self.data_history.push_back(basket_info.clone());
+
+        if self.data_history.len() > self.history_size {
+            let _ = self.data_history.pop_front();
+        }
+
+        basket_info
+    }
+
+    /// Returns the summaries of the past periods, ending with `"End of Update"` like the DataFeed.
+    pub fn get_history(&self) -> Vec<String> {
+        let mut data_history: Vec<String> = self.data_history.iter().cloned().collect();
+
+        data_history.push("End of Update".to_owned());
+
+        data_history
+    }
+}
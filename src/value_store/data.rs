@@ -1,4 +1,17 @@
-#[derive(Debug, Clone)]
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of `;` separated fields in an upstream bar line.
+pub const LINE_FIELDS: usize = 10;
+
+/// Schema version written into every [`BarFormat::V1`] bar.
+pub const BAR_SCHEMA_VERSION: u32 = 1;
+
+/// One open/high/low/close bar of a stock for a single interval.
+///
+/// `timestamp` is in milliseconds since the Unix epoch, `stock_interval` in seconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct OHLCModel {
     pub stock_name: String,
     pub price_open: f64,
@@ -26,69 +39,268 @@ impl OHLCModel {
         }
     }
 
-    pub fn from_string(s: String) -> Self {
-        let mut ohlc_model = OHLCModel {
-            stock_name: String::new(),
-            price_open: 0.0,
-            price_close: 0.0,
-            min_price: 0.0,
-            max_price: 0.0,
-            volume: 0.0,
-            trades: 0,
-            timestamp: 0,
-            stock_interval: 0,
-        };
-
-        let mut tmp = String::new();
-        let mut i = 0;
-
-        for c in s.chars() {
-            match c {
-                ';' | '\n' => {
-                    match i {
-                        1 => ohlc_model.stock_name = tmp,
-                        2 => ohlc_model.price_open = tmp.parse::<f64>().unwrap(),
-                        3 => ohlc_model.price_close = tmp.parse::<f64>().unwrap(),
-                        4 => ohlc_model.min_price = tmp.parse::<f64>().unwrap(),
-                        5 => ohlc_model.max_price = tmp.parse::<f64>().unwrap(),
-                        6 => ohlc_model.volume = tmp.parse::<f64>().unwrap(),
-                        7 => ohlc_model.trades = tmp.parse::<i64>().unwrap(),
-                        8 => ohlc_model.timestamp = tmp.parse::<u128>().unwrap(),
-                        9 => ohlc_model.stock_interval = tmp.parse::<u128>().unwrap(),
-                        _ => (),
-                    };
-
-                    tmp = String::new();
-                    i += 1;
-                },
-                _ => tmp.push(c),
-            };
+    /// Parses one semicolon separated upstream line:
+    ///
+    /// ```text
+    /// <tag>;<stock_name>;<price_open>;<price_close>;<min_price>;<max_price>;<volume>;<trades>;<timestamp>;<stock_interval>
+    /// ```
+    ///
+    /// The leading tag is ignored. A trailing `;`, `\n` or `\r\n` is allowed.
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let mut fields: Vec<&str> = line.split(';').collect();
+
+        if fields.len() == LINE_FIELDS + 1 && fields[LINE_FIELDS].is_empty() {
+            fields.pop();
+        }
+
+        if fields.len() != LINE_FIELDS {
+            return Err(ParseError::WrongFieldCount {
+                expected: LINE_FIELDS,
+                found: fields.len(),
+            });
+        }
+
+        let stock_name = fields[1].trim();
+
+        if stock_name.is_empty() {
+            return Err(ParseError::EmptySymbol);
         }
 
-        ohlc_model
+        let stock_interval = parse_field::<u128>("stock_interval", fields[9])?;
+
+        if stock_interval == 0 {
+            return Err(ParseError::UnknownInterval(stock_interval));
+        }
+
+        Ok(OHLCModel {
+            stock_name: stock_name.to_owned(),
+            price_open: parse_price("price_open", fields[2])?,
+            price_close: parse_price("price_close", fields[3])?,
+            min_price: parse_price("min_price", fields[4])?,
+            max_price: parse_price("max_price", fields[5])?,
+            volume: parse_price("volume", fields[6])?,
+            trades: parse_field::<i64>("trades", fields[7])?,
+            timestamp: parse_field::<u128>("timestamp", fields[8])?,
+            stock_interval,
+        })
+    }
+
+    /// Serializes the bar for subscribers.
+    ///
+    /// [`BarFormat::V1`] produces one object with these fields:
+    ///
+    /// | field            | type    | meaning                                   |
+    /// |------------------|---------|-------------------------------------------|
+    /// | `schema_version` | integer | always [`BAR_SCHEMA_VERSION`]             |
+    /// | `type`           | string  | always `"bar"`                            |
+    /// | `stock_name`     | string  | symbol                                    |
+    /// | `stock_interval` | integer | bar length in seconds                     |
+    /// | `timestamp`      | integer | milliseconds since the Unix epoch         |
+    /// | `price_open`     | number  | first price of the bar                    |
+    /// | `price_close`    | number  | last price of the bar                     |
+    /// | `min_price`      | number  | lowest price of the bar                   |
+    /// | `max_price`      | number  | highest price of the bar                  |
+    /// | `volume`         | number  | traded volume                             |
+    /// | `trades`         | integer | number of trades                          |
+    ///
+    /// [`BarFormat::Legacy`] keeps the original shape: the symbol is called `name`,
+    /// `price_open` is a string with six decimals and there is no version field.
+    pub fn to_json(&self, bar_format: BarFormat) -> String {
+        match bar_format {
+            BarFormat::V1 => self.to_v1_json("bar"),
+            BarFormat::Legacy => self.to_legacy_json(""),
+        }
+    }
+
+    /// Serializes a bar that replaces the stored bar with the same symbol, interval and
+    /// timestamp. [`BarFormat::V1`] sets `type` to `"correction"`, [`BarFormat::Legacy`]
+    /// adds `"correction": true`.
+    pub fn to_correction_json(&self, bar_format: BarFormat) -> String {
+        match bar_format {
+            BarFormat::V1 => self.to_v1_json("correction"),
+            BarFormat::Legacy => self.to_legacy_json(",\n            \"correction\": true"),
+        }
     }
 
-    pub fn to_string(&self) -> String {
+    /// Returns the bar as a JSON value in the [`BarFormat::V1`] shape, for embedding it in
+    /// other messages. Fails if the timestamp or interval doesn't fit in 64 bits.
+    pub fn to_value(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self.to_message("bar"))
+    }
+
+    fn to_message<'a>(&'a self, message_type: &'a str) -> OHLCMessage<'a> {
+        OHLCMessage {
+            schema_version: BAR_SCHEMA_VERSION,
+            message_type,
+            stock_name: &self.stock_name,
+            stock_interval: self.stock_interval,
+            timestamp: self.timestamp,
+            price_open: self.price_open,
+            price_close: self.price_close,
+            min_price: self.min_price,
+            max_price: self.max_price,
+            volume: self.volume,
+            trades: self.trades,
+        }
+    }
+
+    fn to_v1_json(&self, message_type: &str) -> String {
+        serde_json::to_string(&self.to_message(message_type)).expect("Bars always serialize")
+    }
+
+    fn to_legacy_json(&self, extra_fields: &str) -> String {
         format!("{{
             \"stock_interval\": {},
-            \"name\": \"{}\",
+            \"name\": {},
             \"price_open\": \"{:.6}\",
             \"price_close\": {:.6},
             \"min_price\": {},
             \"max_price\": {},
             \"volume\": {},
             \"trades\": {},
-            \"timestamp\": {}
+            \"timestamp\": {}{}
         }}",
             self.stock_interval,
-            self.stock_name,
-            self.price_open as f64,
-            self.price_close as f64,
-            self.min_price as f64,
-            self.max_price as f64,
+            serde_json::Value::from(self.stock_name.as_str()),
+            self.price_open,
+            self.price_close,
+            self.min_price,
+            self.max_price,
             self.volume,
             self.trades,
             self.timestamp,
+            extra_fields,
         )
     }
-}
\ No newline at end of file
+}
+
+/// A bar stored in the cache, as it is announced to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BarEvent {
+    /// A bar that wasn't stored before.
+    Bar(OHLCModel),
+    /// A bar that replaced a different stored bar with the same symbol, interval and timestamp.
+    Correction(OHLCModel),
+}
+
+impl BarEvent {
+    pub fn ohlc_model(&self) -> &OHLCModel {
+        match self {
+            BarEvent::Bar(v) | BarEvent::Correction(v) => v,
+        }
+    }
+
+    pub fn is_correction(&self) -> bool {
+        matches!(self, BarEvent::Correction(_))
+    }
+
+    pub fn to_json(&self, bar_format: BarFormat) -> String {
+        match self {
+            BarEvent::Bar(v) => v.to_json(bar_format),
+            BarEvent::Correction(v) => v.to_correction_json(bar_format),
+        }
+    }
+}
+
+/// Wire shape of a bar sent to subscribers, see [`OHLCModel::to_json`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarFormat {
+    /// The hand-built shape of the first releases, kept for existing frontends.
+    #[default]
+    Legacy,
+    V1,
+}
+
+impl FromStr for BarFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(BarFormat::Legacy),
+            "v1" => Ok(BarFormat::V1),
+            _ => Err("expected \"legacy\" or \"v1\"".to_owned()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OHLCMessage<'a> {
+    schema_version: u32,
+    #[serde(rename = "type")]
+    message_type: &'a str,
+    stock_name: &'a str,
+    stock_interval: u128,
+    timestamp: u128,
+    price_open: f64,
+    price_close: f64,
+    min_price: f64,
+    max_price: f64,
+    volume: f64,
+    trades: i64,
+}
+
+/// Selects the bars a subscriber receives.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BarFilter {
+    /// Intervals to receive, all if `None`.
+    pub intervals: Option<BTreeSet<u128>>,
+    /// Bars per interval in the initial snapshot, the whole history if `None`.
+    pub depth: Option<usize>,
+    /// Also receive the session VWAP and TWAP, see [`crate::value_store::SessionStats`].
+    pub session: bool,
+}
+
+impl BarFilter {
+    pub fn accepts(&self, stock_interval: u128) -> bool {
+        match &self.intervals {
+            Some(v) => v.contains(&stock_interval),
+            None => true,
+        }
+    }
+}
+
+/// Reasons an upstream bar line is rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    WrongFieldCount { expected: usize, found: usize },
+    BadNumber { field: &'static str, value: String },
+    EmptySymbol,
+    UnknownInterval(u128),
+    /// A bar with this timestamp is older than every bar kept for its symbol and interval.
+    Expired(u128),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::WrongFieldCount { expected, found } => {
+                write!(f, "expected {} fields, found {}", expected, found)
+            },
+            ParseError::BadNumber { field, value } => write!(f, "bad number {:?} for {}", value, field),
+            ParseError::EmptySymbol => write!(f, "empty stock name"),
+            ParseError::UnknownInterval(v) => write!(f, "unknown interval {}", v),
+            ParseError::Expired(v) => write!(f, "bar at {} is older than the kept history", v),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn parse_field<T: FromStr>(field: &'static str, value: &str) -> Result<T, ParseError> {
+    value.trim().parse::<T>().map_err(|_| ParseError::BadNumber {
+        field,
+        value: value.to_owned(),
+    })
+}
+
+fn parse_price(field: &'static str, value: &str) -> Result<f64, ParseError> {
+    match parse_field::<f64>(field, value)? {
+        v if v.is_finite() => Ok(v),
+        _ => Err(ParseError::BadNumber {
+            field,
+            value: value.to_owned(),
+        }),
+    }
+}
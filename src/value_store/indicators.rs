@@ -0,0 +1,395 @@
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::value_store::OHLCModel;
+
+/// Longest period accepted for an indicator.
+pub const MAX_INDICATOR_PERIOD: usize = 1000;
+
+/// A technical indicator computed from the close prices of one stock and interval.
+///
+/// In requests an object tagged by `kind`, e.g. `{"kind": "ema", "period": 20}` or
+/// `{"kind": "macd", "fast": 12, "slow": 26, "signal": 9}`. Parameters left out take the
+/// usual defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IndicatorSpec {
+    /// Simple moving average.
+    Sma {
+        #[serde(default = "default_period")]
+        period: usize,
+    },
+    /// Exponential moving average, seeded with the simple average of its first period.
+    Ema {
+        #[serde(default = "default_period")]
+        period: usize,
+    },
+    /// Relative strength index with Wilder's smoothing.
+    Rsi {
+        #[serde(default = "default_rsi_period")]
+        period: usize,
+    },
+    /// Difference of a fast and a slow EMA, with an EMA of it as signal line.
+    Macd {
+        #[serde(default = "default_macd_fast")]
+        fast: usize,
+        #[serde(default = "default_macd_slow")]
+        slow: usize,
+        #[serde(default = "default_macd_signal")]
+        signal: usize,
+    },
+    /// Simple moving average with bands `width` standard deviations above and below it.
+    Bollinger {
+        #[serde(default = "default_period")]
+        period: usize,
+        #[serde(default = "default_bollinger_width")]
+        width: f64,
+    },
+}
+
+fn default_period() -> usize { 20 }
+fn default_rsi_period() -> usize { 14 }
+fn default_macd_fast() -> usize { 12 }
+fn default_macd_slow() -> usize { 26 }
+fn default_macd_signal() -> usize { 9 }
+fn default_bollinger_width() -> f64 { 2.0 }
+
+impl IndicatorSpec {
+    /// Returns a name identifying the indicator and its parameters, e.g. `ema(20)`.
+    pub fn key(&self) -> String {
+        match self {
+            IndicatorSpec::Sma { period } => format!("sma({})", period),
+            IndicatorSpec::Ema { period } => format!("ema({})", period),
+            IndicatorSpec::Rsi { period } => format!("rsi({})", period),
+            IndicatorSpec::Macd { fast, slow, signal } => format!("macd({},{},{})", fast, slow, signal),
+            IndicatorSpec::Bollinger { period, width } => format!("bollinger({},{})", period, width),
+        }
+    }
+
+    /// Returns the names of the values of every point, in order.
+    pub fn fields(&self) -> &'static [&'static str] {
+        match self {
+            IndicatorSpec::Sma { .. } | IndicatorSpec::Ema { .. } | IndicatorSpec::Rsi { .. } => &["value"],
+            IndicatorSpec::Macd { .. } => &["macd", "signal", "histogram"],
+            IndicatorSpec::Bollinger { .. } => &["middle", "upper", "lower"],
+        }
+    }
+
+    /// Returns the longest period of the indicator, in bars. The MACD signal line starts on
+    /// the first point of the slow EMA, so both add up.
+    pub fn longest_period(&self) -> usize {
+        match self {
+            IndicatorSpec::Sma { period }
+            | IndicatorSpec::Ema { period }
+            | IndicatorSpec::Rsi { period }
+            | IndicatorSpec::Bollinger { period, .. } => *period,
+            IndicatorSpec::Macd { slow, signal, .. } => slow + signal - 1,
+        }
+    }
+
+    /// Checks the parameters, returning the reason they are invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        let periods = match self {
+            IndicatorSpec::Sma { period } | IndicatorSpec::Ema { period } | IndicatorSpec::Rsi { period } => vec![*period],
+            IndicatorSpec::Macd { fast, slow, signal } => {
+                if fast >= slow {
+                    return Err("fast period must be shorter than the slow one".to_owned());
+                }
+
+                vec![*fast, *slow, *signal]
+            },
+            IndicatorSpec::Bollinger { period, width } => {
+                if !width.is_finite() || *width <= 0.0 {
+                    return Err("width must be greater than 0".to_owned());
+                }
+
+                vec![*period]
+            },
+        };
+
+        match periods.iter().any(|v| *v == 0 || *v > MAX_INDICATOR_PERIOD) {
+            true => Err(format!("periods must be between 1 and {}", MAX_INDICATOR_PERIOD)),
+            false => Ok(()),
+        }
+    }
+
+    /// Serializes `point` as `{"timestamp": ..., <field>: <value>, ...}`.
+    pub fn point_json(&self, point: &IndicatorPoint) -> Value {
+        let mut object = Map::new();
+
+        object.insert("timestamp".to_owned(), Value::from(point.timestamp as u64));
+
+        for (field, value) in self.fields().iter().zip(point.values.iter()) {
+            object.insert((*field).to_owned(), Value::from(*value));
+        }
+
+        Value::Object(object)
+    }
+
+    fn state(&self) -> IndicatorState {
+        match self {
+            IndicatorSpec::Sma { period } => IndicatorState::Sma(Window::new(*period)),
+            IndicatorSpec::Ema { period } => IndicatorState::Ema(Ema::new(*period)),
+            IndicatorSpec::Rsi { period } => IndicatorState::Rsi(Rsi::new(*period)),
+            IndicatorSpec::Macd { fast, slow, signal } => IndicatorState::Macd {
+                fast: Ema::new(*fast),
+                slow: Ema::new(*slow),
+                signal: Ema::new(*signal),
+            },
+            IndicatorSpec::Bollinger { period, width } => IndicatorState::Bollinger(Window::new(*period), *width),
+        }
+    }
+}
+
+/// The value of an indicator at the close of one bar, see [`IndicatorSpec::fields`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndicatorPoint {
+    pub timestamp: u128,
+    pub values: Vec<f64>,
+}
+
+/// An indicator kept up to date with the bars of one stock and interval.
+///
+/// Every new bar moves the indicator one step. A bar replacing the latest one redoes that
+/// step from the state before it. A bar older than the latest one can't be applied
+/// incrementally; [`IndicatorSeries::update`] then returns false and the series has to be
+/// rebuilt from the history.
+pub struct IndicatorSeries {
+    spec: IndicatorSpec,
+    before_last: IndicatorState,
+    after_last: IndicatorState,
+    last_timestamp: Option<u128>,
+    points: VecDeque<IndicatorPoint>,
+}
+
+impl IndicatorSeries {
+    pub fn new(spec: IndicatorSpec) -> Self {
+        IndicatorSeries {
+            before_last: spec.state(),
+            after_last: spec.state(),
+            spec,
+            last_timestamp: None,
+            points: VecDeque::new(),
+        }
+    }
+
+    pub fn spec(&self) -> &IndicatorSpec {
+        &self.spec
+    }
+
+    /// Applies `ohlc_model`, keeping at most `retention` points. Returns false if it is older
+    /// than the latest bar applied.
+    pub fn update(&mut self, ohlc_model: &OHLCModel, retention: usize) -> bool {
+        match self.last_timestamp {
+            Some(v) if ohlc_model.timestamp < v => return false,
+            Some(v) if ohlc_model.timestamp == v => {
+                self.after_last = self.before_last.clone();
+
+                if self.points.back().is_some_and(|v| v.timestamp == ohlc_model.timestamp) {
+                    let _ = self.points.pop_back();
+                }
+            },
+            _ => self.before_last = self.after_last.clone(),
+        };
+
+        self.last_timestamp = Some(ohlc_model.timestamp);
+
+        if let Some(values) = self.after_last.push(ohlc_model.price_close) {
+            self.points.push_back(IndicatorPoint { timestamp: ohlc_model.timestamp, values });
+
+            if self.points.len() > retention {
+                let _ = self.points.pop_front();
+            }
+        }
+
+        true
+    }
+
+    /// Starts over and applies `bars`, oldest first.
+    pub fn rebuild<'a>(&mut self, bars: impl Iterator<Item = &'a OHLCModel>, retention: usize) {
+        *self = IndicatorSeries::new(self.spec.clone());
+
+        for ohlc_model in bars {
+            self.update(ohlc_model, retention);
+        }
+    }
+
+    /// Returns the points, oldest first. Bars before the indicator's warm-up have none.
+    pub fn points(&self) -> &VecDeque<IndicatorPoint> {
+        &self.points
+    }
+
+    /// Returns the point of the bar at `timestamp`.
+    pub fn point(&self, timestamp: u128) -> Option<&IndicatorPoint> {
+        self.points
+            .binary_search_by_key(&timestamp, |v| v.timestamp)
+            .ok()
+            .map(|i| &self.points[i])
+    }
+}
+
+#[derive(Clone)]
+enum IndicatorState {
+    Sma(Window),
+    Ema(Ema),
+    Rsi(Rsi),
+    Macd { fast: Ema, slow: Ema, signal: Ema },
+    Bollinger(Window, f64),
+}
+
+impl IndicatorState {
+    fn push(&mut self, close: f64) -> Option<Vec<f64>> {
+        match self {
+            IndicatorState::Sma(window) => {
+                window.push(close);
+                window.mean().map(|v| vec![v])
+            },
+            IndicatorState::Ema(ema) => ema.push(close).map(|v| vec![v]),
+            IndicatorState::Rsi(rsi) => rsi.push(close).map(|v| vec![v]),
+            IndicatorState::Macd { fast, slow, signal } => {
+                let (fast, slow) = (fast.push(close), slow.push(close));
+                let macd = fast? - slow?;
+                let signal = signal.push(macd)?;
+
+                Some(vec![macd, signal, macd - signal])
+            },
+            IndicatorState::Bollinger(window, width) => {
+                window.push(close);
+
+                let middle = window.mean()?;
+                let deviation = window.std_dev(middle) * *width;
+
+                Some(vec![middle, middle + deviation, middle - deviation])
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Window {
+    period: usize,
+    closes: VecDeque<f64>,
+    sum: f64,
+}
+
+impl Window {
+    fn new(period: usize) -> Self {
+        Window {
+            period,
+            closes: VecDeque::with_capacity(period),
+            sum: 0.0,
+        }
+    }
+
+    fn push(&mut self, close: f64) {
+        self.closes.push_back(close);
+        self.sum += close;
+
+        if self.closes.len() > self.period {
+            self.sum -= self.closes.pop_front().unwrap_or_default();
+        }
+    }
+
+    fn mean(&self) -> Option<f64> {
+        match self.closes.len() == self.period {
+            true => Some(self.sum / self.period as f64),
+            false => None,
+        }
+    }
+
+    /// Population standard deviation around `mean`.
+    fn std_dev(&self, mean: f64) -> f64 {
+        let variance = self.closes.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / self.period as f64;
+
+        variance.sqrt()
+    }
+}
+
+#[derive(Clone)]
+struct Ema {
+    period: usize,
+    count: usize,
+    sum: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    fn new(period: usize) -> Self {
+        Ema {
+            period,
+            count: 0,
+            sum: 0.0,
+            value: None,
+        }
+    }
+
+    fn push(&mut self, x: f64) -> Option<f64> {
+        match self.value {
+            Some(v) => {
+                let alpha = 2.0 / (self.period as f64 + 1.0);
+
+                self.value = Some(v + alpha * (x - v));
+            },
+            None => {
+                self.count += 1;
+                self.sum += x;
+
+                if self.count == self.period {
+                    self.value = Some(self.sum / self.period as f64);
+                }
+            },
+        };
+
+        self.value
+    }
+}
+
+#[derive(Clone)]
+struct Rsi {
+    period: usize,
+    previous_close: Option<f64>,
+    count: usize,
+    gain: f64,
+    loss: f64,
+}
+
+impl Rsi {
+    fn new(period: usize) -> Self {
+        Rsi {
+            period,
+            previous_close: None,
+            count: 0,
+            gain: 0.0,
+            loss: 0.0,
+        }
+    }
+
+    fn push(&mut self, close: f64) -> Option<f64> {
+        let change = close - self.previous_close.replace(close)?;
+        let period = self.period as f64;
+
+        if self.count < self.period {
+            self.count += 1;
+            self.gain += change.max(0.0) / period;
+            self.loss += (-change).max(0.0) / period;
+
+            if self.count < self.period {
+                return None;
+            }
+        } else {
+            self.gain = (self.gain * (period - 1.0) + change.max(0.0)) / period;
+            self.loss = (self.loss * (period - 1.0) + (-change).max(0.0)) / period;
+        }
+
+        if self.loss == 0.0 {
+            return match self.gain == 0.0 {
+                true => Some(50.0),
+                false => Some(100.0),
+            };
+        }
+
+        Some(100.0 - 100.0 / (1.0 + self.gain / self.loss))
+    }
+}
@@ -1,7 +1,25 @@
 pub mod stock_information_cache;
 pub mod stock_analysis;
 pub mod data;
+pub mod aggregation;
+pub mod bar_store;
+pub mod snapshot;
+pub mod reference_data;
+pub mod indicators;
+pub mod session;
+pub mod basket;
+pub mod top_movers;
+pub mod alerts;
 
-pub use crate::value_store::stock_information_cache::StockInformationCacheInterface;
-pub use crate::value_store::data::OHLCModel;
-pub use crate::value_store::stock_analysis::AnalysisInfo;
\ No newline at end of file
+pub use crate::value_store::stock_information_cache::{EvictionReason, IngestReport, StockInformationCacheInterface};
+pub use crate::value_store::data::{BarEvent, BarFilter, BarFormat, OHLCModel, ParseError};
+pub use crate::value_store::stock_analysis::AnalysisInfo;
+pub use crate::value_store::aggregation::BarAggregator;
+pub use crate::value_store::bar_store::{BarStore, BarStoreReader};
+pub use crate::value_store::snapshot::{CacheSnapshot, StockSnapshot};
+pub use crate::value_store::reference_data::{ReferenceData, SymbolInfo};
+pub use crate::value_store::indicators::{IndicatorPoint, IndicatorSeries, IndicatorSpec};
+pub use crate::value_store::session::{SessionBoundary, SessionInfo, SessionStats};
+pub use crate::value_store::basket::{Basket, BasketSpec, BasketWeighting};
+pub use crate::value_store::top_movers::{MoverStats, MoversWindow, TopMovers};
+pub use crate::value_store::alerts::{Alert, AlertRule, CrossDirection};
@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Columns understood in a reference data CSV file. Other columns are ignored.
+pub const REFERENCE_COLUMNS: [&str; 5] = ["symbol", "name", "exchange", "currency", "sector"];
+
+/// Static metadata of one symbol. Everything but the symbol is optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SymbolInfo {
+    pub symbol: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sector: Option<String>,
+}
+
+/// Registry of [`SymbolInfo`] read from a local file.
+///
+/// Files ending in `.json` hold an array of objects with the fields of [`SymbolInfo`]. Any
+/// other file is read as CSV with a header row naming the [`REFERENCE_COLUMNS`] it has, in
+/// any order; `symbol` is required. Fields may be quoted with `"`, and `""` inside quotes
+/// is a literal quote. Empty fields count as missing.
+///
+/// Clones share the same registry.
+#[derive(Clone, Default)]
+pub struct ReferenceData {
+    path: Arc<RwLock<Option<PathBuf>>>,
+    symbols: Arc<RwLock<HashMap<String, SymbolInfo>>>,
+}
+
+impl ReferenceData {
+    pub fn new() -> Self {
+        ReferenceData::default()
+    }
+
+    /// Replaces the registry with the content of `path` and remembers it for
+    /// [`ReferenceData::reload`]. Returns the number of symbols read. On error the
+    /// registry is left as it was.
+    pub fn load(&self, path: &Path) -> io::Result<usize> {
+        let content = fs::read_to_string(path)?;
+
+        let symbols = match path.extension().and_then(|v| v.to_str()) {
+            Some(v) if v.eq_ignore_ascii_case("json") => parse_json(&content)?,
+            _ => parse_csv(&content)?,
+        };
+
+        let n = symbols.len();
+
+        *self.symbols.write().unwrap() = symbols
+            .into_iter()
+            .map(|v| (v.symbol.clone(), v))
+            .collect();
+        *self.path.write().unwrap() = Some(path.to_owned());
+
+        Ok(n)
+    }
+
+    /// Reads the last loaded file again. Fails if nothing was loaded yet.
+    pub fn reload(&self) -> io::Result<usize> {
+        let path = match self.path.read().unwrap().clone() {
+            Some(v) => v,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no reference data file configured")),
+        };
+
+        self.load(&path)
+    }
+
+    /// Returns the metadata of `symbol`.
+    pub fn get(&self, symbol: &String) -> Option<SymbolInfo> {
+        self.symbols.read().unwrap().get(symbol).cloned()
+    }
+
+    /// Returns the number of symbols in the registry.
+    pub fn len(&self) -> usize {
+        self.symbols.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Parses a JSON array of [`SymbolInfo`] objects.
+pub fn parse_json(content: &str) -> io::Result<Vec<SymbolInfo>> {
+    let symbols = serde_json::from_str::<Vec<SymbolInfo>>(content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    match symbols.iter().position(|v| v.symbol.trim().is_empty()) {
+        Some(i) => Err(invalid_data(&format!("entry {} has an empty symbol", i))),
+        None => Ok(symbols),
+    }
+}
+
+/// Parses reference data CSV, see [`ReferenceData`].
+pub fn parse_csv(content: &str) -> io::Result<Vec<SymbolInfo>> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, v)| !v.trim().is_empty());
+
+    let header = match lines.next() {
+        Some((_, v)) => split_csv_line(v).ok_or_else(|| invalid_data("line 1: unterminated quote"))?,
+        None => return Ok(Vec::new()),
+    };
+
+    let columns: Vec<Option<usize>> = REFERENCE_COLUMNS
+        .iter()
+        .map(|column| header.iter().position(|v| v.trim().eq_ignore_ascii_case(column)))
+        .collect();
+
+    if columns[0].is_none() {
+        return Err(invalid_data("line 1: missing column \"symbol\""));
+    }
+
+    let mut symbols = Vec::new();
+
+    for (i, line) in lines {
+        let fields = split_csv_line(line).ok_or_else(|| invalid_data(&format!("line {}: unterminated quote", i + 1)))?;
+
+        let field = |n: usize| columns[n]
+            .and_then(|v| fields.get(v))
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty());
+
+        let symbol = match field(0) {
+            Some(v) => v,
+            None => return Err(invalid_data(&format!("line {}: empty symbol", i + 1))),
+        };
+
+        symbols.push(SymbolInfo {
+            symbol,
+            name: field(1),
+            exchange: field(2),
+            currency: field(3),
+            sector: field(4),
+        });
+    }
+
+    Ok(symbols)
+}
+
+/// Splits one CSV line on commas outside of quotes. Returns `None` for an unterminated quote.
+fn split_csv_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+
+    fields.push(field);
+
+    Some(fields)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
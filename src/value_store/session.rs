@@ -0,0 +1,215 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::value_store::OHLCModel;
+
+const DAY_MS: u128 = 24 * 3_600_000;
+
+/// Time of day, in UTC, at which a trading session ends and the next one starts.
+///
+/// Written as `HH:MM`, e.g. `"13:30"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SessionBoundary {
+    minutes: u32,
+}
+
+impl SessionBoundary {
+    pub fn new(hours: u32, minutes: u32) -> Option<Self> {
+        match hours < 24 && minutes < 60 {
+            true => Some(SessionBoundary { minutes: hours * 60 + minutes }),
+            false => None,
+        }
+    }
+
+    /// Returns the start of the session containing `timestamp`, both in milliseconds since
+    /// the Unix epoch.
+    pub fn session_start(&self, timestamp: u128) -> u128 {
+        let offset = self.minutes as u128 * 60_000;
+
+        match timestamp < offset {
+            true => 0,
+            false => timestamp - (timestamp - offset) % DAY_MS,
+        }
+    }
+}
+
+impl FromStr for SessionBoundary {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hours, minutes) = s.trim().split_once(':').ok_or_else(|| "expected HH:MM".to_owned())?;
+
+        let hours = hours.parse::<u32>().map_err(|e| e.to_string())?;
+        let minutes = minutes.parse::<u32>().map_err(|e| e.to_string())?;
+
+        SessionBoundary::new(hours, minutes).ok_or_else(|| "not a time of day".to_owned())
+    }
+}
+
+impl TryFrom<String> for SessionBoundary {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for SessionBoundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// Session averages of one stock, as sent to clients.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionInfo {
+    /// Start of the session, in milliseconds since the Unix epoch.
+    pub session_start: u128,
+    /// Interval, in seconds, of the bars the averages are computed from.
+    pub interval: u128,
+    /// Volume weighted average of the typical price `(high + low + close) / 3`.
+    /// `None` until some volume traded.
+    pub vwap: Option<f64>,
+    /// Time weighted average of the close price. Every close counts from its bar's timestamp
+    /// to the next bar's, the latest one for its interval.
+    pub twap: Option<f64>,
+    pub volume: f64,
+    pub bars: u64,
+    /// Timestamp of the latest bar counted.
+    pub last_timestamp: u128,
+}
+
+/// Running VWAP and TWAP of one stock over the current session.
+///
+/// Only the finest bars of the stock are counted, so every trade is counted once. When a
+/// finer interval shows up the session starts over with it. Bars of an earlier session are
+/// ignored and a bar of a later one starts a new session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    session_start: u128,
+    interval: Option<u128>,
+    price_volume: f64,
+    volume: f64,
+    price_time: f64,
+    time: f64,
+    /// Close price of every counted bar by timestamp, for the time each one is in effect.
+    closes: BTreeMap<u128, f64>,
+    bars: u64,
+    last_timestamp: u128,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        SessionStats::default()
+    }
+
+    /// Counts a new bar. Returns true if it changed the averages.
+    pub fn add_ohlc(&mut self, ohlc_model: &OHLCModel, boundary: &SessionBoundary) -> bool {
+        let session_start = boundary.session_start(ohlc_model.timestamp);
+
+        match self.interval {
+            Some(v) if ohlc_model.stock_interval > v => return false,
+            Some(_) if session_start < self.session_start => return false,
+            Some(v) if ohlc_model.stock_interval < v || session_start > self.session_start => self.reset(),
+            Some(_) => (),
+            None => (),
+        };
+
+        self.session_start = session_start;
+        self.interval = Some(ohlc_model.stock_interval);
+
+        self.bars += 1;
+        self.last_timestamp = self.last_timestamp.max(ohlc_model.timestamp);
+        self.apply_volume(ohlc_model, 1.0);
+        self.insert_close(ohlc_model.timestamp, ohlc_model.price_close);
+
+        true
+    }
+
+    /// Replaces the contribution of `previous` by the one of `corrected`. Returns true if it
+    /// changed the averages.
+    pub fn correct_ohlc(&mut self, previous: &OHLCModel, corrected: &OHLCModel, boundary: &SessionBoundary) -> bool {
+        if !self.counts(previous, boundary) {
+            return false;
+        }
+
+        self.apply_volume(previous, -1.0);
+        self.apply_volume(corrected, 1.0);
+        self.correct_close(corrected.timestamp, corrected.price_close);
+
+        true
+    }
+
+    /// Returns the averages, `None` before the first bar.
+    pub fn info(&self) -> Option<SessionInfo> {
+        let interval = self.interval?;
+
+        Some(SessionInfo {
+            session_start: self.session_start,
+            interval,
+            vwap: (self.volume > 0.0).then(|| self.price_volume / self.volume),
+            twap: (self.time > 0.0).then(|| self.price_time / self.time),
+            volume: self.volume,
+            bars: self.bars,
+            last_timestamp: self.last_timestamp,
+        })
+    }
+
+    fn counts(&self, ohlc_model: &OHLCModel, boundary: &SessionBoundary) -> bool {
+        self.interval == Some(ohlc_model.stock_interval)
+            && boundary.session_start(ohlc_model.timestamp) == self.session_start
+    }
+
+    fn apply_volume(&mut self, ohlc_model: &OHLCModel, sign: f64) {
+        let typical_price = (ohlc_model.max_price + ohlc_model.min_price + ohlc_model.price_close) / 3.0;
+
+        self.price_volume += sign * typical_price * ohlc_model.volume;
+        self.volume += sign * ohlc_model.volume;
+    }
+
+    /// Returns how long, in seconds, the close at `timestamp` is in effect when the next
+    /// bar starts at `next`: until then, or for one interval for the latest bar, but never
+    /// past the end of the session.
+    fn duration(&self, timestamp: u128, next: Option<u128>) -> f64 {
+        let session_end = self.session_start + DAY_MS;
+        let end = next.unwrap_or(timestamp + self.interval.unwrap_or_default() * 1000).min(session_end);
+
+        end.saturating_sub(timestamp) as f64 / 1000.0
+    }
+
+    /// Counts the close of a new bar and shortens the time of the close before it.
+    fn insert_close(&mut self, timestamp: u128, price_close: f64) {
+        if self.closes.contains_key(&timestamp) {
+            return self.correct_close(timestamp, price_close);
+        }
+
+        let next = self.closes.range(timestamp..).next().map(|(k, _)| *k);
+
+        if let Some((previous, close)) = self.closes.range(..timestamp).next_back().map(|(k, v)| (*k, *v)) {
+            let change = self.duration(previous, Some(timestamp)) - self.duration(previous, next);
+
+            self.price_time += close * change;
+            self.time += change;
+        }
+
+        let duration = self.duration(timestamp, next);
+
+        self.price_time += price_close * duration;
+        self.time += duration;
+        self.closes.insert(timestamp, price_close);
+    }
+
+    fn correct_close(&mut self, timestamp: u128, price_close: f64) {
+        let next = self.closes.range(timestamp + 1..).next().map(|(k, _)| *k);
+
+        if let Some(previous) = self.closes.insert(timestamp, price_close) {
+            self.price_time += (price_close - previous) * self.duration(timestamp, next);
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = SessionStats::default();
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::value_store::{AnalysisInfo, BasketSpec, OHLCModel};
+
+/// Version written into every [`CacheSnapshot`]. Snapshots of another version aren't loaded.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The whole content of the cache at one point in time: the bar history of every stock,
+/// the DataFeed counters and history and the baskets.
+#[derive(Serialize, Deserialize)]
+pub struct CacheSnapshot {
+    pub snapshot_version: u32,
+    /// When the snapshot was taken, in milliseconds since the Unix epoch.
+    pub timestamp: u128,
+    /// Stocks in the order they were first seen.
+    pub stocks: Vec<StockSnapshot>,
+    pub analysis_info: AnalysisInfo,
+    /// Baskets sorted by name, without their levels. Missing in older snapshots.
+    #[serde(default)]
+    pub baskets: Vec<BasketSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StockSnapshot {
+    pub stock_name: String,
+    /// When the last bar of the stock arrived, in milliseconds since the Unix epoch.
+    /// Missing in older snapshots, which count as updated when they are loaded.
+    #[serde(default)]
+    pub last_update: u128,
+    /// Bars of every interval, ascending by interval and then oldest first.
+    pub bars: Vec<OHLCModel>,
+}
+
+impl CacheSnapshot {
+    /// Writes the snapshot as JSON to `path`. The file is written next to `path` first and
+    /// renamed over it, so `path` always holds a complete snapshot.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+
+        if let Some(v) = path.parent().filter(|v| !v.as_os_str().is_empty()) {
+            fs::create_dir_all(v)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Reads a snapshot written by [`CacheSnapshot::write`].
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let snapshot: CacheSnapshot = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+        if snapshot.snapshot_version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported snapshot version {}", snapshot.snapshot_version),
+            ));
+        }
+
+        Ok(snapshot)
+    }
+}
@@ -1,31 +1,65 @@
 use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::value_store::OHLCModel;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AnalysisInfo {
     stocks: usize,
     trades: i64,
     volume: f64,
     market_value: f64,
+    history_size: usize,
     data_history: VecDeque<String>,
+    /// Start of the current period, the timestamp of the last reset.
+    #[serde(default)]
+    period_start: u128,
 }
 
 impl AnalysisInfo {
-    pub fn new() -> Self {
+    pub fn new(history_size: usize) -> Self {
         AnalysisInfo {
             stocks: 0,
             trades: 0,
             volume: 0.0,
             market_value: 0.0,
+            history_size,
             data_history: VecDeque::new(),
+            period_start: 0,
         }
     }
 
+    /// Returns true if `ohlc_model` starts in the current period, so that its counters
+    /// include it.
+    pub fn in_period(&self, ohlc_model: &OHLCModel) -> bool {
+        ohlc_model.timestamp >= self.period_start
+    }
+
     pub fn add_ohlc(&mut self, ohlc_model: &OHLCModel) {
         self.trades += ohlc_model.trades;
         self.volume += ohlc_model.volume;
-        self.market_value += ohlc_model.price_close * ohlc_model.volume as f64;
+        self.market_value += ohlc_model.price_close * ohlc_model.volume;
+    }
+
+    /// Takes a bar back out of the counters of the current period, used when it is corrected.
+    pub fn remove_ohlc(&mut self, ohlc_model: &OHLCModel) {
+        self.trades -= ohlc_model.trades;
+        self.volume -= ohlc_model.volume;
+        self.market_value -= ohlc_model.price_close * ohlc_model.volume;
+    }
+
+    /// Takes over the counters and DataFeed history of `analysis_info`, keeping this
+    /// instance's history size.
+    pub fn restore(&mut self, analysis_info: AnalysisInfo) {
+        let history_size = self.history_size;
+
+        *self = analysis_info;
+        self.history_size = history_size;
+
+        while self.data_history.len() > self.history_size {
+            let _ = self.data_history.pop_front();
+        }
     }
 
     pub fn set_stock_number(&mut self, n: usize) {
@@ -44,10 +78,11 @@ impl AnalysisInfo {
         self.trades = 0;
         self.volume = 0.0;
         self.market_value = 0.0;
+        self.period_start = timestamp;
 
         self.data_history.push_back(stock_info.clone());
         
-        if self.data_history.len() > 120 {
+        if self.data_history.len() > self.history_size {
             let _ = self.data_history.pop_front();
         }
 
@@ -1,104 +1,666 @@
 use std::{
-    sync::{Arc, RwLock},
-    collections::{HashMap, VecDeque}
+    io,
+    path::Path,
+    sync::{mpsc, Arc, Mutex, RwLock},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    thread,
+    time::SystemTime,
 };
 
-use crate::value_store::{OHLCModel, AnalysisInfo};
+use crate::value_store::{
+    aggregation::{adjust_bar, late_source},
+    basket::BASKET_TOPIC_PREFIX,
+    top_movers::TOP_MOVERS_TOPIC,
+    snapshot::SNAPSHOT_VERSION,
+    AnalysisInfo,
+    BarAggregator,
+    Basket,
+    BasketSpec,
+    BarEvent,
+    BarFilter,
+    BarFormat,
+    BarStore,
+    CacheSnapshot,
+    IndicatorPoint,
+    IndicatorSeries,
+    IndicatorSpec,
+    MoversWindow,
+    OHLCModel,
+    ParseError,
+    SessionBoundary,
+    SessionInfo,
+    SessionStats,
+    StockSnapshot,
+    TopMovers,
+};
+
+/// Outcome of ingesting one batch of upstream bar lines.
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    /// Bars that were stored or corrected, in the order they arrived.
+    pub accepted: Vec<BarEvent>,
+    /// Lines repeating a stored bar unchanged.
+    pub duplicates: usize,
+    /// Lines that were rejected, with the reason.
+    pub rejected: Vec<(String, ParseError)>,
+}
+
+struct BarHistory {
+    retention: usize,
+    bars: VecDeque<OHLCModel>,
+    /// Timestamps of the stored bars built by the [`BarAggregator`] rather than received.
+    built: BTreeSet<u128>,
+    /// Indicators requested by subscribers, by [`IndicatorSpec::key`].
+    indicators: BTreeMap<String, IndicatorSeries>,
+}
+
+impl BarHistory {
+    /// Brings the indicators up to date with the stored bar at `timestamp`. Indicators
+    /// that can't apply it incrementally are rebuilt from the whole history.
+    fn update_indicators(&mut self, timestamp: u128) {
+        let bar = self.bars
+            .binary_search_by_key(&timestamp, |v| v.timestamp)
+            .ok()
+            .map(|i| &self.bars[i]);
+
+        for series in self.indicators.values_mut() {
+            if !bar.is_some_and(|v| series.update(v, self.retention)) {
+                series.rebuild(self.bars.iter(), self.retention);
+            }
+        }
+    }
+}
+
+/// Why a symbol was dropped from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// No bar arrived for longer than `cache.max_idle_secs`.
+    Idle,
+    /// The cache was over `cache.memory_budget_bytes` and the symbol was the least recently updated.
+    MemoryBudget,
+    /// An admin removed it.
+    Removed,
+}
+
+impl EvictionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionReason::Idle => "idle",
+            EvictionReason::MemoryBudget => "memory_budget",
+            EvictionReason::Removed => "removed",
+        }
+    }
+}
+
+/// Rough heap and bookkeeping cost of a symbol, not counting its bars.
+const STOCK_OVERHEAD_BYTES: usize = 256;
 
 struct StockInformation {
-    stock_history: [VecDeque<OHLCModel>; 5],
+    seq: u64,
+    last_update: u128,
+    stock_history: BTreeMap<u128, BarHistory>,
+    session: SessionStats,
 }
 
 impl StockInformation {
-    pub fn new() -> Self {
+    pub fn new(interval_retention: &BTreeMap<u128, usize>, seq: u64, last_update: u128) -> Self {
         StockInformation {
-            stock_history: [
-                VecDeque::new(),
-                VecDeque::new(),
-                VecDeque::new(),
-                VecDeque::new(),
-                VecDeque::new()
-            ],
-        }
-    }
-
-    pub fn add_ohlc(&mut self, ohlc_model: OHLCModel) {
-        let id = match ohlc_model.stock_interval {
-            1 => 0,
-            10 => 1,
-            60 => 2,
-            300 => 3,
-            600 => 4,
-            _ => return,
+            seq,
+            last_update,
+            stock_history: interval_retention
+                .iter()
+                .map(|(interval, retention)| (*interval, BarHistory {
+                    retention: *retention,
+                    bars: VecDeque::new(),
+                    built: BTreeSet::new(),
+                    indicators: BTreeMap::new(),
+                }))
+                .collect(),
+            session: SessionStats::new(),
+        }
+    }
+
+    /// Stores a bar in timestamp order and updates the indicators of its interval. A stored
+    /// bar with the same timestamp is replaced and returned. `built` tells whether the bar
+    /// was built by aggregation.
+    pub fn add_ohlc(&mut self, ohlc_model: OHLCModel, built: bool) -> Option<OHLCModel> {
+        let history = self.stock_history.get_mut(&ohlc_model.stock_interval)?;
+        let timestamp = ohlc_model.timestamp;
+
+        match built {
+            true => history.built.insert(timestamp),
+            false => history.built.remove(&timestamp),
         };
 
-        self.stock_history[id].push_back(ohlc_model);
+        let previous = match history.bars.binary_search_by_key(&timestamp, |v| v.timestamp) {
+            Ok(i) => Some(std::mem::replace(&mut history.bars[i], ohlc_model)),
+            Err(i) => {
+                history.bars.insert(i, ohlc_model);
 
-        if self.stock_history[id].len() > 120 {
-            let _ = self.stock_history[id].pop_front();
+                if history.bars.len() > history.retention {
+                    if let Some(v) = history.bars.pop_front() {
+                        history.built.remove(&v.timestamp);
+                    }
+                }
+
+                None
+            },
+        };
+
+        history.update_indicators(timestamp);
+
+        previous
+    }
+
+    pub fn update_indicators(&mut self, stock_interval: u128, timestamp: u128) {
+        if let Some(v) = self.stock_history.get_mut(&stock_interval) {
+            v.update_indicators(timestamp);
         }
     }
+
+    pub fn get_bars(&self, stock_interval: u128) -> Option<&VecDeque<OHLCModel>> {
+        self.stock_history.get(&stock_interval).map(|v| &v.bars)
+    }
+
+    /// Estimates the memory held by this symbol for the cache's memory budget.
+    pub fn estimated_bytes(&self, name: &str) -> usize {
+        let bars: usize = self.stock_history.values().map(|v| v.bars.len()).sum();
+        let indicator_values: usize = self.stock_history
+            .values()
+            .flat_map(|v| v.indicators.values())
+            .map(|v| v.points().len() * (std::mem::size_of::<IndicatorPoint>() + v.spec().fields().len() * 8))
+            .sum();
+
+        STOCK_OVERHEAD_BYTES + name.len() + bars * (std::mem::size_of::<OHLCModel>() + name.len()) + indicator_values
+    }
+
+    /// Whether a bar at `timestamp` would be dropped right away, being older than every bar
+    /// of a full history.
+    pub fn is_expired(&self, stock_interval: u128, timestamp: u128) -> bool {
+        self.stock_history
+            .get(&stock_interval)
+            .filter(|v| v.bars.len() >= v.retention)
+            .and_then(|v| v.bars.front())
+            .is_some_and(|v| timestamp < v.timestamp)
+    }
+
+    /// Whether the stored bar at `timestamp` was built by aggregation rather than received.
+    pub fn is_built(&self, stock_interval: u128, timestamp: u128) -> bool {
+        self.stock_history.get(&stock_interval).is_some_and(|v| v.built.contains(&timestamp))
+    }
+
+    pub fn get_bar(&self, stock_interval: u128, timestamp: u128) -> Option<&OHLCModel> {
+        let bars = self.get_bars(stock_interval)?;
+
+        bars.binary_search_by_key(&timestamp, |v| v.timestamp).ok().map(|i| &bars[i])
+    }
+
+    pub fn get_bar_mut(&mut self, stock_interval: u128, timestamp: u128) -> Option<&mut OHLCModel> {
+        let bars = &mut self.stock_history.get_mut(&stock_interval)?.bars;
+
+        bars.binary_search_by_key(&timestamp, |v| v.timestamp).ok().map(|i| &mut bars[i])
+    }
 }
 
 struct StockInformationCache {
+    interval_retention: BTreeMap<u128, usize>,
     meta_info: AnalysisInfo,
-    stock_map: HashMap<String, usize>,
-    stock_vec: Vec<StockInformation>,
+    baskets: BTreeMap<String, Basket>,
+    data_history_size: usize,
+    stock_map: HashMap<String, StockInformation>,
+    next_seq: u64,
+    rejected_lines: u64,
+    unknown_intervals: BTreeMap<u128, u64>,
+    aggregator: Option<BarAggregator>,
+    session_boundary: SessionBoundary,
+    movers_window: Option<MoversWindow>,
 }
 
 impl StockInformationCache {
-    pub fn new() -> Self {
+    pub fn new(interval_retention: BTreeMap<u128, usize>, data_history_size: usize, aggregate: bool) -> Self {
+        let aggregator = match aggregate {
+            true => Some(BarAggregator::new(interval_retention.keys().cloned().collect())),
+            false => None,
+        };
+
         StockInformationCache { 
-            meta_info: AnalysisInfo::new(),
+            interval_retention,
+            meta_info: AnalysisInfo::new(data_history_size),
+            baskets: BTreeMap::new(),
+            data_history_size,
             stock_map: HashMap::new(), 
-            stock_vec: Vec::new(),
+            next_seq: 0,
+            rejected_lines: 0,
+            unknown_intervals: BTreeMap::new(),
+            aggregator,
+            session_boundary: SessionBoundary::default(),
+            movers_window: None,
         }
     }
 
-    pub fn add_json(&mut self, json_data: String) -> OHLCModel {
-        let mut last_ohlc_mode = OHLCModel::new();
+    pub fn add_json(&mut self, json_data: String, now: u128) -> IngestReport {
+        let mut ingest_report = IngestReport::default();
 
-        for ohlc_model in parse_ohlc_models(json_data).into_iter() {
-            let id = match self.stock_map.get(&ohlc_model.stock_name) {
-                Some(v) => *v,
-                None => {
-                    let n = self.stock_vec.len();
-    
-                    self.stock_vec.push(StockInformation::new());
-                    self.stock_map.insert(ohlc_model.stock_name.clone(), n);
-                    self.meta_info.set_stock_number(n+1);
-    
-                    n
-                }
+        for line in json_data.split('\n').filter(|v| !v.trim().is_empty()) {
+            let result = OHLCModel::parse(line).and_then(|ohlc_model| self.add_ohlc(ohlc_model, now));
+
+            match result {
+                Ok(v) if v.is_empty() => ingest_report.duplicates += 1,
+                Ok(v) => ingest_report.accepted.extend(v),
+                Err(e) => {
+                    println!("Rejected bar line {:?}: {}", line, e);
+                    ingest_report.rejected.push((line.to_owned(), e));
+                },
             };
+        }
+
+        self.rejected_lines += ingest_report.rejected.len() as u64;
+
+        ingest_report
+    }
+
+    pub fn add_ohlc(&mut self, ohlc_model: OHLCModel, now: u128) -> Result<Vec<BarEvent>, ParseError> {
+        if !self.interval_retention.contains_key(&ohlc_model.stock_interval) {
+            let count = self.unknown_intervals.entry(ohlc_model.stock_interval).or_insert(0);
+
+            if *count == 0 {
+                println!(
+                    "Received first bar with unconfigured interval {} for {:?}, add it to cache.intervals to keep it",
+                    ohlc_model.stock_interval,
+                    ohlc_model.stock_name,
+                );
+            }
+
+            *count += 1;
+
+            return Err(ParseError::UnknownInterval(ohlc_model.stock_interval));
+        }
+
+        self.insert_stock(&ohlc_model.stock_name, now);
+
+        let stock = self.stock_map.get_mut(&ohlc_model.stock_name).expect("Stock was just inserted");
+
+        if stock.is_expired(ohlc_model.stock_interval, ohlc_model.timestamp) {
+            return Err(ParseError::Expired(ohlc_model.timestamp));
+        }
+
+        stock.last_update = now;
+
+        // A received bar takes the place of a bar built for its slot as a new bar, the built
+        // one was never counted for the DataFeed.
+        let stored = stock
+            .get_bar(ohlc_model.stock_interval, ohlc_model.timestamp)
+            .filter(|_| !stock.is_built(ohlc_model.stock_interval, ohlc_model.timestamp));
+
+        match stored {
+            Some(v) if *v == ohlc_model => Ok(Vec::new()),
+            Some(v) => {
+                let previous = v.clone();
+
+                Ok(self.correct_ohlc(previous, ohlc_model))
+            },
+            None => {
+                let (completed, late) = match self.aggregator.as_mut() {
+                    Some(v) => v.add_ohlc(&ohlc_model),
+                    None => (Vec::new(), Vec::new()),
+                };
+
+                self.meta_info.add_ohlc(&ohlc_model);
 
+                for basket in self.baskets.values_mut().filter(|v| v.contains(&ohlc_model.stock_name)) {
+                    basket.add_ohlc(&ohlc_model);
+                }
+
+                stock.session.add_ohlc(&ohlc_model, &self.session_boundary);
+                stock.add_ohlc(ohlc_model.clone(), false);
+
+                for bar in completed.iter() {
+                    stock.add_ohlc(bar.clone(), true);
+                }
+
+                let mut stored = vec![BarEvent::Bar(ohlc_model.clone())];
+                stored.extend(completed.into_iter().map(BarEvent::Bar));
+
+                for (interval, start) in late.into_iter() {
+                    if let Some(bar) = stock.get_bar_mut(interval, start) {
+                        adjust_bar(bar, &late_source(&ohlc_model), &ohlc_model);
+                        stored.push(BarEvent::Correction(bar.clone()));
+                        stock.update_indicators(interval, start);
+                    }
+                }
+
+                Ok(stored)
+            },
+        }
+    }
+
+    /// Replaces `previous` with `ohlc_model`, moves the DataFeed and basket counters by the
+    /// difference if the bar is in their current period and adjusts the coarser bars built
+    /// from it. A closed period keeps the summary it was published with.
+    fn correct_ohlc(&mut self, previous: OHLCModel, ohlc_model: OHLCModel) -> Vec<BarEvent> {
+        let stock = self.stock_map.get_mut(&ohlc_model.stock_name).expect("Corrected stock exists");
+
+        if self.meta_info.in_period(&previous) {
+            self.meta_info.remove_ohlc(&previous);
             self.meta_info.add_ohlc(&ohlc_model);
-            self.stock_vec[id].add_ohlc(ohlc_model.clone());
-            last_ohlc_mode = ohlc_model;
         }
 
-        last_ohlc_mode
+        for basket in self.baskets.values_mut().filter(|v| v.contains(&ohlc_model.stock_name) && v.in_period(&previous)) {
+            basket.remove_ohlc(&previous);
+            basket.add_ohlc(&ohlc_model);
+        }
+
+        stock.session.correct_ohlc(&previous, &ohlc_model, &self.session_boundary);
+        stock.add_ohlc(ohlc_model.clone(), false);
+
+        let completed = match self.aggregator.as_mut() {
+            Some(v) => v.correct_ohlc(&ohlc_model),
+            None => Vec::new(),
+        };
+
+        let mut events = vec![BarEvent::Correction(ohlc_model.clone())];
+
+        for (interval, start) in completed.into_iter() {
+            if let Some(bar) = stock.get_bar_mut(interval, start) {
+                adjust_bar(bar, &previous, &ohlc_model);
+                events.push(BarEvent::Correction(bar.clone()));
+                stock.update_indicators(interval, start);
+            }
+        }
+
+        events
+    }
+
+    /// Puts a bar read back from storage into the history and the session averages, without
+    /// aggregating it or counting it for the DataFeed. It replaces a stored bar with the same
+    /// timestamp. Bars of unconfigured intervals are ignored.
+    pub fn restore_ohlc(&mut self, ohlc_model: OHLCModel, now: u128) {
+        if !self.interval_retention.contains_key(&ohlc_model.stock_interval) {
+            return;
+        }
+
+        self.insert_stock(&ohlc_model.stock_name, now);
+
+        if let Some(v) = self.stock_map.get_mut(&ohlc_model.stock_name) {
+            match v.add_ohlc(ohlc_model.clone(), false) {
+                Some(previous) => v.session.correct_ohlc(&previous, &ohlc_model, &self.session_boundary),
+                None => v.session.add_ohlc(&ohlc_model, &self.session_boundary),
+            };
+        }
+    }
+
+    pub fn set_session_boundary(&mut self, session_boundary: SessionBoundary) {
+        self.session_boundary = session_boundary;
+    }
+
+    pub fn get_session(&self, name: &String) -> Option<SessionInfo> {
+        self.stock_map.get(name)?.session.info()
+    }
+
+    pub fn snapshot(&self, timestamp: u128) -> CacheSnapshot {
+        let mut stocks: Vec<(u64, StockSnapshot)> = self.stock_map
+            .iter()
+            .map(|(name, stock)| (stock.seq, StockSnapshot {
+                stock_name: name.clone(),
+                last_update: stock.last_update,
+                bars: stock.stock_history.values().flat_map(|v| v.bars.iter().cloned()).collect(),
+            }))
+            .collect();
+
+        stocks.sort_by_key(|(seq, _)| *seq);
+
+        CacheSnapshot {
+            snapshot_version: SNAPSHOT_VERSION,
+            timestamp,
+            stocks: stocks.into_iter().map(|(_, v)| v).collect(),
+            analysis_info: self.meta_info.clone(),
+            baskets: self.baskets.values().map(|v| v.spec().clone()).collect(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: CacheSnapshot, now: u128) {
+        self.stock_map.clear();
+
+        if self.aggregator.is_some() {
+            self.aggregator = Some(BarAggregator::new(self.interval_retention.keys().cloned().collect()));
+        }
+
+        for stock in snapshot.stocks.into_iter() {
+            let last_update = match stock.last_update {
+                0 => now,
+                v => v,
+            };
+
+            self.insert_stock(&stock.stock_name, last_update);
+
+            for ohlc_model in stock.bars.into_iter() {
+                self.restore_ohlc(ohlc_model, now);
+            }
+        }
+
+        self.meta_info.restore(snapshot.analysis_info);
+        self.meta_info.set_stock_number(self.stock_map.len());
+
+        for spec in snapshot.baskets.into_iter() {
+            self.add_basket(spec);
+        }
+    }
+
+    fn insert_stock(&mut self, name: &String, now: u128) {
+        if self.stock_map.contains_key(name) {
+            return;
+        }
+
+        self.stock_map.insert(name.clone(), StockInformation::new(&self.interval_retention, self.next_seq, now));
+        self.next_seq += 1;
+        self.meta_info.set_stock_number(self.stock_map.len());
+    }
+
+    pub fn remove_symbol(&mut self, name: &String) -> bool {
+        if self.stock_map.remove(name).is_none() {
+            return false;
+        }
+
+        if let Some(v) = self.aggregator.as_mut() {
+            v.remove_stock(name);
+        }
+
+        for basket in self.baskets.values_mut() {
+            basket.remove_symbol(name);
+        }
+
+        self.meta_info.set_stock_number(self.stock_map.len());
+
+        true
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.stock_map.iter().map(|(name, stock)| stock.estimated_bytes(name)).sum()
+    }
+
+    pub fn get_last_update(&self, name: &String) -> Option<u128> {
+        self.stock_map.get(name).map(|v| v.last_update)
+    }
+
+    pub fn evict_symbols(
+        &mut self,
+        now: u128,
+        max_idle_ms: Option<u128>,
+        memory_budget: Option<usize>,
+        subscribed: &HashSet<String>,
+    ) -> Vec<(String, EvictionReason)> {
+        let mut evicted = Vec::new();
+
+        if let Some(max_idle_ms) = max_idle_ms {
+            let idle: Vec<String> = self.stock_map
+                .iter()
+                .filter(|(_, stock)| stock.last_update + max_idle_ms < now)
+                .map(|(name, _)| name.clone())
+                .collect();
+
+            evicted.extend(idle.into_iter().map(|v| (v, EvictionReason::Idle)));
+        }
+
+        for (name, _) in evicted.iter() {
+            self.remove_symbol(name);
+        }
+
+        if let Some(memory_budget) = memory_budget {
+            let mut memory_usage = self.memory_usage();
+
+            if memory_usage > memory_budget {
+                let mut by_age: Vec<(bool, u128, u64, String)> = self.stock_map
+                    .iter()
+                    .map(|(name, stock)| (subscribed.contains(name), stock.last_update, stock.seq, name.clone()))
+                    .collect();
+
+                by_age.sort();
+
+                for (_, _, _, name) in by_age.into_iter() {
+                    if memory_usage <= memory_budget {
+                        break;
+                    }
+
+                    memory_usage -= self.stock_map[&name].estimated_bytes(&name);
+                    self.remove_symbol(&name);
+                    evicted.push((name, EvictionReason::MemoryBudget));
+                }
+            }
+        }
+
+        evicted
+    }
+
+    pub fn rejected_lines(&self) -> u64 {
+        self.rejected_lines
+    }
+
+    pub fn get_intervals(&self) -> Vec<u128> {
+        self.interval_retention.keys().cloned().collect()
+    }
+
+    pub fn get_retention(&self, stock_interval: u128) -> Option<usize> {
+        self.interval_retention.get(&stock_interval).copied()
+    }
+
+    pub fn get_unknown_intervals(&self) -> BTreeMap<u128, u64> {
+        self.unknown_intervals.clone()
     }
 
     pub fn has_key(&self, name: &String) -> bool {
         self.stock_map.contains_key(name)
     }
 
-    pub fn get_vec_of_stock(&self, name: &String) -> Vec<String> {
+    pub fn get_symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.stock_map.keys().cloned().collect();
+        symbols.sort();
+
+        symbols
+    }
+
+    pub fn list_symbols(&self, prefix: Option<&str>, contains: Option<&str>) -> Vec<(String, u128)> {
+        let prefix = prefix.map(|v| v.to_lowercase());
+        let contains = contains.map(|v| v.to_lowercase());
+
+        let mut symbols: Vec<(String, u128)> = self.stock_map
+            .iter()
+            .filter(|(name, _)| {
+                let name = name.to_lowercase();
+
+                prefix.as_ref().is_none_or(|v| name.starts_with(v.as_str()))
+                    && contains.as_ref().is_none_or(|v| name.contains(v.as_str()))
+            })
+            .map(|(name, stock)| (name.clone(), stock.last_update))
+            .collect();
+
+        symbols.sort();
+
+        symbols
+    }
+
+    pub fn get_history(&self, name: &String, stock_interval: u128) -> Vec<OHLCModel> {
+        match self.get_bars(name, stock_interval) {
+            Some(v) => v.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_latest(&self, name: &String, stock_interval: u128) -> Option<OHLCModel> {
+        self.get_bars(name, stock_interval).and_then(|v| v.back().cloned())
+    }
+
+    pub fn get_previous(&self, name: &String, stock_interval: u128, timestamp: u128) -> Option<OHLCModel> {
+        let bars = self.get_bars(name, stock_interval)?;
+        let index = bars.partition_point(|v| v.timestamp < timestamp);
+
+        bars.get(index.checked_sub(1)?).cloned()
+    }
+
+    pub fn get_first_since(&self, name: &String, stock_interval: u128, timestamp: u128) -> Option<OHLCModel> {
+        let bars = self.get_bars(name, stock_interval)?;
+
+        bars.get(bars.partition_point(|v| v.timestamp < timestamp)).cloned()
+    }
+
+    pub fn query_range(&self, name: &String, stock_interval: u128, from: u128, to: u128, limit: usize) -> Vec<OHLCModel> {
+        let bars = match self.get_bars(name, stock_interval) {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+
+        let mut range: Vec<OHLCModel> = bars
+            .iter()
+            .rev()
+            .filter(|v| v.timestamp >= from && v.timestamp <= to)
+            .take(limit)
+            .cloned()
+            .collect();
+
+        range.reverse();
+
+        range
+    }
+
+    fn get_bars(&self, name: &String, stock_interval: u128) -> Option<&VecDeque<OHLCModel>> {
+        self.stock_map.get(name)?.get_bars(stock_interval)
+    }
+
+    pub fn get_vec_of_stock(&self, name: &String, bar_format: BarFormat, bar_filter: &BarFilter, now: u128) -> Vec<String> {
         if name == "DataFeed" {
             return self.meta_info.get_history();
         }
 
-        let id = match self.stock_map.get(name) {
-            Some(v) => *v,
+        if name == TOP_MOVERS_TOPIC {
+            let mut stock_vec: Vec<String> = self.top_movers(now).iter().map(|v| v.to_json()).collect();
+
+            stock_vec.push("End of Update".to_owned());
+
+            return stock_vec;
+        }
+
+        if let Some(v) = self.get_basket(name) {
+            return v.get_history();
+        }
+
+        let stock = match self.stock_map.get(name) {
+            Some(v) => v,
             None => return Vec::new(),
         };
 
         let mut stock_vec = Vec::<String>::new();
 
-        for i in 0..5 {
-            for stock in self.stock_vec[id].stock_history[i].iter() {
-                stock_vec.push(stock.to_string());
+        for (interval, history) in stock.stock_history.iter() {
+            if !bar_filter.accepts(*interval) {
+                continue;
+            }
+
+            let skip = match bar_filter.depth {
+                Some(v) => history.bars.len().saturating_sub(v),
+                None => 0,
+            };
+
+            for stock in history.bars.iter().skip(skip) {
+                stock_vec.push(stock.to_json(bar_format));
             }   
         }
 
@@ -107,53 +669,495 @@ impl StockInformationCache {
         stock_vec
     }
 
+    pub fn add_indicator(&mut self, name: &String, stock_interval: u128, spec: &IndicatorSpec) -> bool {
+        let history = match self.stock_map.get_mut(name).and_then(|v| v.stock_history.get_mut(&stock_interval)) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        if !history.indicators.contains_key(&spec.key()) {
+            let mut series = IndicatorSeries::new(spec.clone());
+            series.rebuild(history.bars.iter(), history.retention);
+
+            history.indicators.insert(spec.key(), series);
+        }
+
+        true
+    }
+
+    pub fn remove_indicator(&mut self, name: &String, stock_interval: u128, key: &String) {
+        if let Some(v) = self.stock_map.get_mut(name).and_then(|v| v.stock_history.get_mut(&stock_interval)) {
+            v.indicators.remove(key);
+        }
+    }
+
+    fn get_indicator(&self, name: &String, stock_interval: u128, key: &String) -> Option<&IndicatorSeries> {
+        self.stock_map.get(name)?.stock_history.get(&stock_interval)?.indicators.get(key)
+    }
+
+    pub fn get_indicator_points(&self, name: &String, stock_interval: u128, key: &String) -> Vec<IndicatorPoint> {
+        match self.get_indicator(name, stock_interval, key) {
+            Some(v) => v.points().iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_indicator_point(&self, name: &String, stock_interval: u128, key: &String, timestamp: u128) -> Option<IndicatorPoint> {
+        self.get_indicator(name, stock_interval, key)?.point(timestamp).cloned()
+    }
+
     pub fn retrieve_data_events(&mut self, timestamp: u128) -> String {
         self.meta_info.reset(timestamp)
     }
+
+    pub fn set_movers_window(&mut self, movers_window: MoversWindow) {
+        self.movers_window = Some(movers_window);
+    }
+
+    pub fn top_movers(&self, timestamp: u128) -> Option<TopMovers> {
+        let movers_window = self.movers_window?;
+
+        let stats = self.stock_map
+            .iter()
+            .filter_map(|(name, stock)| movers_window.stats(name, stock.get_bars(movers_window.stock_interval)?, timestamp))
+            .collect();
+
+        Some(TopMovers::rank(&movers_window, timestamp, stats))
+    }
+
+    pub fn add_basket(&mut self, spec: BasketSpec) -> bool {
+        if self.baskets.contains_key(&spec.name) {
+            return false;
+        }
+
+        self.baskets.insert(spec.name.clone(), Basket::new(spec, self.data_history_size));
+
+        true
+    }
+
+    pub fn remove_basket(&mut self, name: &String) -> bool {
+        self.baskets.remove(name).is_some()
+    }
+
+    /// Looks a basket up by its topic, e.g. `basket:TECH`.
+    fn get_basket(&self, topic: &str) -> Option<&Basket> {
+        self.baskets.get(topic.strip_prefix(BASKET_TOPIC_PREFIX)?)
+    }
+
+    pub fn get_basket_spec(&self, name: &String) -> Option<BasketSpec> {
+        self.baskets.get(name).map(|v| v.spec().clone())
+    }
+
+    pub fn has_basket_topic(&self, topic: &str) -> bool {
+        self.get_basket(topic).is_some()
+    }
+
+    pub fn get_baskets(&self) -> Vec<(BasketSpec, f64)> {
+        self.baskets.values().map(|v| (v.spec().clone(), v.level())).collect()
+    }
+
+    pub fn retrieve_basket_events(&mut self, timestamp: u128) -> Vec<(String, String)> {
+        self.baskets
+            .values_mut()
+            .map(|v| (v.spec().topic(), v.reset(timestamp)))
+            .collect()
+    }
 }
 
+/// Work for the thread writing to the [`BarStore`].
+enum StoreRequest {
+    Append(Vec<OHLCModel>),
+    /// Answered once every bar sent before it is written.
+    Flush(mpsc::Sender<()>),
+}
+
+/// The thread writing to the [`BarStore`] and the channel it reads its work from.
+struct StoreWriter {
+    sender: mpsc::Sender<StoreRequest>,
+    thread: thread::JoinHandle<()>,
+}
+
+/// Thread-safe handle to the bar history of every stock seen so far.
+///
+/// Once [`StockInformationCacheInterface::open_store`] was called, every stored bar is also
+/// appended to a [`BarStore`]. The appends are done by a dedicated thread, so storing a bar
+/// never waits for the disk. Clones share the same cache and store.
 #[derive(Clone)]
 pub struct StockInformationCacheInterface {
     stock_cache: Arc<RwLock<StockInformationCache>>,
+    bar_store: Arc<Mutex<Option<BarStore>>>,
+    store_writer: Arc<Mutex<Option<StoreWriter>>>,
 }
 
 impl StockInformationCacheInterface {
-    pub fn new() -> Self {
+    /// Creates an empty cache for the intervals in `interval_retention`, each keeping the mapped
+    /// number of bars per stock, and `data_history_size` DataFeed summaries. With `aggregate`,
+    /// coarser intervals are built from finer bars, see [`BarAggregator`].
+    pub fn new(interval_retention: BTreeMap<u128, usize>, data_history_size: usize, aggregate: bool) -> Self {
         StockInformationCacheInterface {
-            stock_cache: Arc::new(RwLock::new(StockInformationCache::new())),
+            stock_cache: Arc::new(RwLock::new(StockInformationCache::new(interval_retention, data_history_size, aggregate))),
+            bar_store: Arc::new(Mutex::new(None)),
+            store_writer: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub fn add_json(&self, json_data:String) -> OHLCModel {
-        self.stock_cache.write().unwrap().add_json(json_data)
+    /// Opens the [`BarStore`] in `path`, puts the bars it holds back into the cache and
+    /// appends every bar stored from now on.
+    pub fn open_store(&self, path: &Path, segment_bytes: u64, retention_ms: u128, fsync: bool) -> io::Result<()> {
+        self.flush_store();
+
+        let mut store_writer = self.store_writer.lock().unwrap();
+        let mut bar_store = self.bar_store.lock().unwrap();
+        let mut stock_cache = self.stock_cache.write().unwrap();
+        let mut restored = 0;
+        let now = now_ms();
+
+        let store = BarStore::open(path, segment_bytes, retention_ms, fsync, |ohlc_model| {
+            stock_cache.restore_ohlc(ohlc_model, now);
+            restored += 1;
+        })?;
+
+        println!("Restored {} bars from {} segments in {:?}", restored, store.segment_count(), path);
+
+        *bar_store = Some(store);
+
+        if store_writer.is_none() {
+            *store_writer = Some(Self::spawn_store_writer(self.bar_store.clone())?);
+        }
+
+        Ok(())
     }
 
+    /// Starts the thread appending the bars it is sent to `bar_store`. It stops once its
+    /// sender is dropped.
+    fn spawn_store_writer(bar_store: Arc<Mutex<Option<BarStore>>>) -> io::Result<StoreWriter> {
+        let (sender, receiver) = mpsc::channel();
+
+        let thread = thread::Builder::new().name("bar-store-writer".to_owned()).spawn(move || {
+            for request in receiver.iter() {
+                match request {
+                    StoreRequest::Append(bars) => if let Some(v) = bar_store.lock().unwrap().as_mut() {
+                        if let Err(e) = v.append(&bars) {
+                            println!("Couldn't append {} bars to the bar store: {}", bars.len(), e);
+                        }
+                    },
+                    StoreRequest::Flush(v) => {
+                        let _ = v.send(());
+                    },
+                };
+            }
+        })?;
+
+        Ok(StoreWriter { sender, thread })
+    }
+
+    fn append_to_store(store_writer: &Option<StoreWriter>, bar_events: &[BarEvent]) {
+        if let Some(v) = store_writer.as_ref().filter(|_| !bar_events.is_empty()) {
+            let bars: Vec<OHLCModel> = bar_events.iter().map(|v| v.ohlc_model().clone()).collect();
+
+            let _ = v.sender.send(StoreRequest::Append(bars));
+        }
+    }
+
+    /// Waits until every bar stored so far is written to the [`BarStore`], e.g. before
+    /// shutting down. Returns at once if no store is open.
+    pub fn flush_store(&self) {
+        let (sender, receiver) = mpsc::channel();
+
+        let sent = match self.store_writer.lock().unwrap().as_ref() {
+            Some(v) => v.sender.send(StoreRequest::Flush(sender)).is_ok(),
+            None => false,
+        };
+
+        if sent {
+            let _ = receiver.recv();
+        }
+    }
+
+    /// Stops the thread writing to the [`BarStore`] once every bar stored so far is written
+    /// and waits for it, e.g. when shutting down. Bars stored afterwards aren't written until
+    /// the store is opened again. Returns at once if no store is open.
+    pub fn close_store(&self) {
+        let store_writer = self.store_writer.lock().unwrap().take();
+
+        if let Some(StoreWriter { sender, thread }) = store_writer {
+            drop(sender);
+
+            if thread.join().is_err() {
+                println!("The bar store writer panicked");
+            }
+        }
+    }
+
+    /// Parses newline separated upstream bar lines and stores the valid ones.
+    /// Invalid lines are skipped, counted and returned in the report. The accepted bars
+    /// include the coarser bars completed or corrected by aggregation.
+    pub fn add_json(&self, json_data:String) -> IngestReport {
+        let store_writer = self.store_writer.lock().unwrap();
+        let ingest_report = self.stock_cache.write().unwrap().add_json(json_data, now_ms());
+
+        Self::append_to_store(&store_writer, &ingest_report.accepted);
+
+        ingest_report
+    }
+
+    /// Stores a single bar. Fails if its interval isn't configured, or if the bar is older than
+    /// the whole history kept for it, since it couldn't be told apart from one already counted.
+    ///
+    /// Bars are keyed by symbol, interval and timestamp. A bar equal to the stored one with
+    /// the same key is ignored and returns no events. A different one replaces it and is
+    /// returned as a [`BarEvent::Correction`], followed by the corrected coarser bars built
+    /// from it. Otherwise `ohlc_model` is returned as a [`BarEvent::Bar`], followed by the
+    /// coarser bars it completed and, as corrections, the completed coarser bars it arrived
+    /// too late for.
+    pub fn add_ohlc(&self, ohlc_model: OHLCModel) -> Result<Vec<BarEvent>, ParseError> {
+        let store_writer = self.store_writer.lock().unwrap();
+        let stored = self.stock_cache.write().unwrap().add_ohlc(ohlc_model, now_ms())?;
+
+        Self::append_to_store(&store_writer, &stored);
+
+        Ok(stored)
+    }
+
+    /// Returns how many upstream lines were rejected since startup.
+    pub fn rejected_lines(&self) -> u64 {
+        self.stock_cache.read().unwrap().rejected_lines()
+    }
+
+    /// Returns the configured bar intervals in seconds, ascending.
+    pub fn get_intervals(&self) -> Vec<u128> {
+        self.stock_cache.read().unwrap().get_intervals()
+    }
+
+    /// Returns how many bars of `stock_interval` are kept per stock, `None` if the interval
+    /// isn't configured.
+    pub fn get_retention(&self, stock_interval: u128) -> Option<usize> {
+        self.stock_cache.read().unwrap().get_retention(stock_interval)
+    }
+
+    /// Returns how many bars arrived for each interval that isn't configured.
+    pub fn get_unknown_intervals(&self) -> BTreeMap<u128, u64> {
+        self.stock_cache.read().unwrap().get_unknown_intervals()
+    }
+
+    /// Returns true if at least one bar was stored for `name`.
     pub fn has_key(&self, name: &String) -> bool {
         self.stock_cache.read().unwrap().has_key(name)
     }
 
-    pub fn get_vec_of_stock(&self, name: &String) -> Vec<String> {
-        self.stock_cache.read().unwrap().get_vec_of_stock(name)
+    /// Returns the names of all stocks in the cache, sorted.
+    pub fn get_symbols(&self) -> Vec<String> {
+        self.stock_cache.read().unwrap().get_symbols()
     }
 
+    /// Returns the stocks whose name starts with `prefix` and contains `contains`, ignoring
+    /// case, sorted by name, with the time their last bar arrived in milliseconds since the
+    /// Unix epoch.
+    pub fn list_symbols(&self, prefix: Option<&str>, contains: Option<&str>) -> Vec<(String, u128)> {
+        self.stock_cache.read().unwrap().list_symbols(prefix, contains)
+    }
+
+    /// Returns the stored bars of `name` for `stock_interval`, oldest first.
+    pub fn get_history(&self, name: &String, stock_interval: u128) -> Vec<OHLCModel> {
+        self.stock_cache.read().unwrap().get_history(name, stock_interval)
+    }
+
+    /// Returns the most recent bar of `name` for `stock_interval`.
+    pub fn get_latest(&self, name: &String, stock_interval: u128) -> Option<OHLCModel> {
+        self.stock_cache.read().unwrap().get_latest(name, stock_interval)
+    }
+
+    /// Returns the latest cached bar of `name` for `stock_interval` older than `timestamp`.
+    /// Never reads the [`BarStore`].
+    pub fn get_previous(&self, name: &String, stock_interval: u128, timestamp: u128) -> Option<OHLCModel> {
+        self.stock_cache.read().unwrap().get_previous(name, stock_interval, timestamp)
+    }
+
+    /// Returns the oldest cached bar of `name` for `stock_interval` at or after `timestamp`.
+    /// Never reads the [`BarStore`].
+    pub fn get_first_since(&self, name: &String, stock_interval: u128, timestamp: u128) -> Option<OHLCModel> {
+        self.stock_cache.read().unwrap().get_first_since(name, stock_interval, timestamp)
+    }
+
+    /// Returns the stored bars of `name` for `stock_interval` with a timestamp between `from`
+    /// and `to`, both inclusive. Only the latest `limit` of them are returned, oldest first.
+    ///
+    /// Bars older than the cached history are read from the [`BarStore`], if one is open.
+    /// The store isn't locked while its files are read, so this never holds up the appends,
+    /// but it blocks the calling thread.
+    pub fn query_range(&self, name: &String, stock_interval: u128, from: u128, to: u128, limit: usize) -> Vec<OHLCModel> {
+        let mut bars = self.stock_cache.read().unwrap().query_range(name, stock_interval, from, to, limit);
+
+        if bars.len() >= limit {
+            return bars;
+        }
+
+        let stored_to = match self.stock_cache.read().unwrap().get_bars(name, stock_interval).and_then(|v| v.front()) {
+            Some(v) if v.timestamp <= from => return bars,
+            Some(v) => v.timestamp - 1,
+            None => to,
+        };
+
+        let reader = self.bar_store.lock().unwrap().as_ref().map(|v| v.reader());
+
+        if let Some(v) = reader {
+            match v.query(name, stock_interval, from, stored_to.min(to), limit - bars.len()) {
+                Ok(mut stored) => {
+                    stored.extend(bars);
+                    bars = stored;
+                },
+                Err(e) => println!("Couldn't read bars of {:?} from the bar store: {}", name, e),
+            };
+        }
+
+        bars
+    }
+
+    /// Returns the serialized snapshot sent to a new subscriber of `name`, limited to the
+    /// intervals and depth of `bar_filter`.
+    pub fn get_vec_of_stock(&self, name: &String, bar_format: BarFormat, bar_filter: &BarFilter) -> Vec<String> {
+        self.stock_cache.read().unwrap().get_vec_of_stock(name, bar_format, bar_filter, now_ms())
+    }
+
+    /// Returns a copy of everything in the cache.
+    pub fn snapshot(&self) -> CacheSnapshot {
+        self.stock_cache.read().unwrap().snapshot(now_ms())
+    }
+
+    /// Replaces everything in the cache with the content of `snapshot`. Bars of intervals
+    /// that aren't configured are dropped and histories are cut to the configured retention.
+    /// Baskets of the snapshot are added, a basket that already exists is kept as it is.
+    pub fn restore(&self, snapshot: CacheSnapshot) {
+        self.stock_cache.write().unwrap().restore(snapshot, now_ms())
+    }
+
+    /// Writes a [`CacheSnapshot`] of the cache to `path`.
+    pub fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        self.snapshot().write(path)
+    }
+
+    /// Replaces the cache with the [`CacheSnapshot`] in `path`.
+    pub fn load_snapshot(&self, path: &Path) -> io::Result<()> {
+        self.restore(CacheSnapshot::read(path)?);
+
+        Ok(())
+    }
+
+    /// Drops `name` and all its bars from the cache. Returns false if it wasn't cached.
+    /// Its bars stay in the [`BarStore`] until they expire.
+    pub fn remove_symbol(&self, name: &String) -> bool {
+        self.stock_cache.write().unwrap().remove_symbol(name)
+    }
+
+    /// Drops every stock without a new bar for more than `max_idle_ms`, then stocks until the
+    /// estimated size of the cache is within `memory_budget` bytes: the least recently updated
+    /// first, and those in `subscribed` only once no other is left. Returns the dropped stocks
+    /// and why they were dropped.
+    pub fn evict_symbols(
+        &self,
+        now: u128,
+        max_idle_ms: Option<u128>,
+        memory_budget: Option<usize>,
+        subscribed: &HashSet<String>,
+    ) -> Vec<(String, EvictionReason)> {
+        self.stock_cache.write().unwrap().evict_symbols(now, max_idle_ms, memory_budget, subscribed)
+    }
+
+    /// Returns the estimated size of the cached bars in bytes, as counted against the memory budget.
+    pub fn memory_usage(&self) -> usize {
+        self.stock_cache.read().unwrap().memory_usage()
+    }
+
+    /// Returns when the last bar of `name` arrived, in milliseconds since the Unix epoch.
+    pub fn get_last_update(&self, name: &String) -> Option<u128> {
+        self.stock_cache.read().unwrap().get_last_update(name)
+    }
+
+    /// Starts computing `spec` for the bars of `name` and `stock_interval`, from the stored
+    /// history on, unless it already is. Returns false if the stock or interval isn't cached.
+    pub fn add_indicator(&self, name: &String, stock_interval: u128, spec: &IndicatorSpec) -> bool {
+        self.stock_cache.write().unwrap().add_indicator(name, stock_interval, spec)
+    }
+
+    /// Stops computing the indicator with [`IndicatorSpec::key`] `key`.
+    pub fn remove_indicator(&self, name: &String, stock_interval: u128, key: &String) {
+        self.stock_cache.write().unwrap().remove_indicator(name, stock_interval, key)
+    }
+
+    /// Returns the points of an indicator added with [`StockInformationCacheInterface::add_indicator`],
+    /// oldest first.
+    pub fn get_indicator_points(&self, name: &String, stock_interval: u128, key: &String) -> Vec<IndicatorPoint> {
+        self.stock_cache.read().unwrap().get_indicator_points(name, stock_interval, key)
+    }
+
+    /// Returns the point of an indicator for the bar at `timestamp`, if it is past its warm-up.
+    pub fn get_indicator_point(&self, name: &String, stock_interval: u128, key: &String, timestamp: u128) -> Option<IndicatorPoint> {
+        self.stock_cache.read().unwrap().get_indicator_point(name, stock_interval, key, timestamp)
+    }
+
+    /// Sets the time of day at which the session VWAP and TWAP start over. Midnight UTC
+    /// unless set.
+    pub fn set_session_boundary(&self, session_boundary: SessionBoundary) {
+        self.stock_cache.write().unwrap().set_session_boundary(session_boundary)
+    }
+
+    /// Returns the VWAP and TWAP of `name` over the current session, see [`SessionStats`].
+    /// After a restart they cover the bars put back from the snapshot or the bar store.
+    pub fn get_session(&self, name: &String) -> Option<SessionInfo> {
+        self.stock_cache.read().unwrap().get_session(name)
+    }
+
+    /// Closes the current DataFeed period at `timestamp` and returns its summary.
     pub fn retrieve_data_events(&self, timestamp: u128) -> String {
         self.stock_cache.write().unwrap().retrieve_data_events(timestamp)
     }
-}
 
-fn parse_ohlc_models(json_data: String) -> Vec<OHLCModel> {
-    let mut ohlc_models = Vec::<OHLCModel>::new();
-    let mut tmp = String::new();
+    /// Sets the bars [`StockInformationCacheInterface::top_movers`] ranks the stocks on.
+    pub fn set_movers_window(&self, movers_window: MoversWindow) {
+        self.stock_cache.write().unwrap().set_movers_window(movers_window)
+    }
 
-    for c in json_data.chars() {
-        tmp.push(c);
+    /// Ranks every stock by its move, trades and volume over the bars of the window ending
+    /// at `timestamp`, see [`TopMovers`]. `None` until the window is set.
+    pub fn top_movers(&self, timestamp: u128) -> Option<TopMovers> {
+        self.stock_cache.read().unwrap().top_movers(timestamp)
+    }
 
-        if c == '\n' {
-            ohlc_models.push(OHLCModel::from_string(tmp));
+    /// Starts aggregating the bars of the symbols of `spec`, see [`Basket`]. Bars stored
+    /// before aren't counted. Returns false if a basket with the same name exists.
+    pub fn add_basket(&self, spec: BasketSpec) -> bool {
+        self.stock_cache.write().unwrap().add_basket(spec)
+    }
 
-            tmp = String::new();
-        }
+    /// Drops the basket `name`. Returns false if it didn't exist.
+    pub fn remove_basket(&self, name: &String) -> bool {
+        self.stock_cache.write().unwrap().remove_basket(name)
+    }
+
+    pub fn get_basket_spec(&self, name: &String) -> Option<BasketSpec> {
+        self.stock_cache.read().unwrap().get_basket_spec(name)
+    }
+
+    /// Returns true if `topic` is the name a basket is subscribed by, e.g. `basket:TECH`.
+    pub fn has_basket_topic(&self, topic: &str) -> bool {
+        self.stock_cache.read().unwrap().has_basket_topic(topic)
     }
 
-    ohlc_models
+    /// Returns every basket with its index level at the end of the last period, sorted by name.
+    pub fn get_baskets(&self) -> Vec<(BasketSpec, f64)> {
+        self.stock_cache.read().unwrap().get_baskets()
+    }
+
+    /// Closes the current period of every basket at `timestamp` and returns the topic and
+    /// summary of each.
+    pub fn retrieve_basket_events(&self, timestamp: u128) -> Vec<(String, String)> {
+        self.stock_cache.write().unwrap().retrieve_basket_events(timestamp)
+    }
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time is after 1970")
+        .as_millis()
 }
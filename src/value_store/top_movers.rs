@@ -0,0 +1,112 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+use serde::Serialize;
+
+use crate::value_store::OHLCModel;
+
+/// Name the leaderboard is subscribed by, like `"DataFeed"`.
+pub const TOP_MOVERS_TOPIC: &str = "TopMovers";
+
+/// The bars the leaderboard is ranked on: the last `bars` bars of `stock_interval`, and
+/// how many symbols every list holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoversWindow {
+    pub stock_interval: u128,
+    pub bars: usize,
+    pub size: usize,
+}
+
+impl MoversWindow {
+    /// Returns the length of the window in milliseconds.
+    pub fn span_ms(&self) -> u128 {
+        self.stock_interval * 1000 * self.bars as u128
+    }
+
+    /// Returns the move of one stock over the bars of the window ending at `timestamp`,
+    /// `None` if it has no bar in it.
+    pub fn stats(&self, stock_name: &str, history: &VecDeque<OHLCModel>, timestamp: u128) -> Option<MoverStats> {
+        let start = timestamp.saturating_sub(self.span_ms());
+        let skip = history.partition_point(|v| v.timestamp < start);
+        let bars = history.range(skip..);
+
+        let first = bars.clone().next()?;
+        let last = history.back()?;
+
+        let change_pct = match first.price_open > 0.0 {
+            true => Some((last.price_close - first.price_open) / first.price_open * 100.0),
+            false => None,
+        };
+
+        let (trades, volume) = bars.fold((0, 0.0), |(trades, volume), v| (trades + v.trades, volume + v.volume));
+
+        Some(MoverStats {
+            symbol: stock_name.to_owned(),
+            price: last.price_close,
+            change_pct,
+            trades,
+            volume,
+        })
+    }
+}
+
+/// One symbol in the leaderboard.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MoverStats {
+    pub symbol: String,
+    /// Latest close.
+    pub price: f64,
+    /// Change from the open of the first bar of the window to the latest close, in percent.
+    /// `None` if that open isn't positive.
+    pub change_pct: Option<f64>,
+    pub trades: i64,
+    pub volume: f64,
+}
+
+/// Ranking of the symbols over a [`MoversWindow`], as sent to the subscribers of
+/// [`TOP_MOVERS_TOPIC`].
+///
+/// Gainers only hold symbols that went up and losers symbols that went down. Ties are
+/// broken by symbol.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopMovers {
+    pub interval: u128,
+    pub window: usize,
+    pub timestamp: u128,
+    /// Number of symbols with a bar in the window.
+    pub stock_n: usize,
+    pub gainers: Vec<MoverStats>,
+    pub losers: Vec<MoverStats>,
+    pub most_traded: Vec<MoverStats>,
+    pub highest_volume: Vec<MoverStats>,
+}
+
+impl TopMovers {
+    pub fn rank(movers_window: &MoversWindow, timestamp: u128, mut stats: Vec<MoverStats>) -> Self {
+        stats.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        let top = |key: &dyn Fn(&MoverStats) -> Option<f64>| -> Vec<MoverStats> {
+            let mut ranked: Vec<(f64, &MoverStats)> = stats
+                .iter()
+                .filter_map(|v| key(v).filter(|k| *k > 0.0).map(|k| (k, v)))
+                .collect();
+
+            ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+            ranked.into_iter().take(movers_window.size).map(|(_, v)| v.clone()).collect()
+        };
+
+        TopMovers {
+            interval: movers_window.stock_interval,
+            window: movers_window.bars,
+            timestamp,
+            stock_n: stats.len(),
+            gainers: top(&|v| v.change_pct),
+            losers: top(&|v| v.change_pct.map(|k| -k)),
+            most_traded: top(&|v| Some(v.trades as f64)),
+            highest_volume: top(&|v| Some(v.volume)),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Top movers always serialize")
+    }
+}
@@ -1,8 +1,12 @@
 pub mod notification_server;
 pub mod notification_client;
 pub mod websocket_server;
+pub mod protocol;
+pub mod shutdown;
 pub mod utils;
 
 pub use crate::websockets::notification_server::NotificationServer;
 pub use crate::websockets::notification_client::NotificationClient;
-pub use crate::websockets::utils::ConnectionService;
\ No newline at end of file
+pub use crate::websockets::websocket_server::{WebSocketServer, WebSocketServerBuilder};
+pub use crate::websockets::shutdown::ShutdownHandle;
+pub use crate::websockets::utils::{ConnectionService, SubscriptionState};
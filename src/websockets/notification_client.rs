@@ -1,62 +1,94 @@
-use std::{
-    thread,
-    time::Duration,
-    collections::HashSet,
-};
+use std::time::Duration;
 
-use tungstenite::{
-    connect,
-    Message,
+use futures_util::{SinkExt, StreamExt};
+use tokio::time;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
 };
 
-use crate::websockets::ConnectionService;
+use crate::websockets::{
+    shutdown::SHUTDOWN_REASON,
+    ConnectionService,
+    ShutdownHandle,
+};
 
+/// Connects to the upstream bar feed and publishes every received bar
+/// through the [`ConnectionService`], reconnecting when the feed drops.
 pub struct NotificationClient {
     ip_client: String,
+    reconnect_delay: Duration,
     connection_service: ConnectionService,
+    shutdown: ShutdownHandle,
 }
 
 impl NotificationClient {
-    pub fn new(ip_client: String, connection_service: ConnectionService) -> Self {
+    pub fn new(ip_client: String,
+               reconnect_delay: Duration,
+               connection_service: ConnectionService,
+               shutdown: ShutdownHandle) -> Self {
         NotificationClient {
-            ip_client: ip_client, 
-            connection_service: connection_service,
+            ip_client,
+            reconnect_delay,
+            connection_service,
+            shutdown,
         }
     }
 
-    pub fn start_client(&mut self) {
-        loop {
+    /// Reads the feed until a shutdown is requested through the [`ShutdownHandle`].
+    pub async fn start_client(&mut self) {
+        while !self.shutdown.is_shutdown() {
             println!("Trying to Connect");
     
-            let (mut client, _response) = match connect(format!("ws://{}", self.ip_client)) {
-                Ok(v) => v,
+            let mut client = match connect_async(format!("ws://{}", self.ip_client)).await {
+                Ok((v, _response)) => v,
                 Err(_v) => { 
-                    thread::sleep(Duration::from_millis(1000)); 
+                    tokio::select! {
+                        _ = self.shutdown.wait() => (),
+                        _ = time::sleep(self.reconnect_delay) => (),
+                    };
                     
                     continue;
                 },
             };
     
             loop {
-                let message = match client.read() {
-                    Ok(p) => p,
-                    Err(e) => {
+                let message = tokio::select! {
+                    _ = self.shutdown.wait() => {
+                        let close_frame = CloseFrame {
+                            code: CloseCode::Away,
+                            reason: SHUTDOWN_REASON.into(),
+                        };
+
+                        let _ = client.send(Message::Close(Some(close_frame))).await;
+                        break;
+                    },
+                    message = client.next() => message,
+                };
+
+                let message = match message {
+                    Some(Ok(p)) => p,
+                    Some(Err(e)) => {
                         println!("Error receiving message {} \n Closing Client", e);
                         break;
                     },
+                    None => {
+                        println!("Feed closed the connection \n Closing Client");
+                        break;
+                    },
                 };
     
-                match message {
-                    msg @ Message::Text(_) => {
-                        let text: String = msg.into_text().unwrap();
-                        let ohlc_model = self.connection_service.add_ohlc_json(text);
-                        let ids_to_update:HashSet<usize> = self.connection_service.get_subscribers(&ohlc_model.stock_name);
-
-                        self.connection_service.add_events(ids_to_update, ohlc_model.to_string());
-                    }
-                    _ => (),
+                if let msg @ Message::Text(_) = message {
+                    let text: String = msg.into_text().unwrap();
+
+                    self.connection_service.publish_ohlc_json(text);
                 }
             }
         }
+
+        println!("Feed client stopped");
     }
-}
\ No newline at end of file
+}
@@ -1,209 +1,508 @@
 use std::{
-    thread,
-    collections::HashMap,
-    net::{TcpStream, TcpListener},
+    net::SocketAddr,
     time::{Duration, SystemTime},
-    ops::AddAssign,
 };
 
-use tungstenite::{
-    accept,
-    protocol::{Role, WebSocket},
-    Message,
+use serde_json::{json, Value};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{self, Instant, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    accept_async,
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
 };
 
-use crate::websockets::ConnectionService;
+use crate::{
+    value_store::{basket::basket_topic, BarFilter},
+    websockets::{
+        protocol::{self, ClientMessage, ControlAction, ControlError, ControlRequest, ErrorCode, LegacyRequest},
+        shutdown::SHUTDOWN_REASON,
+        ConnectionService,
+        ShutdownHandle,
+    },
+};
 
+/// Accepts websocket subscribers and emits the DataFeed summary on every tick.
+///
+/// Every connection is served by a single task that receives subscription requests
+/// and forwards the events pushed to it by the [`ConnectionService`].
 pub struct NotificationServer {
     ip_server: String,
+    data_feed_tick_ms: u64,
+    ping_interval_ms: u64,
     connection_service: ConnectionService,
+    shutdown: ShutdownHandle,
 }
 
 impl NotificationServer {
-    pub fn new(ip_server: String, connection_service: ConnectionService) -> Self {
+    pub fn new(ip_server: String,
+               data_feed_tick_ms: u64,
+               ping_interval_ms: u64,
+               connection_service: ConnectionService,
+               shutdown: ShutdownHandle) -> Self {
         NotificationServer{ 
-            ip_server: ip_server,
-            connection_service: connection_service,
+            ip_server,
+            data_feed_tick_ms,
+            ping_interval_ms,
+            connection_service,
+            shutdown,
         }
     }
 
-    pub fn start_server(&self) {
-        let server = TcpListener::bind(self.ip_server.clone()).unwrap();
+    /// Binds the listener and spawns the accept and DataFeed tasks on the [`ShutdownHandle`].
+    /// Returns the bound address, which has the actual port when binding port 0.
+    pub async fn start_server(&self) -> std::io::Result<SocketAddr> {
+        let server = TcpListener::bind(self.ip_server.clone()).await?;
+        let local_addr = server.local_addr()?;
+
         let connection_service = self.connection_service.clone();
         let connection_service_clone = connection_service.clone();
+        let data_feed_tick_ms = self.data_feed_tick_ms;
+        let ping_interval = Duration::from_millis(self.ping_interval_ms);
+        let shutdown = self.shutdown.clone();
+        let shutdown_clone = shutdown.clone();
 
-        thread::spawn(move || {
-            for stream in server.incoming() {
-                let connection_service_clone = connection_service.clone();
-
-                thread::spawn(move || {
-                    let stream_read = stream.unwrap();
-                    let send_stream = stream_read.try_clone().unwrap();
-
-                    let websocket_read = accept(stream_read).unwrap();
-                    let websocket_send = WebSocket::from_raw_socket(send_stream, Role::Server, None);
-
-                    let id = connection_service_clone.add_subscriber();
-                    
-                    start_websocket_receiver(
-                        websocket_read, 
-                        connection_service_clone.clone(),
-                        id
-                    );
-                    
-                    start_websocket_sender(
-                        websocket_send, 
-                        connection_service_clone, 
-                        id
-                    );
-        
-                    println!("Spawned websocket {}", id);
-                });
+        self.shutdown.spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    _ = shutdown.wait() => break,
+                    accepted = server.accept() => match accepted {
+                        Ok((v, _)) => v,
+                        Err(e) => {
+                            println!("Error accepting connection {}", e);
+                            continue;
+                        },
+                    },
+                };
+
+                shutdown.spawn(handle_connection(
+                    stream,
+                    connection_service.clone(),
+                    ping_interval,
+                    shutdown.clone(),
+                ));
             }
+
+            println!("Stopped accepting connections");
         });
 
-        thread::spawn(move || {
-            let mut target_time = SystemTime::now();
-            let unix_time = target_time
+        self.shutdown.spawn(async move {
+            let unix_time = SystemTime::now()
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .expect("Time is after 1970")
                     .as_millis();
-            target_time.add_assign(Duration::from_millis(1000 - (unix_time % 1000) as u64));
+            let offset = data_feed_tick_ms - (unix_time % data_feed_tick_ms as u128) as u64;
+            let mut target_time = Instant::now() + Duration::from_millis(offset);
+            let mut target_unix_time = unix_time + offset as u128;
     
             loop {
-                target_time.add_assign(Duration::from_millis(1000));
+                target_time += Duration::from_millis(data_feed_tick_ms);
+                target_unix_time += data_feed_tick_ms as u128;
 
-                let unix_time = target_time
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("Time is after 1970")
-                    .as_millis();
-    
-                connection_service_clone.sync_data_events(unix_time);
-    
-                match target_time.duration_since(SystemTime::now()) {
-                    Ok(v) => thread::sleep(v),
-                    Err(_) => (),
+                tokio::select! {
+                    _ = shutdown_clone.wait() => break,
+                    _ = time::sleep_until(target_time) => (),
                 };
+
+                connection_service_clone.sync_data_events(target_unix_time);
             }
         });
+
+        Ok(local_addr)
     }
 }
 
-fn start_websocket_receiver(mut receiver: WebSocket<TcpStream>,
-                            connection_service:ConnectionService,
-                            id: usize) {
-    thread::spawn(move || {
-        let mut key_stock:String = String::new();
-
-        loop {
-            let message_json:String = match receiver.read() {
-                Ok(message) => match message {
-                    msg @ Message::Text(_) => msg.into_text().unwrap(),
-                    _msg @ Message::Ping(_) | _msg @ Message::Pong(_) => continue,
-                    _ => break,
-                },
-                Err(e) =>{
-                    println!("Error in message {} thread: {}", e, id);
-                    break;
-                },
-            };
-
-            let parsed_json = parse_json(&message_json);
-
-            let stock_name = match parsed_json.get("stock") {
-                Some(v) => v.to_string(),
-                None => {
-                    println!("Error with stock in thread {}", id);
-                    continue;
-                }
-            };
-            
-            connection_service.remove_stock_subscription(id, &key_stock);
-            key_stock = stock_name;
-            connection_service.add_stock_subscription(id, &key_stock);
-        }
+async fn handle_connection(stream: TcpStream,
+                           connection_service: ConnectionService,
+                           ping_interval: Duration,
+                           shutdown: ShutdownHandle) {
+    let websocket = match accept_async(stream).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Error during websocket handshake {}", e);
+            return;
+        },
+    };
 
-        println!("Closing Receiver thread {}", id);
-        connection_service.remove_stock_subscription(id, &key_stock);
-    });
+    let (id, events) = connection_service.add_subscriber();
+    println!("Spawned websocket {}", id);
 
+    serve_websocket(websocket, &connection_service, id, events, ping_interval, shutdown).await;
 
+    println!("Closing websocket {}", id);
+    connection_service.remove_subscriber(id);
 }
 
-fn start_websocket_sender(mut sender: WebSocket<TcpStream>,
-                          connection_service: ConnectionService,
-                          id: usize) {
-    thread::spawn(move || {
-        let mut ping_cnt:usize = 0;
+async fn serve_websocket(websocket: WebSocketStream<TcpStream>,
+                         connection_service: &ConnectionService,
+                         id: usize,
+                         mut events: mpsc::Receiver<String>,
+                         ping_interval: Duration,
+                         shutdown: ShutdownHandle) {
+    let (mut sender, mut receiver) = websocket.split();
+
+    let mut ping = time::interval_at(Instant::now() + ping_interval, ping_interval);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.wait() => {
+                events.close();
+
+                while let Some(update) = events.recv().await {
+                    if sender.feed(Message::Text(update)).await.is_err() {
+                        return;
+                    }
+                }
 
-        loop {
-            let connection_vec = connection_service.read_events(&id);
+                let close_frame = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: SHUTDOWN_REASON.into(),
+                };
 
-            if connection_vec.len() == 0 {
-                thread::sleep(Duration::from_millis(10));
+                let _ = sender.send(Message::Close(Some(close_frame))).await;
+                println!("Closed websocket {} on shutdown", id);
 
-                if !send_ping(&mut sender, &mut ping_cnt) { 
-                    break; 
-                }
-                
-                continue;
-            }
+                return;
+            },
+            message = receiver.next() => {
+                let message_json: String = match message {
+                    Some(Ok(msg @ Message::Text(_))) => msg.into_text().unwrap(),
+                    Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                    Some(Ok(_)) | None => return,
+                    Some(Err(e)) => {
+                        println!("Error in message {} connection: {}", e, id);
+                        return;
+                    },
+                };
 
-            for update in connection_vec.into_iter() {
-                match sender.send(Message::Text(update)) {
-                    Ok(v) => v,
-                    Err(_) => break,
+                if let Some(reply) = handle_message(connection_service, id, &message_json).await {
+                    if sender.send(Message::Text(reply)).await.is_err() {
+                        println!("Error sending message. Closing Websocket {}", id);
+                        return;
+                    }
                 }
-            }
-        }
+            },
+            update = events.recv() => {
+                let update = match update {
+                    Some(v) => v,
+                    None => return,
+                };
 
-        println!("Error sending message. Closing Websocket {}", id);
-        connection_service.remove_subscriber(id);
-    });
-}
+                if sender.feed(Message::Text(update)).await.is_err() {
+                    println!("Error sending message. Closing Websocket {}", id);
+                    return;
+                }
 
-fn send_ping(sender: &mut WebSocket<TcpStream>, ping_cnt: &mut usize) -> bool {
-    *ping_cnt += 1;
+                while let Ok(update) = events.try_recv() {
+                    if sender.feed(Message::Text(update)).await.is_err() {
+                        println!("Error sending message. Closing Websocket {}", id);
+                        return;
+                    }
+                }
 
-    if *ping_cnt < 100 { 
-        return true; 
+                if sender.flush().await.is_err() {
+                    return;
+                }
+            },
+            _ = ping.tick() => {
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    println!("Error sending message. Closing Websocket {}", id);
+                    return;
+                }
+            },
+        }
     }
+}
 
-    match sender.send(Message::Ping(Vec::new())) {
+/// Handles a message from a client and returns the reply to send, if any.
+///
+/// Control requests (see [`protocol::PROTOCOL_VERSION`]) and undecodable messages are always
+/// answered, [`LegacyRequest`]s are not.
+async fn handle_message(connection_service: &ConnectionService, id: usize, message_json: &str) -> Option<String> {
+    let message = match protocol::decode_message(message_json) {
         Ok(v) => v,
-        Err(_) =>  return false,
-    };
+        Err(e) => {
+            println!("Invalid message in connection {}: {}", id, e.error.message);
 
-    *ping_cnt = 0;
+            return Some(protocol::error_response(e.id.as_ref(), e.action.as_deref(), &e.error));
+        },
+    };
 
-    true
+    match message {
+        ClientMessage::Control(request) => Some(handle_request(connection_service, id, &request).await),
+        ClientMessage::Legacy(LegacyRequest::Subscribe(stock_name)) => {
+            let _ = connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default());
+            None
+        },
+        ClientMessage::Legacy(LegacyRequest::Unsubscribe(stock_name)) => {
+            connection_service.remove_stock_subscription(id, &stock_name);
+            None
+        },
+        ClientMessage::Legacy(LegacyRequest::Stock(stock_name)) => {
+            connection_service.remove_all_subscriptions(id);
+            let _ = connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default());
+            None
+        },
+    }
 }
 
-pub fn parse_json(json_data: &str) -> HashMap<String ,String> {
-    let mut tmp: String = String::new();
-    let mut key: String = String::new();
-
-    let mut parsed_json:HashMap<String,String> = HashMap::new();
-
-    for p in json_data.chars() {
-        match p {
-            ' ' | '\n' | '\t' | '\"' | '{' | '}' => (),
-            ':' | ',' => {
-                match key.len() {
-                    0 => key = tmp,
-                    _ => {
-                        parsed_json.insert(key, tmp);
-                        key = String::new();
-                    }
-                };
-                
-                tmp = String::new();
+async fn handle_request(connection_service: &ConnectionService, id: usize, request: &ControlRequest) -> String {
+    let result = match &request.action {
+        ControlAction::Subscribe { symbol, intervals, depth, session } => match connection_service.add_stock_subscription(
+            id,
+            symbol,
+            &protocol::bar_filter(intervals, depth, *session),
+        ) {
+            Ok(state) => Ok(json!({
+                "symbol": symbol,
+                "intervals": intervals,
+                "depth": depth,
+                "session": session,
+                "state": state.as_str(),
+            })),
+            Err(ErrorCode::QuotaExceeded) => Err(ControlError::new(
+                ErrorCode::QuotaExceeded,
+                "maximum number of subscriptions reached",
+            )),
+            Err(ErrorCode::UnknownInterval) => Err(ControlError::new(
+                ErrorCode::UnknownInterval,
+                &format!("intervals {:?} aren't all configured", intervals.as_deref().unwrap_or_default()),
+            )),
+            Err(ErrorCode::SnapshotTooLarge) => Err(ControlError::new(
+                ErrorCode::SnapshotTooLarge,
+                &format!("snapshot of {:?} doesn't fit in the connection queue, try a smaller depth", symbol),
+            )),
+            Err(code) => Err(ControlError::new(code, &format!("no data for symbol {:?}", symbol))),
+        },
+        ControlAction::Unsubscribe { symbol } => if connection_service.remove_stock_subscription(id, symbol) {
+            Ok(json!({ "symbol": symbol }))
+        } else {
+            Err(ControlError::new(ErrorCode::NotSubscribed, &format!("not subscribed to {:?}", symbol)))
+        },
+        ControlAction::QueryRange { symbol, interval, from, to, limit } => match run_blocking(connection_service, {
+            let (symbol, interval, from, to, limit) = (symbol.clone(), *interval as u128, *from as u128, *to as u128, *limit);
+
+            move |v| v.query_range(&symbol, interval, from, to, limit)
+        }).await {
+            Err(e) => Err(e),
+            Ok(Ok(bars)) => match bars.iter().map(|v| v.to_value()).collect::<Result<Vec<Value>, _>>() {
+                Ok(bars) => Ok(json!({
+                    "symbol": symbol,
+                    "interval": interval,
+                    "bars": bars,
+                })),
+                Err(e) => Err(ControlError::new(ErrorCode::Internal, &format!("couldn't serialize bars: {}", e))),
             },
-            _ => tmp.push(p),
-        };
-    }
+            Ok(Err(ErrorCode::UnknownInterval)) => Err(ControlError::new(
+                ErrorCode::UnknownInterval,
+                &format!("interval {} isn't configured", interval),
+            )),
+            Ok(Err(code)) => Err(ControlError::new(code, &format!("no data for symbol {:?}", symbol))),
+        },
+        ControlAction::RemoveSymbol { symbol, token } => if !connection_service.is_admin(token.as_deref()) {
+            Err(ControlError::new(ErrorCode::Forbidden, "admin token missing or invalid"))
+        } else if connection_service.remove_symbol(symbol) {
+            Ok(json!({ "symbol": symbol }))
+        } else {
+            Err(ControlError::new(ErrorCode::UnknownSymbol, &format!("no data for symbol {:?}", symbol)))
+        },
+        ControlAction::GetReference { symbol } => match connection_service.reference_data().get(symbol) {
+            Some(v) => Ok(json!({ "symbol": symbol, "reference": v })),
+            None => Err(ControlError::new(ErrorCode::UnknownSymbol, &format!("no reference data for symbol {:?}", symbol))),
+        },
+        ControlAction::ReloadReferenceData { token } => if !connection_service.is_admin(token.as_deref()) {
+            Err(ControlError::new(ErrorCode::Forbidden, "admin token missing or invalid"))
+        } else {
+            match run_blocking(connection_service, |v| v.reference_data().reload()).await {
+                Err(e) => Err(e),
+                Ok(Ok(n)) => {
+                    println!("Reloaded reference data of {} symbols", n);
+                    Ok(json!({ "symbols": n }))
+                },
+                Ok(Err(e)) => Err(ControlError::new(ErrorCode::ReloadFailed, &format!("couldn't reload reference data: {}", e))),
+            }
+        },
+        ControlAction::ListSymbols { prefix, contains, offset, limit } => {
+            let (total, symbols) = connection_service.list_symbols(prefix.as_deref(), contains.as_deref(), *offset, *limit);
+
+            Ok(json!({
+                "total": total,
+                "offset": offset,
+                "symbols": symbols
+                    .iter()
+                    .map(|(symbol, last_update)| json!({ "symbol": symbol, "last_update": *last_update as u64 }))
+                    .collect::<Vec<Value>>(),
+            }))
+        },
+        ControlAction::GetSession { symbol } => match connection_service.stock_cache().get_session(symbol) {
+            Some(v) => Ok(json!({ "symbol": symbol, "session": v })),
+            None => Err(ControlError::new(ErrorCode::UnknownSymbol, &format!("no data for symbol {:?}", symbol))),
+        },
+        ControlAction::SubscribeIndicator { symbol, interval, indicator } => match connection_service.add_indicator_subscription(
+            id,
+            symbol,
+            *interval as u128,
+            indicator,
+        ) {
+            Ok(()) => Ok(json!({ "symbol": symbol, "interval": interval, "indicator": indicator.key() })),
+            Err(ErrorCode::QuotaExceeded) => Err(ControlError::new(
+                ErrorCode::QuotaExceeded,
+                "maximum number of indicator subscriptions reached",
+            )),
+            Err(ErrorCode::UnknownInterval) => Err(ControlError::new(
+                ErrorCode::UnknownInterval,
+                &format!("interval {} isn't configured", interval),
+            )),
+            Err(ErrorCode::PeriodTooLong) => Err(ControlError::new(
+                ErrorCode::PeriodTooLong,
+                &format!(
+                    "{} needs more than the {} bars kept for interval {}",
+                    indicator.key(),
+                    connection_service.stock_cache().get_retention(*interval as u128).unwrap_or_default(),
+                    interval,
+                ),
+            )),
+            Err(ErrorCode::SnapshotTooLarge) => Err(ControlError::new(
+                ErrorCode::SnapshotTooLarge,
+                &format!("snapshot of {} of {:?} doesn't fit in the connection queue", indicator.key(), symbol),
+            )),
+            Err(code) => Err(ControlError::new(code, &format!("no data for symbol {:?}", symbol))),
+        },
+        ControlAction::UnsubscribeIndicator { symbol, interval, indicator } => {
+            if connection_service.remove_indicator_subscription(id, symbol, *interval as u128, indicator) {
+                Ok(json!({ "symbol": symbol, "interval": interval, "indicator": indicator.key() }))
+            } else {
+                Err(ControlError::new(
+                    ErrorCode::NotSubscribed,
+                    &format!("not subscribed to {} of {:?} at {}s", indicator.key(), symbol, interval),
+                ))
+            }
+        },
+        ControlAction::CreateBasket { token, .. } if !connection_service.is_admin(token.as_deref()) => {
+            Err(ControlError::new(ErrorCode::Forbidden, "admin token missing or invalid"))
+        },
+        ControlAction::CreateBasket { name, symbols, weighting, .. } => match connection_service.add_basket(
+            protocol::basket_spec(name, symbols, *weighting),
+        ) {
+            Ok(()) => Ok(json!({
+                "name": name,
+                "topic": basket_topic(name),
+                "symbols": symbols,
+                "weighting": weighting.as_str(),
+            })),
+            Err(ErrorCode::QuotaExceeded) => Err(ControlError::new(
+                ErrorCode::QuotaExceeded,
+                "maximum number of baskets reached",
+            )),
+            Err(code) => Err(ControlError::new(code, &format!("basket {:?} exists with other symbols or weighting", name))),
+        },
+        ControlAction::DeleteBasket { name, token } => if !connection_service.is_admin(token.as_deref()) {
+            Err(ControlError::new(ErrorCode::Forbidden, "admin token missing or invalid"))
+        } else if connection_service.remove_basket(name) {
+            Ok(json!({ "name": name }))
+        } else {
+            Err(ControlError::new(ErrorCode::UnknownBasket, &format!("no basket {:?}", name)))
+        },
+        ControlAction::ListBaskets => Ok(json!({
+            "baskets": connection_service
+                .stock_cache()
+                .get_baskets()
+                .iter()
+                .map(|(spec, level)| json!({
+                    "name": spec.name,
+                    "topic": spec.topic(),
+                    "symbols": spec.symbols,
+                    "weighting": spec.weighting.as_str(),
+                    "level": level,
+                }))
+                .collect::<Vec<Value>>(),
+        })),
+        ControlAction::CreateAlert { rule } => match connection_service.add_alert(id, rule.clone()) {
+            Ok((token, alert_id, rule)) => Ok(json!({
+                "alert_id": alert_id,
+                "session_token": token,
+                "rule": rule,
+            })),
+            Err(ErrorCode::QuotaExceeded) => Err(ControlError::new(
+                ErrorCode::QuotaExceeded,
+                "maximum number of alerts reached",
+            )),
+            Err(ErrorCode::UnknownInterval) => Err(ControlError::new(ErrorCode::UnknownInterval, &match rule.interval() {
+                Some(v) => format!("interval {} isn't configured", v),
+                None => "no interval is configured".to_owned(),
+            })),
+            Err(code) => Err(ControlError::new(code, &format!("couldn't create an alert on {:?}", rule.symbol()))),
+        },
+        ControlAction::DeleteAlert { alert_id } => if connection_service.remove_alert(id, *alert_id) {
+            Ok(json!({ "alert_id": alert_id }))
+        } else {
+            Err(ControlError::new(ErrorCode::UnknownAlert, &format!("no alert {}", alert_id)))
+        },
+        ControlAction::ListAlerts => {
+            let (token, alerts) = connection_service.get_alerts(id).unzip();
+
+            Ok(json!({
+                "session_token": token,
+                "alerts": alerts
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|(alert_id, rule)| json!({ "alert_id": alert_id, "rule": rule }))
+                    .collect::<Vec<Value>>(),
+            }))
+        },
+        ControlAction::ResumeSession { token } => match connection_service.resume_session(id, token) {
+            Ok((alerts, delivered)) => Ok(json!({
+                "session_token": token,
+                "alerts": alerts,
+                "delivered": delivered,
+            })),
+            Err(code) => Err(ControlError::new(code, "session expired or unknown")),
+        },
+        ControlAction::ListSubscriptions => Ok(json!({
+            "symbols": connection_service.get_subscriptions(id),
+            "indicators": connection_service
+                .get_indicator_subscriptions(id)
+                .iter()
+                .map(|(symbol, interval, indicator)| json!({
+                    "symbol": symbol,
+                    "interval": *interval as u64,
+                    "indicator": indicator,
+                }))
+                .collect::<Vec<Value>>(),
+        })),
+        ControlAction::Ping => Ok(json!({
+            "timestamp": SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("Time is after 1970")
+                .as_millis() as u64,
+        })),
+    };
 
-    if key.len() > 0 && tmp.len() > 0 { parsed_json.insert(key, tmp); } 
+    match result {
+        Ok(v) => protocol::ok_response(&request.id, request.action.name(), v),
+        Err(e) => protocol::error_response(Some(&request.id), Some(request.action.name()), &e),
+    }
+}
 
-    parsed_json
-}
\ No newline at end of file
+/// Runs `f` on the blocking thread pool, for requests that read files, so the runtime keeps
+/// serving the other connections meanwhile.
+async fn run_blocking<T, F>(connection_service: &ConnectionService, f: F) -> Result<T, ControlError>
+where
+    T: Send + 'static,
+    F: FnOnce(&ConnectionService) -> T + Send + 'static,
+{
+    let connection_service = connection_service.clone();
+
+    tokio::task::spawn_blocking(move || f(&connection_service))
+        .await
+        .map_err(|e| ControlError::new(ErrorCode::Internal, &format!("request failed: {}", e)))
+}
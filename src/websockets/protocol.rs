@@ -0,0 +1,553 @@
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::value_store::{AlertRule, BarFilter, BasketSpec, BasketWeighting, IndicatorSpec};
+
+/// Version of the control protocol spoken by this server.
+///
+/// A control request is a JSON object with a version, a client chosen id and an action:
+///
+/// ```text
+/// {"v": 1, "id": "7", "action": "subscribe", "symbol": "AAPL"}
+/// {"v": 1, "id": "7", "action": "subscribe", "symbol": "AAPL", "intervals": [60], "depth": 30}
+/// {"v": 1, "id": 8, "action": "unsubscribe", "symbol": "AAPL"}
+/// {"v": 1, "id": "9", "action": "list_subscriptions"}
+/// {"v": 1, "id": "11", "action": "query_range", "symbol": "AAPL", "interval": 60,
+///  "from": 1700000000000, "to": 1700003600000, "limit": 30}
+/// {"v": 1, "id": "10", "action": "ping"}
+/// {"v": 1, "id": "12", "action": "remove_symbol", "symbol": "AAPL", "token": "..."}
+/// {"v": 1, "id": "13", "action": "get_reference", "symbol": "AAPL"}
+/// {"v": 1, "id": "14", "action": "reload_reference_data", "token": "..."}
+/// {"v": 1, "id": "15", "action": "list_symbols", "prefix": "AA", "contains": "P", "offset": 0, "limit": 20}
+/// {"v": 1, "id": "18", "action": "get_session", "symbol": "AAPL"}
+/// {"v": 1, "id": "16", "action": "subscribe_indicator", "symbol": "AAPL", "interval": 60,
+///  "indicator": {"kind": "ema", "period": 20}}
+/// {"v": 1, "id": "17", "action": "unsubscribe_indicator", "symbol": "AAPL", "interval": 60,
+///  "indicator": {"kind": "ema", "period": 20}}
+/// {"v": 1, "id": "19", "action": "create_basket", "name": "TECH", "symbols": ["AAPL", "MSFT"],
+///  "weighting": "value", "token": "..."}
+/// {"v": 1, "id": "20", "action": "list_baskets"}
+/// {"v": 1, "id": "21", "action": "delete_basket", "name": "TECH", "token": "..."}
+/// {"v": 1, "id": "22", "action": "create_alert",
+///  "rule": {"kind": "price_cross", "symbol": "AAPL", "direction": "above", "level": 200}}
+/// {"v": 1, "id": "23", "action": "delete_alert", "alert_id": 1}
+/// {"v": 1, "id": "24", "action": "list_alerts"}
+/// {"v": 1, "id": "25", "action": "resume_session", "token": "..."}
+/// ```
+///
+/// The bars of a `query_range` result always have the `bar_format = "v1"` shape of
+/// [`crate::OHLCModel::to_json`], whatever format pushed bars use.
+///
+/// A subscription receives the bars of every interval unless `intervals` lists the wanted
+/// ones, and its snapshot holds the whole history of each interval unless `depth` limits it.
+/// Its result has `"state": "pending"` if the symbol has no bars yet; the connection then gets
+/// `{"v": 1, "type": "subscription", "symbol": "AAPL", "state": "active"}` and the snapshot
+/// once the first bar arrives. A snapshot is queued whole or not at all: if it doesn't fit in
+/// the connection queue the subscription fails with `snapshot_too_large`, or, when pending,
+/// the connection gets `{"v": 1, "type": "subscription", "symbol": "AAPL", "state": "failed",
+/// "error": {"code": "snapshot_too_large"}}` instead. With `"session": true` it also gets
+/// `{"v": 1, "type": "session", "symbol": "AAPL", "session": {"vwap": ..., "twap": ..., ...}}`
+/// in its snapshot and whenever a bar changes the session averages. The snapshot of a symbol
+/// with reference data starts with a separate
+/// `{"v": 1, "type": "reference", "symbol": "AAPL", "reference": {...}}` message, with legacy
+/// bars too.
+///
+/// An indicator subscription first gets
+/// `{"v": 1, "type": "indicator_snapshot", "symbol": ..., "interval": ..., "indicator": "ema(20)", "points": [...]}`
+/// with the indicator over the cached history, then
+/// `{"v": 1, "type": "indicator", "symbol": ..., "interval": ..., "indicator": "ema(20)", "point": {...}}`
+/// for every new or corrected bar. A point is `{"timestamp": ..., "value": ...}`; MACD points
+/// have `macd`, `signal` and `histogram` and Bollinger points `middle`, `upper` and `lower`.
+/// Periods longer than the bars kept for the interval fail with `period_too_long`.
+///
+/// A basket is subscribed like the DataFeed, by its topic `basket:<name>`, and gets
+/// `{"basket": "TECH", "weighting": "value", "stock_n": ..., "trade_n": ..., "volume_n": ...,
+/// "market_v_n": ..., "level": ..., "timestamp": ...}` once per DataFeed tick. Subscribing to a
+/// basket that doesn't exist yet is pending until it is created. Deleting a basket that
+/// doesn't exist fails with `unknown_basket`.
+///
+/// Alerts belong to a session whose token is returned by `create_alert`. A triggered alert
+/// sends `{"v": 1, "type": "alert", "alert_id": 1, "symbol": "AAPL", "rule": {...}, "value": ...}`
+/// once and is dropped. After a reconnect, `resume_session` with the token brings the alerts
+/// back along with those that triggered in between.
+///
+/// Subscribers of `TopMovers` get the ranking of [`crate::value_store::TopMovers`] once per
+/// DataFeed tick, and the current ranking as snapshot.
+///
+/// `remove_symbol`, `reload_reference_data`, `create_basket` and `delete_basket` are admin requests and need the configured `server.admin_token`.
+/// Subscribers of a stock that is removed or evicted get
+/// `{"v": 1, "type": "evicted", "symbol": "AAPL", "reason": "idle"}` and are unsubscribed.
+///
+/// The id is a string or an integer. Every request is answered with exactly one response
+/// carrying the same id:
+///
+/// ```text
+/// {"v": 1, "type": "response", "id": "7", "action": "subscribe", "status": "ok", "result": {...}}
+/// {"v": 1, "type": "response", "id": "7", "action": "subscribe", "status": "error",
+///  "error": {"code": "unknown_symbol", "message": "..."}}
+/// ```
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// Longest symbol, in characters, accepted in a request.
+pub const MAX_SYMBOL_LEN: usize = 64;
+
+/// The [`ControlAction::name`] of every action. Any other action is answered with
+/// `unknown_action`.
+pub const ACTION_NAMES: &[&str] = &[
+    "subscribe",
+    "unsubscribe",
+    "list_subscriptions",
+    "ping",
+    "query_range",
+    "remove_symbol",
+    "get_reference",
+    "reload_reference_data",
+    "list_symbols",
+    "get_session",
+    "subscribe_indicator",
+    "unsubscribe_indicator",
+    "create_basket",
+    "delete_basket",
+    "list_baskets",
+    "create_alert",
+    "delete_alert",
+    "list_alerts",
+    "resume_session",
+];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ControlAction {
+    Subscribe {
+        symbol: String,
+        #[serde(default)]
+        intervals: Option<Vec<u64>>,
+        #[serde(default)]
+        depth: Option<usize>,
+        #[serde(default)]
+        session: bool,
+    },
+    Unsubscribe { symbol: String },
+    ListSubscriptions,
+    Ping,
+    /// Bars of one interval with a timestamp in `[from, to]`, in milliseconds.
+    QueryRange {
+        symbol: String,
+        interval: u64,
+        from: u64,
+        to: u64,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Drops a stock from the cache, needs the admin token.
+    RemoveSymbol {
+        symbol: String,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// Reference data of a symbol, see [`crate::value_store::ReferenceData`].
+    GetReference { symbol: String },
+    /// Reads the reference data file again, needs the admin token.
+    ReloadReferenceData {
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// Cached symbols, optionally filtered by a case-insensitive prefix and substring.
+    ListSymbols {
+        #[serde(default)]
+        prefix: Option<String>,
+        #[serde(default)]
+        contains: Option<String>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// VWAP and TWAP of a symbol over the current session.
+    GetSession { symbol: String },
+    SubscribeIndicator {
+        symbol: String,
+        interval: u64,
+        indicator: IndicatorSpec,
+    },
+    UnsubscribeIndicator {
+        symbol: String,
+        interval: u64,
+        indicator: IndicatorSpec,
+    },
+    /// Basket of symbols aggregated once per DataFeed tick, see [`crate::value_store::Basket`].
+    /// Needs the admin token.
+    CreateBasket {
+        name: String,
+        symbols: Vec<String>,
+        #[serde(default)]
+        weighting: BasketWeighting,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// Drops a basket, needs the admin token.
+    DeleteBasket {
+        name: String,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    ListBaskets,
+    /// Alert on the bars of a symbol, see [`crate::value_store::AlertRule`].
+    CreateAlert { rule: AlertRule },
+    DeleteAlert { alert_id: u64 },
+    ListAlerts,
+    /// Attaches the alert session of `token` to this connection.
+    ResumeSession { token: String },
+}
+
+impl ControlAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControlAction::Subscribe { .. } => "subscribe",
+            ControlAction::Unsubscribe { .. } => "unsubscribe",
+            ControlAction::ListSubscriptions => "list_subscriptions",
+            ControlAction::Ping => "ping",
+            ControlAction::QueryRange { .. } => "query_range",
+            ControlAction::RemoveSymbol { .. } => "remove_symbol",
+            ControlAction::GetReference { .. } => "get_reference",
+            ControlAction::ReloadReferenceData { .. } => "reload_reference_data",
+            ControlAction::ListSymbols { .. } => "list_symbols",
+            ControlAction::GetSession { .. } => "get_session",
+            ControlAction::SubscribeIndicator { .. } => "subscribe_indicator",
+            ControlAction::UnsubscribeIndicator { .. } => "unsubscribe_indicator",
+            ControlAction::CreateBasket { .. } => "create_basket",
+            ControlAction::DeleteBasket { .. } => "delete_basket",
+            ControlAction::ListBaskets => "list_baskets",
+            ControlAction::CreateAlert { .. } => "create_alert",
+            ControlAction::DeleteAlert { .. } => "delete_alert",
+            ControlAction::ListAlerts => "list_alerts",
+            ControlAction::ResumeSession { .. } => "resume_session",
+        }
+    }
+
+    fn validate(&self) -> Result<(), ControlError> {
+        match self {
+            ControlAction::Subscribe { symbol, intervals, .. } => {
+                validate_symbol(symbol)?;
+
+                match intervals {
+                    Some(v) if v.is_empty() => Err(ControlError::new(ErrorCode::MalformedRequest, "intervals is empty")),
+                    Some(v) if v.contains(&0) => Err(ControlError::new(ErrorCode::MalformedRequest, "interval 0 is invalid")),
+                    _ => Ok(()),
+                }
+            },
+            ControlAction::Unsubscribe { symbol }
+            | ControlAction::RemoveSymbol { symbol, .. }
+            | ControlAction::GetReference { symbol }
+            | ControlAction::GetSession { symbol } => validate_symbol(symbol),
+            ControlAction::QueryRange { symbol, interval, from, to, limit } => {
+                validate_symbol(symbol)?;
+
+                if *interval == 0 {
+                    return Err(ControlError::new(ErrorCode::MalformedRequest, "interval 0 is invalid"));
+                }
+
+                if from > to {
+                    return Err(ControlError::new(ErrorCode::MalformedRequest, "from is after to"));
+                }
+
+                match limit {
+                    Some(0) => Err(ControlError::new(ErrorCode::MalformedRequest, "limit must be greater than 0")),
+                    _ => Ok(()),
+                }
+            },
+            ControlAction::ListSymbols { prefix, contains, limit, .. } => {
+                for v in [prefix, contains].into_iter().flatten() {
+                    if v.chars().count() > MAX_SYMBOL_LEN {
+                        return Err(ControlError::new(ErrorCode::MalformedRequest, "search text is too long"));
+                    }
+                }
+
+                match limit {
+                    Some(0) => Err(ControlError::new(ErrorCode::MalformedRequest, "limit must be greater than 0")),
+                    _ => Ok(()),
+                }
+            },
+            ControlAction::SubscribeIndicator { symbol, interval, indicator }
+            | ControlAction::UnsubscribeIndicator { symbol, interval, indicator } => {
+                validate_symbol(symbol)?;
+
+                if *interval == 0 {
+                    return Err(ControlError::new(ErrorCode::MalformedRequest, "interval 0 is invalid"));
+                }
+
+                indicator.validate().map_err(|e| ControlError::new(ErrorCode::MalformedRequest, &e))
+            },
+            ControlAction::CreateBasket { name, symbols, weighting, .. } => {
+                for symbol in symbols.iter() {
+                    validate_symbol(symbol)?;
+                }
+
+                basket_spec(name, symbols, *weighting)
+                    .validate()
+                    .map_err(|e| ControlError::new(ErrorCode::MalformedRequest, &e))
+            },
+            ControlAction::DeleteBasket { name, .. } => match name.trim().is_empty() {
+                true => Err(ControlError::new(ErrorCode::MalformedRequest, "basket name is empty")),
+                false => Ok(()),
+            },
+            ControlAction::CreateAlert { rule } => {
+                validate_symbol(rule.symbol())?;
+
+                rule.validate().map_err(|e| ControlError::new(ErrorCode::MalformedRequest, &e))
+            },
+            ControlAction::ResumeSession { token } => match token.trim().is_empty() {
+                true => Err(ControlError::new(ErrorCode::MalformedRequest, "token is empty")),
+                false => Ok(()),
+            },
+            ControlAction::ListSubscriptions
+            | ControlAction::Ping
+            | ControlAction::ReloadReferenceData { .. }
+            | ControlAction::ListBaskets
+            | ControlAction::DeleteAlert { .. }
+            | ControlAction::ListAlerts => Ok(()),
+        }
+    }
+}
+
+/// Builds the filter of a subscribe request.
+pub fn bar_filter(intervals: &Option<Vec<u64>>, depth: &Option<usize>, session: bool) -> BarFilter {
+    BarFilter {
+        intervals: intervals.as_ref().map(|v| v.iter().map(|interval| *interval as u128).collect()),
+        depth: *depth,
+        session,
+    }
+}
+
+/// Builds the basket of a create_basket request.
+pub fn basket_spec(name: &str, symbols: &[String], weighting: BasketWeighting) -> BasketSpec {
+    BasketSpec {
+        name: name.to_owned(),
+        symbols: symbols.to_vec(),
+        weighting,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlRequest {
+    pub id: Value,
+    pub action: ControlAction,
+}
+
+/// The unversioned messages understood before the control protocol existed.
+/// They are never answered.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum LegacyRequest {
+    /// Replaces all subscriptions of the connection.
+    Stock(String),
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+impl LegacyRequest {
+    pub fn symbol(&self) -> &String {
+        match self {
+            LegacyRequest::Stock(v) | LegacyRequest::Subscribe(v) | LegacyRequest::Unsubscribe(v) => v,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    Control(ControlRequest),
+    Legacy(LegacyRequest),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    MalformedRequest,
+    UnsupportedVersion,
+    UnknownAction,
+    UnknownSymbol,
+    NotSubscribed,
+    QuotaExceeded,
+    UnknownInterval,
+    Forbidden,
+    ReloadFailed,
+    AlreadyExists,
+    UnknownAlert,
+    UnknownSession,
+    SnapshotTooLarge,
+    UnknownBasket,
+    PeriodTooLong,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::MalformedRequest => "malformed_request",
+            ErrorCode::UnsupportedVersion => "unsupported_version",
+            ErrorCode::UnknownAction => "unknown_action",
+            ErrorCode::UnknownSymbol => "unknown_symbol",
+            ErrorCode::NotSubscribed => "not_subscribed",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::UnknownInterval => "unknown_interval",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::ReloadFailed => "reload_failed",
+            ErrorCode::AlreadyExists => "already_exists",
+            ErrorCode::UnknownAlert => "unknown_alert",
+            ErrorCode::UnknownSession => "unknown_session",
+            ErrorCode::SnapshotTooLarge => "snapshot_too_large",
+            ErrorCode::UnknownBasket => "unknown_basket",
+            ErrorCode::PeriodTooLong => "period_too_long",
+            ErrorCode::Internal => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ControlError {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        ControlError {
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+/// A message that couldn't be decoded, with whatever could be recovered for the reply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError {
+    pub id: Option<Value>,
+    pub action: Option<String>,
+    pub error: ControlError,
+}
+
+impl DecodeError {
+    fn new(id: Option<Value>, action: Option<String>, code: ErrorCode, message: &str) -> Self {
+        DecodeError {
+            id,
+            action,
+            error: ControlError::new(code, message),
+        }
+    }
+}
+
+/// Decodes a text frame sent by a client.
+///
+/// Objects with an `action` or `v` field are control requests; anything else must be one of
+/// the [`LegacyRequest`] shapes.
+pub fn decode_message(message_json: &str) -> Result<ClientMessage, DecodeError> {
+    let object = match serde_json::from_str::<Value>(message_json) {
+        Ok(Value::Object(v)) => v,
+        Ok(_) => return Err(DecodeError::new(None, None, ErrorCode::MalformedRequest, "expected a JSON object")),
+        Err(e) => return Err(DecodeError::new(None, None, ErrorCode::MalformedRequest, &e.to_string())),
+    };
+
+    if object.contains_key("action") || object.contains_key("v") {
+        return decode_request(object).map(ClientMessage::Control);
+    }
+
+    let legacy_request = serde_json::from_value::<LegacyRequest>(Value::Object(object))
+        .map_err(|e| DecodeError::new(None, None, ErrorCode::MalformedRequest, &e.to_string()))?;
+
+    validate_symbol(legacy_request.symbol())
+        .map_err(|e| DecodeError { id: None, action: None, error: e })?;
+
+    Ok(ClientMessage::Legacy(legacy_request))
+}
+
+fn decode_request(object: Map<String, Value>) -> Result<ControlRequest, DecodeError> {
+    let id = object.get("id").cloned();
+    let action = object.get("action").and_then(|v| v.as_str()).map(|v| v.to_owned());
+
+    let fail = |code: ErrorCode, message: &str| Err(DecodeError::new(id.clone(), action.clone(), code, message));
+
+    let request_id = match &id {
+        Some(Value::String(v)) if !v.is_empty() => Value::String(v.clone()),
+        Some(Value::Number(v)) if v.is_u64() || v.is_i64() => Value::Number(v.clone()),
+        Some(_) => return fail(ErrorCode::MalformedRequest, "field \"id\" must be a non-empty string or an integer"),
+        None => return fail(ErrorCode::MalformedRequest, "missing field \"id\""),
+    };
+
+    match object.get("v") {
+        Some(v) if v.as_u64() == Some(PROTOCOL_VERSION) => (),
+        Some(Value::Number(_)) => return fail(ErrorCode::UnsupportedVersion, "only protocol version 1 is supported"),
+        Some(_) => return fail(ErrorCode::MalformedRequest, "field \"v\" must be an integer"),
+        None => return fail(ErrorCode::MalformedRequest, "missing field \"v\""),
+    };
+
+    match object.get("action") {
+        Some(Value::String(v)) if ACTION_NAMES.contains(&v.as_str()) => (),
+        Some(Value::String(_)) => return fail(ErrorCode::UnknownAction, "unknown action"),
+        Some(_) => return fail(ErrorCode::MalformedRequest, "field \"action\" must be a string"),
+        None => return fail(ErrorCode::MalformedRequest, "missing field \"action\""),
+    };
+
+    let control_action = match serde_json::from_value::<ControlAction>(Value::Object(object.clone())) {
+        Ok(v) => v,
+        Err(e) => return fail(ErrorCode::MalformedRequest, &e.to_string()),
+    };
+
+    if let Err(e) = control_action.validate() {
+        return Err(DecodeError { id, action, error: e });
+    }
+
+    Ok(ControlRequest {
+        id: request_id,
+        action: control_action,
+    })
+}
+
+/// Checks that a symbol is non-empty, at most [`MAX_SYMBOL_LEN`] characters long and free of
+/// control characters. Spaces and punctuation are allowed.
+pub fn validate_symbol(symbol: &str) -> Result<(), ControlError> {
+    if symbol.trim().is_empty() {
+        return Err(ControlError::new(ErrorCode::MalformedRequest, "symbol is empty"));
+    }
+
+    if symbol.chars().count() > MAX_SYMBOL_LEN {
+        return Err(ControlError::new(ErrorCode::MalformedRequest, "symbol is too long"));
+    }
+
+    if symbol.chars().any(|c| c.is_control()) {
+        return Err(ControlError::new(ErrorCode::MalformedRequest, "symbol contains control characters"));
+    }
+
+    Ok(())
+}
+
+pub fn ok_response(id: &Value, action: &str, result: Value) -> String {
+    json!({
+        "v": PROTOCOL_VERSION,
+        "type": "response",
+        "id": id,
+        "action": action,
+        "status": "ok",
+        "result": result,
+    }).to_string()
+}
+
+pub fn error_response(id: Option<&Value>, action: Option<&str>, error: &ControlError) -> String {
+    json!({
+        "v": PROTOCOL_VERSION,
+        "type": "response",
+        "id": id,
+        "action": action,
+        "status": "error",
+        "error": {
+            "code": error.code.as_str(),
+            "message": error.message,
+        },
+    }).to_string()
+}
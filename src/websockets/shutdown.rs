@@ -0,0 +1,103 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::watch,
+    task::{self, JoinHandle},
+};
+
+/// Close reason sent to websocket peers when the server stops.
+pub const SHUTDOWN_REASON: &str = "Server shutting down";
+
+/// Blocking work run once every task is joined.
+type JoinWork = Box<dyn FnOnce() + Send>;
+
+/// Signals every server task to stop and keeps track of them so they can be joined.
+/// Work registered with [`ShutdownHandle::on_join`] runs once they are.
+///
+/// Clones share the same state.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stopped: Arc<watch::Sender<bool>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    on_join: Arc<Mutex<Vec<JoinWork>>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        ShutdownHandle {
+            stopped: Arc::new(watch::Sender::new(false)),
+            tasks: Arc::new(Mutex::new(Vec::new())),
+            on_join: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Requests a shutdown. Returns immediately; may be called from any thread.
+    pub fn shutdown(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.stopped.borrow()
+    }
+
+    /// Spawns a task on the current runtime that is joined by [`ShutdownHandle::join_all`].
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().unwrap();
+
+        tasks.retain(|v| !v.is_finished());
+        tasks.push(tokio::spawn(future));
+    }
+
+    /// Registers blocking work, such as joining a thread, that [`ShutdownHandle::join_all`]
+    /// waits for after every task.
+    pub fn on_join<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.on_join.lock().unwrap().push(Box::new(f));
+    }
+
+    /// Resolves once a shutdown is requested.
+    pub async fn wait(&self) {
+        let mut stopped = self.stopped.subscribe();
+        let _ = stopped.wait_for(|v| *v).await;
+    }
+
+    /// Awaits every task spawned through this handle, including ones spawned while joining,
+    /// then the work registered with [`ShutdownHandle::on_join`].
+    pub async fn join_all(&self) {
+        loop {
+            let tasks: Vec<JoinHandle<()>> = self.tasks.lock().unwrap().drain(..).collect();
+
+            if tasks.is_empty() {
+                break;
+            }
+
+            for task in tasks.into_iter() {
+                if task.await.is_err() {
+                    println!("A server task panicked during shutdown");
+                }
+            }
+        }
+
+        let on_join: Vec<JoinWork> = self.on_join.lock().unwrap().drain(..).collect();
+
+        for f in on_join.into_iter() {
+            if task::spawn_blocking(f).await.is_err() {
+                println!("Shutdown work panicked");
+            }
+        }
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        ShutdownHandle::new()
+    }
+}
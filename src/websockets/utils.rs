@@ -1,116 +1,1075 @@
 use std::{
-    collections::{HashSet, HashMap},
+    collections::{BTreeMap, HashSet, HashMap, VecDeque},
     sync::{Arc, RwLock},
+    time::SystemTime,
 };
 
-use crate::value_store::{StockInformationCacheInterface, OHLCModel};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
 
+use crate::{
+    config::ServerConfig,
+    value_store::top_movers::TOP_MOVERS_TOPIC,
+    value_store::{Alert, AlertRule, BarEvent, BarFilter, BarFormat, BasketSpec, EvictionReason, IndicatorSpec, IngestReport, ReferenceData, StockInformationCacheInterface, OHLCModel, ParseError},
+    websockets::protocol::{ErrorCode, PROTOCOL_VERSION},
+};
+
+/// Whether a subscription already receives bars or waits for the first bar of its stock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionState {
+    Active,
+    Pending,
+}
+
+impl SubscriptionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionState::Active => "active",
+            SubscriptionState::Pending => "pending",
+        }
+    }
+}
+
+/// One indicator of one stock and interval, see [`ConnectionService::add_indicator_subscription`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct IndicatorKey {
+    stock_name: String,
+    stock_interval: u128,
+    indicator: String,
+}
+
+struct IndicatorSubscribers {
+    spec: IndicatorSpec,
+    ids: HashSet<usize>,
+}
+
+/// Alerts of one client, kept across its reconnects, see [`ConnectionService::add_alert`].
+struct AlertSession {
+    connection: Option<usize>,
+    alerts: BTreeMap<u64, Alert>,
+    next_alert_id: u64,
+    /// Alerts fired while no connection was attached, oldest first.
+    undelivered: VecDeque<String>,
+    detached_at: u128,
+}
+
+/// Shared state between the feed client and all websocket connections:
+/// the bar cache, per connection event channels and stock subscriptions.
+///
+/// Clones share the same state.
 #[derive(Clone)]
 pub struct ConnectionService {
     stock_cache: StockInformationCacheInterface,
+    reference_data: ReferenceData,
+    queue_capacity: usize,
+    max_subscriptions: usize,
+    max_query_bars: usize,
+    max_list_symbols: usize,
+    max_baskets: usize,
+    max_alerts: usize,
+    alert_session_ttl_ms: u128,
+    bar_format: BarFormat,
+    admin_token: Option<String>,
+    max_idle_ms: Option<u128>,
+    memory_budget: Option<usize>,
     current_id: Arc<RwLock<usize>>,
-    conn_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-    subscr_map: Arc<RwLock<HashMap::<String, HashSet<usize>>>>,
+    conn_queue: Arc<RwLock<HashMap::<usize, mpsc::Sender<String>>>>,
+    subscr_map: Arc<RwLock<HashMap::<String, HashMap<usize, BarFilter>>>>,
+    pending_subscr: Arc<RwLock<HashMap::<String, HashMap<usize, BarFilter>>>>,
+    conn_subscr: Arc<RwLock<HashMap::<usize, HashSet<String>>>>,
+    indicator_subscr: Arc<RwLock<HashMap::<IndicatorKey, IndicatorSubscribers>>>,
+    conn_indicators: Arc<RwLock<HashMap::<usize, HashSet<IndicatorKey>>>>,
+    conn_sessions: Arc<RwLock<HashMap::<usize, String>>>,
+    alert_sessions: Arc<RwLock<HashMap::<String, AlertSession>>>,
 }
 
 impl ConnectionService {
-    pub fn new() -> Self {
-        ConnectionService {
-            stock_cache: StockInformationCacheInterface::new(),
+    /// Creates a service with an empty cache sized from `config`.
+    pub fn new(config: &ServerConfig) -> Self {
+        let connection_service = ConnectionService {
+            stock_cache: StockInformationCacheInterface::new(
+                config.cache.interval_retention(),
+                config.cache.data_history_size,
+                config.cache.aggregate,
+            ),
+            reference_data: ReferenceData::new(),
+            queue_capacity: config.server.queue_capacity,
+            max_subscriptions: config.server.max_subscriptions,
+            max_query_bars: config.server.max_query_bars,
+            max_list_symbols: config.server.max_list_symbols,
+            max_baskets: config.server.max_baskets,
+            max_alerts: config.server.max_alerts,
+            alert_session_ttl_ms: config.server.alert_session_ttl_secs as u128 * 1000,
+            bar_format: config.server.bar_format,
+            admin_token: config.server.admin_token.clone(),
+            max_idle_ms: config.cache.max_idle_ms(),
+            memory_budget: config.cache.memory_budget(),
             current_id: Arc::new(RwLock::new(0)),
             conn_queue: Arc::new(RwLock::new(HashMap::new())),
             subscr_map: Arc::new(RwLock::new(HashMap::new())),
+            pending_subscr: Arc::new(RwLock::new(HashMap::new())),
+            conn_subscr: Arc::new(RwLock::new(HashMap::new())),
+            indicator_subscr: Arc::new(RwLock::new(HashMap::new())),
+            conn_indicators: Arc::new(RwLock::new(HashMap::new())),
+            conn_sessions: Arc::new(RwLock::new(HashMap::new())),
+            alert_sessions: Arc::new(RwLock::new(HashMap::new())),
+        };
+
+        connection_service.stock_cache.set_session_boundary(config.cache.session_start);
+        connection_service.stock_cache.set_movers_window(config.top_movers.movers_window(&config.cache));
+
+        for spec in config.baskets.iter() {
+            connection_service.stock_cache.add_basket(spec.clone());
         }
+
+        connection_service
     }
 
-    pub fn add_ohlc_json(&self, json_data: String) -> OHLCModel {
+    /// Returns a handle to the bar cache.
+    pub fn stock_cache(&self) -> StockInformationCacheInterface {
+        self.stock_cache.clone()
+    }
+
+    /// Returns a handle to the symbol reference data.
+    pub fn reference_data(&self) -> ReferenceData {
+        self.reference_data.clone()
+    }
+
+    /// Returns the shape bars are serialized in for clients.
+    pub fn bar_format(&self) -> BarFormat {
+        self.bar_format
+    }
+
+    pub fn add_ohlc_json(&self, json_data: String) -> IngestReport {
         self.stock_cache.add_json(json_data)
     }
 
+    /// Stores `ohlc_model` and queues it, and any coarser bar it completed, for every
+    /// subscriber of its stock. Pending subscriptions to the stock become live and alerts
+    /// on the stored bars are checked.
+    pub fn publish_ohlc(&self, ohlc_model: OHLCModel) -> Result<(), ParseError> {
+        let bar_events = self.stock_cache.add_ohlc(ohlc_model)?;
+
+        for bar_event in bar_events.iter() {
+            let ids_to_update = self.get_bar_subscribers(bar_event.ohlc_model());
+
+            self.add_events(ids_to_update, bar_event.to_json(self.bar_format));
+        }
+
+        self.publish_indicators(&bar_events);
+        self.publish_sessions(&bar_events);
+        self.check_alerts(&bar_events);
+        self.activate_pending(&bar_events);
+
+        Ok(())
+    }
+
+    /// Stores every valid upstream bar line in `json_data` and queues each stored bar
+    /// for its subscribers. Invalid lines are skipped and reported. Pending subscriptions
+    /// to the stocks of the stored bars become live and alerts on them are checked.
+    pub fn publish_ohlc_json(&self, json_data: String) -> IngestReport {
+        let ingest_report = self.add_ohlc_json(json_data);
+
+        for bar_event in ingest_report.accepted.iter() {
+            let ids_to_update = self.get_bar_subscribers(bar_event.ohlc_model());
+
+            self.add_events(ids_to_update, bar_event.to_json(self.bar_format));
+        }
+
+        self.publish_indicators(&ingest_report.accepted);
+        self.publish_sessions(&ingest_report.accepted);
+        self.check_alerts(&ingest_report.accepted);
+        self.activate_pending(&ingest_report.accepted);
+
+        ingest_report
+    }
+
     pub fn get_subscribers(&self, stock_name: &String) -> HashSet<usize> {
         match self.subscr_map.read().unwrap().get(stock_name) {
-            Some(v) => v.clone(),
+            Some(v) => v.keys().cloned().collect(),
+            None => HashSet::new(),
+        }
+    }
+
+    /// Returns the subscribers of the bar's stock whose filter accepts its interval.
+    pub fn get_bar_subscribers(&self, ohlc_model: &OHLCModel) -> HashSet<usize> {
+        match self.subscr_map.read().unwrap().get(&ohlc_model.stock_name) {
+            Some(v) => v
+                .iter()
+                .filter(|(_, bar_filter)| bar_filter.accepts(ohlc_model.stock_interval))
+                .map(|(id, _)| *id)
+                .collect(),
             None => HashSet::new(),
         }
     }
 
+    /// Pushes `event` to every connection in `ids_to_update`. Events for a connection
+    /// whose channel is full are dropped.
     pub fn add_events(&self, ids_to_update: HashSet<usize>, event: String) {
-        let mut connection_vec = self.conn_queue.write().unwrap();
+        let connection_vec = self.conn_queue.read().unwrap();
 
         for id in ids_to_update.iter() {
-            match connection_vec.get_mut(id) {
-                Some(v) => {
-                    if v.len() < 1000 {
-                        v.push(event.clone());
-                    }
-                },
+            if let Some(v) = connection_vec.get(id) {
+                let _ = v.try_send(event.clone());
+            }
+        }
+    }
+
+    /// Returns the stocks connection `id` is subscribed to, sorted.
+    pub fn get_subscriptions(&self, id: usize) -> Vec<String> {
+        let mut subscriptions: Vec<String> = match self.conn_subscr.read().unwrap().get(&id) {
+            Some(v) => v.iter().cloned().collect(),
+            None => Vec::new(),
+        };
+
+        subscriptions.sort();
+
+        subscriptions
+    }
+
+    /// Unsubscribes connection `id` from `stock_name`. Returns false if it wasn't subscribed.
+    pub fn remove_stock_subscription(&self, id: usize, stock_name: &String) -> bool {
+        if stock_name.is_empty() {
+            return false;
+        }
+
+        let mut removed = false;
+
+        for subscr_map in [&self.subscr_map, &self.pending_subscr] {
+            let mut subscr_map = subscr_map.write().unwrap();
+
+            if let Some(v) = subscr_map.get_mut(stock_name) {
+                removed |= v.remove(&id).is_some();
+
+                if v.is_empty() {
+                    subscr_map.remove(stock_name);
+                }
+            }
+        }
+
+        if let Some(v) = self.conn_subscr.write().unwrap().get_mut(&id) {
+            v.remove(stock_name);
+        }
+
+        removed
+    }
+
+    /// Removes every subscription of connection `id`.
+    pub fn remove_all_subscriptions(&self, id: usize) {
+        for stock_name in self.get_subscriptions(id).iter() {
+            self.remove_stock_subscription(id, stock_name);
+        }
+    }
+
+    /// Subscribes connection `id` to the bars of `stock_name` selected by `bar_filter` and
+    /// queues the matching snapshot. In both bar formats the snapshot starts with
+    /// `{"v": 1, "type": "reference", "symbol": ..., "reference": {...}}` if the stock has
+    /// reference data. Subscribing twice with the same filter does nothing,
+    /// with another filter it replaces the filter and queues a new snapshot. If the snapshot
+    /// doesn't fit in the connection queue nothing is queued and the subscription fails with
+    /// [`ErrorCode::SnapshotTooLarge`].
+    ///
+    /// A subscription to a stock that isn't cached yet, or to a basket that doesn't exist
+    /// yet, is held as pending. When the first bar of the stock arrives or the basket is
+    /// created, the connection gets
+    /// `{"v": 1, "type": "subscription", "symbol": ..., "state": "active"}` followed by the
+    /// snapshot, and live bars from then on.
+    pub fn add_stock_subscription(&self, id: usize, stock_name: &String, bar_filter: &BarFilter) -> Result<SubscriptionState, ErrorCode> {
+        if let Some(v) = &bar_filter.intervals {
+            let intervals = self.stock_cache.get_intervals();
+
+            if v.iter().any(|interval| !intervals.contains(interval)) {
+                return Err(ErrorCode::UnknownInterval);
+            }
+        }
+
+        let mut subscr_map = self.subscr_map.write().unwrap();
+        let mut pending_subscr = self.pending_subscr.write().unwrap();
+
+        let state = match self.stock_cache.has_key(stock_name) || stock_name == "DataFeed"
+            || stock_name == TOP_MOVERS_TOPIC
+            || self.stock_cache.has_basket_topic(stock_name)
+        {
+            true => SubscriptionState::Active,
+            false => SubscriptionState::Pending,
+        };
+
+        let current = subscr_map
+            .get(stock_name)
+            .or(pending_subscr.get(stock_name))
+            .and_then(|v| v.get(&id));
+
+        match current {
+            Some(v) if v == bar_filter => return Ok(state),
+            Some(_) => (),
+            None => if self.conn_subscr.read().unwrap().get(&id).is_some_and(|v| v.len() >= self.max_subscriptions) {
+                return Err(ErrorCode::QuotaExceeded);
+            },
+        };
+
+        match state {
+            SubscriptionState::Active => {
+                if let Some(v) = self.conn_queue.read().unwrap().get(&id) {
+                    send_all(v, self.snapshot_events(stock_name, bar_filter))?;
+                }
+
+                subscr_map.entry(stock_name.clone()).or_default().insert(id, bar_filter.clone());
+            },
+            SubscriptionState::Pending => {
+                println!("Holding subscription to {:?} until its first bar", stock_name);
+
+                pending_subscr.entry(stock_name.clone()).or_default().insert(id, bar_filter.clone());
+            },
+        };
+
+        self.conn_subscr.write().unwrap().entry(id).or_default().insert(stock_name.clone());
+
+        Ok(state)
+    }
+
+    fn session_event(&self, stock_name: &String) -> Option<String> {
+        let session_info = self.stock_cache.get_session(stock_name)?;
+
+        Some(json!({
+            "v": PROTOCOL_VERSION,
+            "type": "session",
+            "symbol": stock_name,
+            "session": session_info,
+        }).to_string())
+    }
+
+    /// Queues the session averages of the stocks whose averages `bar_events` changed for
+    /// the subscribers that asked for them.
+    fn publish_sessions(&self, bar_events: &[BarEvent]) {
+        let mut stock_names: Vec<&String> = Vec::new();
+
+        for bar_event in bar_events.iter() {
+            let ohlc_model = bar_event.ohlc_model();
+
+            if !stock_names.contains(&&ohlc_model.stock_name) {
+                stock_names.push(&ohlc_model.stock_name);
+            }
+        }
+
+        for stock_name in stock_names.into_iter() {
+            let ids_to_update: HashSet<usize> = match self.subscr_map.read().unwrap().get(stock_name) {
+                Some(v) => v.iter().filter(|(_, bar_filter)| bar_filter.session).map(|(id, _)| *id).collect(),
                 None => continue,
             };
+
+            let counted = self.stock_cache.get_session(stock_name).is_some_and(|session_info| {
+                bar_events.iter().any(|v| {
+                    &v.ohlc_model().stock_name == stock_name && v.ohlc_model().stock_interval == session_info.interval
+                })
+            });
+
+            if ids_to_update.is_empty() || !counted {
+                continue;
+            }
+
+            if let Some(event) = self.session_event(stock_name) {
+                self.add_events(ids_to_update, event);
+            }
         }
     }
 
-    pub fn read_events(&self, id: &usize) -> Vec<String> {
-        match self.conn_queue.write().unwrap().get_mut(id) {
-            Some(v) => {
-                let conn_vec = v.clone();
-                *v = Vec::<String>::new();
+    fn snapshot_events(&self, stock_name: &String, bar_filter: &BarFilter) -> Vec<String> {
+        let mut events = Vec::new();
 
-                conn_vec
-            },
-            None => Vec::new(),
+        if let Some(symbol_info) = self.reference_data.get(stock_name) {
+            events.push(json!({
+                "v": PROTOCOL_VERSION,
+                "type": "reference",
+                "symbol": stock_name,
+                "reference": symbol_info,
+            }).to_string());
         }
 
+        if let Some(event) = self.session_event(stock_name).filter(|_| bar_filter.session) {
+            events.push(event);
+        }
+
+        events.extend(self.stock_cache.get_vec_of_stock(stock_name, self.bar_format, bar_filter));
+
+        events
     }
 
-    pub fn remove_stock_subscription(&self, id: usize, stock_name: &String) {
-        if stock_name.len() == 0 {
+    /// Turns the pending subscriptions of the stocks of `bar_events` into live ones and
+    /// queues their status message and snapshot.
+    fn activate_pending(&self, bar_events: &[BarEvent]) {
+        let mut subscr_map = self.subscr_map.write().unwrap();
+        let mut pending_subscr = self.pending_subscr.write().unwrap();
+
+        if pending_subscr.is_empty() {
             return;
         }
 
-        match self.subscr_map.write().unwrap().get_mut(stock_name) {
-            Some(v) => { v.remove(&id); },
-            None => println!("Couldn't find key {:?}", stock_name),
-        };
+        for bar_event in bar_events.iter() {
+            if let Some(v) = pending_subscr.remove(&bar_event.ohlc_model().stock_name) {
+                self.activate_subscribers(&mut subscr_map, &bar_event.ohlc_model().stock_name, v);
+            }
+        }
     }
 
-    pub fn add_stock_subscription(&self, id: usize, stock_name: &String) {
-        if !self.stock_cache.has_key(stock_name) && stock_name != "DataFeed" {
-            println!("Couldn't find key stock_name {:?}", stock_name);
+    fn activate_subscribers(
+        &self,
+        subscr_map: &mut HashMap<String, HashMap<usize, BarFilter>>,
+        stock_name: &String,
+        subscribers: HashMap<usize, BarFilter>,
+    ) {
+        let conn_queue = self.conn_queue.read().unwrap();
 
-            return;
+        println!("Activating {} pending subscriptions to {:?}", subscribers.len(), stock_name);
+
+        for (id, bar_filter) in subscribers.into_iter() {
+            if let Some(v) = conn_queue.get(&id) {
+                let mut events = vec![json!({
+                    "v": PROTOCOL_VERSION,
+                    "type": "subscription",
+                    "symbol": stock_name,
+                    "state": SubscriptionState::Active.as_str(),
+                }).to_string()];
+
+                events.extend(self.snapshot_events(stock_name, &bar_filter));
+
+                if let Err(code) = send_all(v, events) {
+                    println!("Dropping subscription of {} to {:?}: snapshot doesn't fit its queue", id, stock_name);
+
+                    let _ = v.try_send(json!({
+                        "v": PROTOCOL_VERSION,
+                        "type": "subscription",
+                        "symbol": stock_name,
+                        "state": "failed",
+                        "error": { "code": code.as_str() },
+                    }).to_string());
+
+                    if let Some(v) = self.conn_subscr.write().unwrap().get_mut(&id) {
+                        v.remove(stock_name);
+                    }
+
+                    continue;
+                }
+            }
+
+            subscr_map.entry(stock_name.clone()).or_default().insert(id, bar_filter);
         }
+    }
 
+    /// Creates a basket, see [`crate::value_store::Basket`], and activates the pending
+    /// subscriptions to its topic. Creating a basket again with the same symbols and
+    /// weighting does nothing.
+    pub fn add_basket(&self, spec: BasketSpec) -> Result<(), ErrorCode> {
         let mut subscr_map = self.subscr_map.write().unwrap();
+        let mut pending_subscr = self.pending_subscr.write().unwrap();
 
-        match subscr_map.get_mut(stock_name) {
-            Some(v) => {
-                v.insert(id);
+        match self.stock_cache.get_basket_spec(&spec.name) {
+            Some(v) if v == spec => return Ok(()),
+            Some(_) => return Err(ErrorCode::AlreadyExists),
+            None => if self.stock_cache.get_baskets().len() >= self.max_baskets {
+                return Err(ErrorCode::QuotaExceeded);
             },
-            None => {
-                subscr_map.insert(stock_name.clone(), HashSet::from([id]));
+        };
+
+        let topic = spec.topic();
+
+        println!("Created basket {:?} of {} symbols", spec.name, spec.symbols.len());
+        self.stock_cache.add_basket(spec);
+
+        if let Some(v) = pending_subscr.remove(&topic) {
+            self.activate_subscribers(&mut subscr_map, &topic, v);
+        }
+
+        Ok(())
+    }
+
+    /// Drops the basket `name` and unsubscribes its subscribers, telling them why.
+    /// Returns false if it didn't exist.
+    pub fn remove_basket(&self, name: &String) -> bool {
+        let topic = match self.stock_cache.get_basket_spec(name) {
+            Some(v) => v.topic(),
+            None => return false,
+        };
+
+        if !self.stock_cache.remove_basket(name) {
+            return false;
+        }
+
+        println!("Removed basket {:?}", name);
+        self.notify_evicted(&topic, EvictionReason::Removed);
+
+        true
+    }
+
+    /// Returns the bars of `stock_name` for `stock_interval` between `from` and `to`, both
+    /// inclusive, oldest first. At most `limit` bars, capped by `server.max_query_bars`, are
+    /// returned; the latest ones are kept.
+    pub fn query_range(
+        &self,
+        stock_name: &String,
+        stock_interval: u128,
+        from: u128,
+        to: u128,
+        limit: Option<usize>,
+    ) -> Result<Vec<OHLCModel>, ErrorCode> {
+        if !self.stock_cache.has_key(stock_name) {
+            return Err(ErrorCode::UnknownSymbol);
+        }
+
+        if !self.stock_cache.get_intervals().contains(&stock_interval) {
+            return Err(ErrorCode::UnknownInterval);
+        }
+
+        let limit = limit.unwrap_or(self.max_query_bars).min(self.max_query_bars);
+
+        Ok(self.stock_cache.query_range(stock_name, stock_interval, from, to, limit))
+    }
+
+    /// Returns the total number of stocks matching `prefix` and `contains`, see
+    /// [`StockInformationCacheInterface::list_symbols`], and the page of them starting at
+    /// `offset`. At most `limit` stocks, capped by `server.max_list_symbols`, are returned.
+    pub fn list_symbols(
+        &self,
+        prefix: Option<&str>,
+        contains: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> (usize, Vec<(String, u128)>) {
+        let symbols = self.stock_cache.list_symbols(prefix, contains);
+        let limit = limit.unwrap_or(self.max_list_symbols).min(self.max_list_symbols);
+
+        (symbols.len(), symbols.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// Subscribes connection `id` to `spec` computed over the bars of `stock_name` for
+    /// `stock_interval` and queues its snapshot. From then on every new or corrected bar
+    /// queues the indicator's point for it, once past the warm-up. Subscribing twice does
+    /// nothing. An indicator with a period longer than the bars kept for `stock_interval` is
+    /// refused with [`ErrorCode::PeriodTooLong`]. If the snapshot doesn't fit in the
+    /// connection queue nothing is queued and the subscription fails with
+    /// [`ErrorCode::SnapshotTooLarge`].
+    pub fn add_indicator_subscription(
+        &self,
+        id: usize,
+        stock_name: &String,
+        stock_interval: u128,
+        spec: &IndicatorSpec,
+    ) -> Result<(), ErrorCode> {
+        if !self.stock_cache.has_key(stock_name) {
+            return Err(ErrorCode::UnknownSymbol);
+        }
+
+        match self.stock_cache.get_retention(stock_interval) {
+            Some(v) if spec.longest_period() > v => return Err(ErrorCode::PeriodTooLong),
+            Some(_) => (),
+            None => return Err(ErrorCode::UnknownInterval),
+        };
+
+        let key = IndicatorKey {
+            stock_name: stock_name.clone(),
+            stock_interval,
+            indicator: spec.key(),
+        };
+
+        let mut indicator_subscr = self.indicator_subscr.write().unwrap();
+        let mut conn_indicators = self.conn_indicators.write().unwrap();
+        let subscriptions = conn_indicators.entry(id).or_default();
+
+        if subscriptions.contains(&key) {
+            return Ok(());
+        }
+
+        if subscriptions.len() >= self.max_subscriptions {
+            return Err(ErrorCode::QuotaExceeded);
+        }
+
+        let computed = indicator_subscr.contains_key(&key);
+
+        if !self.stock_cache.add_indicator(stock_name, stock_interval, spec) {
+            return Err(ErrorCode::UnknownSymbol);
+        }
+
+        if let Some(v) = self.conn_queue.read().unwrap().get(&id) {
+            let points = self.stock_cache.get_indicator_points(stock_name, stock_interval, &key.indicator);
+            let snapshot = json!({
+                "v": PROTOCOL_VERSION,
+                "type": "indicator_snapshot",
+                "symbol": stock_name,
+                "interval": stock_interval as u64,
+                "indicator": key.indicator,
+                "points": points.iter().map(|v| spec.point_json(v)).collect::<Vec<Value>>(),
+            }).to_string();
+
+            if let Err(e) = send_all(v, vec![snapshot]) {
+                if !computed {
+                    self.stock_cache.remove_indicator(stock_name, stock_interval, &key.indicator);
+                }
+
+                return Err(e);
             }
+        }
+
+        subscriptions.insert(key.clone());
+        indicator_subscr
+            .entry(key.clone())
+            .or_insert_with(|| IndicatorSubscribers { spec: spec.clone(), ids: HashSet::new() })
+            .ids
+            .insert(id);
+
+        Ok(())
+    }
+
+    /// Unsubscribes connection `id` from an indicator. The indicator stops being computed
+    /// once nobody is subscribed to it. Returns false if `id` wasn't subscribed.
+    pub fn remove_indicator_subscription(&self, id: usize, stock_name: &str, stock_interval: u128, spec: &IndicatorSpec) -> bool {
+        let key = IndicatorKey {
+            stock_name: stock_name.to_owned(),
+            stock_interval,
+            indicator: spec.key(),
         };
 
-        self.conn_queue.write().unwrap().insert(id, self.stock_cache.get_vec_of_stock(stock_name));
+        self.remove_indicator_key(id, &key)
+    }
+
+    fn remove_indicator_key(&self, id: usize, key: &IndicatorKey) -> bool {
+        let mut indicator_subscr = self.indicator_subscr.write().unwrap();
+
+        if let Some(v) = self.conn_indicators.write().unwrap().get_mut(&id) {
+            v.remove(key);
+        }
+
+        let removed = match indicator_subscr.get_mut(key) {
+            Some(v) => v.ids.remove(&id),
+            None => false,
+        };
+
+        if indicator_subscr.get(key).is_some_and(|v| v.ids.is_empty()) {
+            indicator_subscr.remove(key);
+            self.stock_cache.remove_indicator(&key.stock_name, key.stock_interval, &key.indicator);
+        }
+
+        removed
+    }
+
+    /// Returns the indicators connection `id` is subscribed to as stock, interval and
+    /// [`IndicatorSpec::key`], sorted.
+    pub fn get_indicator_subscriptions(&self, id: usize) -> Vec<(String, u128, String)> {
+        let mut subscriptions: Vec<(String, u128, String)> = match self.conn_indicators.read().unwrap().get(&id) {
+            Some(v) => v.iter().map(|v| (v.stock_name.clone(), v.stock_interval, v.indicator.clone())).collect(),
+            None => Vec::new(),
+        };
+
+        subscriptions.sort();
+
+        subscriptions
     }
 
-    pub fn add_subscriber(&self) -> usize {
+    /// Queues the indicator points of the bars of `bar_events` for their subscribers.
+    fn publish_indicators(&self, bar_events: &[BarEvent]) {
+        let indicator_subscr = self.indicator_subscr.read().unwrap();
+
+        if indicator_subscr.is_empty() {
+            return;
+        }
+
+        for bar_event in bar_events.iter() {
+            let ohlc_model = bar_event.ohlc_model();
+
+            for (key, subscribers) in indicator_subscr.iter() {
+                if key.stock_name != ohlc_model.stock_name || key.stock_interval != ohlc_model.stock_interval {
+                    continue;
+                }
+
+                let point = match self.stock_cache.get_indicator_point(&key.stock_name, key.stock_interval, &key.indicator, ohlc_model.timestamp) {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                let event = json!({
+                    "v": PROTOCOL_VERSION,
+                    "type": "indicator",
+                    "symbol": key.stock_name,
+                    "interval": key.stock_interval as u64,
+                    "indicator": key.indicator,
+                    "point": subscribers.spec.point_json(&point),
+                }).to_string();
+
+                self.add_events(subscribers.ids.clone(), event);
+            }
+        }
+    }
+
+    /// Registers a connection. Events for it arrive on the returned channel.
+    pub fn add_subscriber(&self) -> (usize, mpsc::Receiver<String>) {
         let mut conn_queue = self.conn_queue.write().unwrap();
+        let (sender, receiver) = mpsc::channel(self.queue_capacity);
 
         let mut current_id = self.current_id.write().unwrap();
         *current_id += 1;
-        conn_queue.insert(*current_id-1, Vec::new());
+        conn_queue.insert(*current_id-1, sender);
 
-        *current_id-1
+        (*current_id-1, receiver)
     }
 
+    /// Unregisters a connection and drops all of its subscriptions. Its alert session is
+    /// kept for `server.alert_session_ttl_secs`, see [`ConnectionService::resume_session`].
     pub fn remove_subscriber(&self, id: usize) {
+        self.remove_all_subscriptions(id);
+        self.detach_session(id, now_ms());
+
+        let indicators: Vec<IndicatorKey> = match self.conn_indicators.write().unwrap().remove(&id) {
+            Some(v) => v.into_iter().collect(),
+            None => Vec::new(),
+        };
+
+        for key in indicators.iter() {
+            self.remove_indicator_key(id, key);
+        }
+
+        self.conn_subscr.write().unwrap().remove(&id);
         self.conn_queue.write().unwrap().remove(&id);
     }
 
+    /// Registers `rule` for connection `id` and returns the token of its alert session and
+    /// the id of the alert. The first alert of a connection opens the session.
+    ///
+    /// The rule is checked on every new or corrected bar of its stock and interval, the
+    /// finest configured interval unless it names one. When it triggers, the connection gets
+    /// `{"v": 1, "type": "alert", "alert_id": ..., "symbol": ..., "interval": ..., "rule": {...}, "timestamp": ..., "value": ...}`
+    /// and the alert is dropped. Alerts that trigger while the session has no connection are
+    /// held, up to `server.queue_capacity` of them, until it is resumed.
+    pub fn add_alert(&self, id: usize, rule: AlertRule) -> Result<(String, u64, AlertRule), ErrorCode> {
+        let intervals = self.stock_cache.get_intervals();
+
+        let rule = match intervals.iter().min() {
+            Some(v) => rule.with_default_interval(*v as u64),
+            None => return Err(ErrorCode::UnknownInterval),
+        };
+
+        if !rule.interval().is_some_and(|v| intervals.contains(&(v as u128))) {
+            return Err(ErrorCode::UnknownInterval);
+        }
+
+        let mut conn_sessions = self.conn_sessions.write().unwrap();
+        let mut alert_sessions = self.alert_sessions.write().unwrap();
+
+        let token = conn_sessions.entry(id).or_insert_with(new_session_token).clone();
+        let alert_session = alert_sessions.entry(token.clone()).or_insert_with(|| AlertSession {
+            connection: Some(id),
+            alerts: BTreeMap::new(),
+            next_alert_id: 1,
+            undelivered: VecDeque::new(),
+            detached_at: 0,
+        });
+
+        if alert_session.alerts.len() >= self.max_alerts {
+            return Err(ErrorCode::QuotaExceeded);
+        }
+
+        let alert_id = alert_session.next_alert_id;
+
+        alert_session.next_alert_id += 1;
+        alert_session.alerts.insert(alert_id, Alert::new(rule.clone()));
+
+        Ok((token, alert_id, rule))
+    }
+
+    /// Drops alert `alert_id` of the session of connection `id`. Returns false if it has no
+    /// such alert.
+    pub fn remove_alert(&self, id: usize, alert_id: u64) -> bool {
+        let conn_sessions = self.conn_sessions.read().unwrap();
+        let mut alert_sessions = self.alert_sessions.write().unwrap();
+
+        conn_sessions
+            .get(&id)
+            .and_then(|token| alert_sessions.get_mut(token))
+            .is_some_and(|v| v.alerts.remove(&alert_id).is_some())
+    }
+
+    /// Returns the token of the alert session of connection `id` and its alerts by id,
+    /// `None` if it has no session.
+    pub fn get_alerts(&self, id: usize) -> Option<(String, Vec<(u64, AlertRule)>)> {
+        let conn_sessions = self.conn_sessions.read().unwrap();
+        let alert_sessions = self.alert_sessions.read().unwrap();
+
+        let token = conn_sessions.get(&id)?;
+        let alerts = alert_sessions
+            .get(token)?
+            .alerts
+            .iter()
+            .map(|(alert_id, alert)| (*alert_id, alert.rule().clone()))
+            .collect();
+
+        Some((token.clone(), alerts))
+    }
+
+    /// Attaches the alert session `token` to connection `id`, e.g. after a reconnect, and
+    /// queues the alerts that triggered in between. A connection attached to it before is
+    /// detached, as is the session `id` had. Returns the number of alerts of the session and
+    /// of alerts queued.
+    pub fn resume_session(&self, id: usize, token: &str) -> Result<(usize, usize), ErrorCode> {
+        let mut conn_sessions = self.conn_sessions.write().unwrap();
+        let mut alert_sessions = self.alert_sessions.write().unwrap();
+
+        let token = match alert_sessions.keys().find(|v| tokens_match(v, token)) {
+            Some(v) => v.clone(),
+            None => return Err(ErrorCode::UnknownSession),
+        };
+
+        let previous_connection = alert_sessions[&token].connection;
+
+        if let Some(v) = previous_connection.filter(|v| *v != id) {
+            conn_sessions.remove(&v);
+        }
+
+        if let Some(v) = conn_sessions.insert(id, token.clone()).filter(|v| v != &token) {
+            Self::detach(&mut alert_sessions, &v, now_ms());
+        }
+
+        let alert_session = alert_sessions.get_mut(&token).expect("Session exists");
+        let undelivered: Vec<String> = alert_session.undelivered.drain(..).collect();
+
+        alert_session.connection = Some(id);
+
+        if let Some(v) = self.conn_queue.read().unwrap().get(&id) {
+            for event in undelivered.iter() {
+                let _ = v.try_send(event.clone());
+            }
+        }
+
+        println!("Connection {} resumed alert session with {} alerts", id, alert_session.alerts.len());
+
+        Ok((alert_session.alerts.len(), undelivered.len()))
+    }
+
+    fn detach_session(&self, id: usize, now: u128) {
+        let token = match self.conn_sessions.write().unwrap().remove(&id) {
+            Some(v) => v,
+            None => return,
+        };
+
+        Self::detach(&mut self.alert_sessions.write().unwrap(), &token, now);
+    }
+
+    /// Detaches the session `token` from its connection. Sessions without alerts to keep
+    /// or deliver are dropped right away.
+    fn detach(alert_sessions: &mut HashMap<String, AlertSession>, token: &String, now: u128) {
+        if let Some(v) = alert_sessions.get_mut(token) {
+            v.connection = None;
+            v.detached_at = now;
+
+            if v.alerts.is_empty() && v.undelivered.is_empty() {
+                alert_sessions.remove(token);
+            }
+        }
+    }
+
+    /// Drops the alert sessions without a connection for more than `server.alert_session_ttl_secs`.
+    fn expire_sessions(&self, now: u128) {
+        let mut alert_sessions = self.alert_sessions.write().unwrap();
+        let n = alert_sessions.len();
+
+        alert_sessions.retain(|_, v| v.connection.is_some() || v.detached_at + self.alert_session_ttl_ms >= now);
+
+        if alert_sessions.len() < n {
+            println!("Expired {} alert sessions", n - alert_sessions.len());
+        }
+    }
+
+    /// Checks the alerts watching the bars of `bar_events` and sends the ones that trigger
+    /// to their session.
+    fn check_alerts(&self, bar_events: &[BarEvent]) {
+        let mut alert_sessions = self.alert_sessions.write().unwrap();
+
+        if alert_sessions.is_empty() {
+            return;
+        }
+
+        let conn_queue = self.conn_queue.read().unwrap();
+
+        for alert_session in alert_sessions.values_mut() {
+            let mut fired = Vec::new();
+
+            for bar_event in bar_events.iter() {
+                let ohlc_model = bar_event.ohlc_model();
+
+                for (alert_id, alert) in alert_session.alerts.iter() {
+                    if fired.iter().any(|(v, _)| v == alert_id) || !alert.watches(ohlc_model) {
+                        continue;
+                    }
+
+                    if let Some(value) = alert.check(ohlc_model, &self.stock_cache) {
+                        fired.push((*alert_id, json!({
+                            "v": PROTOCOL_VERSION,
+                            "type": "alert",
+                            "alert_id": alert_id,
+                            "symbol": ohlc_model.stock_name,
+                            "interval": ohlc_model.stock_interval as u64,
+                            "rule": alert.rule(),
+                            "timestamp": ohlc_model.timestamp as u64,
+                            "value": value,
+                        }).to_string()));
+                    }
+                }
+            }
+
+            for (alert_id, event) in fired.into_iter() {
+                alert_session.alerts.remove(&alert_id);
+
+                match alert_session.connection.and_then(|v| conn_queue.get(&v)) {
+                    Some(v) => {
+                        let _ = v.try_send(event);
+                    },
+                    None => {
+                        alert_session.undelivered.push_back(event);
+
+                        if alert_session.undelivered.len() > self.queue_capacity {
+                            let _ = alert_session.undelivered.pop_front();
+                        }
+                    },
+                };
+            }
+        }
+    }
+
+    /// Returns true if `token` is the configured admin token. Always false when none is set.
+    pub fn is_admin(&self, token: Option<&str>) -> bool {
+        match (&self.admin_token, token) {
+            (Some(admin_token), Some(token)) => tokens_match(admin_token, token),
+            _ => false,
+        }
+    }
+
+    /// Drops `stock_name` from the cache and unsubscribes its subscribers, telling them why.
+    /// Returns false if it wasn't cached.
+    pub fn remove_symbol(&self, stock_name: &String) -> bool {
+        if !self.stock_cache.remove_symbol(stock_name) {
+            return false;
+        }
+
+        println!("Removed stock {:?}", stock_name);
+        self.notify_evicted(stock_name, EvictionReason::Removed);
+
+        true
+    }
+
+    /// Drops idle stocks and the least recently updated ones over the memory budget, sparing
+    /// subscribed stocks as long as possible, see [`StockInformationCacheInterface::evict_symbols`],
+    /// and notifies their subscribers.
+    pub fn evict_symbols(&self, now: u128) -> Vec<(String, EvictionReason)> {
+        if self.max_idle_ms.is_none() && self.memory_budget.is_none() {
+            return Vec::new();
+        }
+
+        let mut subscribed: HashSet<String> = self.subscr_map.read().unwrap().keys().cloned().collect();
+        subscribed.extend(self.indicator_subscr.read().unwrap().keys().map(|v| v.stock_name.clone()));
+
+        let evicted = self.stock_cache.evict_symbols(now, self.max_idle_ms, self.memory_budget, &subscribed);
+
+        for (stock_name, reason) in evicted.iter() {
+            println!("Evicted stock {:?}: {}", stock_name, reason.as_str());
+            self.notify_evicted(stock_name, *reason);
+        }
+
+        evicted
+    }
+
+    fn notify_evicted(&self, stock_name: &String, reason: EvictionReason) {
+        let mut ids_to_update = match self.subscr_map.write().unwrap().remove(stock_name) {
+            Some(v) => v.into_keys().collect::<HashSet<usize>>(),
+            None => HashSet::new(),
+        };
+
+        let indicators: Vec<(IndicatorKey, HashSet<usize>)> = self.indicator_subscr
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| &key.stock_name == stock_name)
+            .map(|(key, subscribers)| (key.clone(), subscribers.ids.clone()))
+            .collect();
+
+        for (key, ids) in indicators.into_iter() {
+            for id in ids.into_iter() {
+                self.remove_indicator_key(id, &key);
+                ids_to_update.insert(id);
+            }
+        }
+
+        if ids_to_update.is_empty() {
+            return;
+        }
+
+        let mut conn_subscr = self.conn_subscr.write().unwrap();
+
+        for id in ids_to_update.iter() {
+            if let Some(v) = conn_subscr.get_mut(id) {
+                v.remove(stock_name);
+            }
+        }
+
+        drop(conn_subscr);
+
+        let event = json!({
+            "v": PROTOCOL_VERSION,
+            "type": "evicted",
+            "symbol": stock_name,
+            "reason": reason.as_str(),
+        }).to_string();
+
+        self.add_events(ids_to_update, event);
+    }
+
+    /// Evicts stocks and expired alert sessions, then closes the DataFeed and basket periods
+    /// at `timestamp` and queues their summaries for their subscribers. The top movers are
+    /// ranked and queued too, if anyone subscribed to them.
     pub fn sync_data_events(&self, timestamp: u128) {
+        self.evict_symbols(timestamp);
+        self.expire_sessions(timestamp);
+
         let msg = self.stock_cache.retrieve_data_events(timestamp);
         let ids_to_update = self.get_subscribers(&"DataFeed".to_owned());
         self.add_events(ids_to_update, msg);
+
+        let ids_to_update = self.get_subscribers(&TOP_MOVERS_TOPIC.to_owned());
+
+        if let Some(top_movers) = self.stock_cache.top_movers(timestamp).filter(|_| !ids_to_update.is_empty()) {
+            self.add_events(ids_to_update, top_movers.to_json());
+        }
+
+        for (topic, msg) in self.stock_cache.retrieve_basket_events(timestamp).into_iter() {
+            let ids_to_update = self.get_subscribers(&topic);
+            self.add_events(ids_to_update, msg);
+        }
+    }
+}
+
+/// Returns a new random alert session token, 128 bits from the OS random source as 32 hex digits.
+fn new_session_token() -> String {
+    let mut bytes = [0u8; 16];
+
+    getrandom::getrandom(&mut bytes).expect("OS random source is available");
+
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+/// Compares two session or admin tokens in a time that doesn't depend on where they differ.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Queues all of `events` on `sender`, or none of them if the queue hasn't room for all.
+fn send_all(sender: &mpsc::Sender<String>, events: Vec<String>) -> Result<(), ErrorCode> {
+    let permits = sender.try_reserve_many(events.len()).map_err(|_| ErrorCode::SnapshotTooLarge)?;
+
+    for (permit, event) in permits.zip(events) {
+        permit.send(event);
     }
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time is after 1970")
+        .as_millis()
+}
@@ -1,33 +1,229 @@
-use crate::websockets::{NotificationClient, NotificationServer, ConnectionService};
+use std::{io, net::SocketAddr, path::Path, sync::Mutex, time::Duration};
 
+use crate::{
+    config::{ConfigError, IntervalSettings, ServerConfig},
+    value_store::{BarFormat, BasketSpec},
+    websockets::{NotificationClient, NotificationServer, ConnectionService, ShutdownHandle},
+};
+
+/// The websocket server together with its upstream feed client.
+///
+/// Build one with [`WebSocketServer::builder`] or [`WebSocketServer::new`].
 pub struct WebSocketServer {
-    ip_server: String,
-    ip_client: String,
+    config: ServerConfig,
+    connection_service: ConnectionService,
+    shutdown: ShutdownHandle,
+    local_addr: Mutex<Option<SocketAddr>>,
 }
 
 impl WebSocketServer {
-    pub fn new(ip_server: &str, ip_client: &str) -> Self {
-        WebSocketServer { 
-            ip_server: ip_server.to_owned(), 
-            ip_client: ip_client.to_owned() 
+    /// Creates a server from an already validated configuration.
+    pub fn new(config: ServerConfig) -> Self {
+        let connection_service = ConnectionService::new(&config);
+
+        WebSocketServer {
+            config,
+            connection_service,
+            shutdown: ShutdownHandle::new(),
+            local_addr: Mutex::new(None),
         }
     }
 
-    pub fn start_server(&self) {
-        let connection_service = ConnectionService::new();
+    /// Starts a builder initialised with the default configuration.
+    pub fn builder() -> WebSocketServerBuilder {
+        WebSocketServerBuilder::new()
+    }
+
+    /// Returns the configuration the server runs with.
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Returns the service used to publish bars and inspect the cache while the server runs.
+    pub fn connection_service(&self) -> ConnectionService {
+        self.connection_service.clone()
+    }
+
+    /// Returns the address subscribers connect to while the server accepts them, with the
+    /// actual port if `server.address` asked for port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+
+    /// Returns the handle that stops a running [`WebSocketServer::start_server`].
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Starts accepting websocket subscribers and, if enabled, reading the upstream feed.
+    /// The cache is first loaded from `cache.snapshot_path`, if that file exists, and then
+    /// from storage, if enabled. Reference data is read from `cache.reference_data_path`.
+    ///
+    /// Runs until [`ShutdownHandle::shutdown`] is called. Open connections then receive their
+    /// pending events and a "going away" close frame, and every server task is joined before
+    /// the pending bars are written to storage, the storage writer thread is joined, the cache
+    /// is written to `cache.snapshot_path` and this returns.
+    pub async fn start_server(&self) -> io::Result<()> {
+        if let Some(path) = self.config.cache.snapshot_path.as_ref().filter(|v| v.exists()) {
+            self.connection_service.stock_cache().load_snapshot(path)?;
+            println!("Loaded cache snapshot {:?}", path);
+        }
+
+        if let Some(path) = self.config.cache.reference_data_path.as_ref() {
+            let n = self.connection_service.reference_data().load(path)?;
+            println!("Loaded reference data of {} symbols from {:?}", n, path);
+        }
+
+        if self.config.storage.enabled {
+            self.connection_service.stock_cache().open_store(
+                &self.config.storage.path,
+                self.config.storage.segment_bytes,
+                self.config.storage.retention_ms(),
+                self.config.storage.fsync,
+            )?;
+
+            let stock_cache = self.connection_service.stock_cache();
+
+            self.shutdown.on_join(move || stock_cache.close_store());
+        }
 
         let notification_server = NotificationServer::new(
-            self.ip_server.clone(),
-            connection_service.clone(),
+            self.config.server.address.clone(),
+            self.config.server.data_feed_tick_ms,
+            self.config.server.ping_interval_ms,
+            self.connection_service.clone(),
+            self.shutdown.clone(),
         );
-        
-        notification_server.start_server();
 
-        let mut notification_client = NotificationClient::new(
-            self.ip_client.clone(),
-            connection_service,
-        );
+        let local_addr = notification_server.start_server().await?;
+
+        println!("Listening on {}", local_addr);
+
+        *self.local_addr.lock().unwrap() = Some(local_addr);
+
+        if self.config.feed.enabled {
+            let mut notification_client = NotificationClient::new(
+                self.config.feed.address.clone(),
+                Duration::from_millis(self.config.feed.reconnect_delay_ms),
+                self.connection_service.clone(),
+                self.shutdown.clone(),
+            );
+
+            notification_client.start_client().await;
+        } else {
+            self.shutdown.wait().await;
+        }
+
+        self.shutdown.join_all().await;
+
+        *self.local_addr.lock().unwrap() = None;
+
+        if let Some(path) = self.config.cache.snapshot_path.as_ref() {
+            self.connection_service.stock_cache().save_snapshot(path)?;
+            println!("Saved cache snapshot {:?}", path);
+        }
+
+        println!("Server stopped");
+
+        Ok(())
+    }
 
-        notification_client.start_client();
+    /// Runs [`WebSocketServer::start_server`] on a new multi-threaded runtime,
+    /// blocking the calling thread.
+    pub fn run(&self) -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+
+        runtime.block_on(self.start_server())
+    }
+}
+
+/// Builder for [`WebSocketServer`].
+pub struct WebSocketServerBuilder {
+    config: ServerConfig,
+}
+
+impl WebSocketServerBuilder {
+    pub fn new() -> Self {
+        WebSocketServerBuilder {
+            config: ServerConfig::default(),
+        }
+    }
+
+    /// Replaces the whole configuration.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the `host:port` websocket subscribers connect to.
+    pub fn server_address(mut self, address: &str) -> Self {
+        self.config.server.address = address.to_owned();
+        self
+    }
+
+    /// Sets the `host:port` of the upstream bar feed.
+    pub fn feed_address(mut self, address: &str) -> Self {
+        self.config.feed.address = address.to_owned();
+        self.config.feed.enabled = true;
+        self
+    }
+
+    /// Disables the upstream feed client. Bars then only arrive through
+    /// [`ConnectionService::publish_ohlc`].
+    pub fn without_feed(mut self) -> Self {
+        self.config.feed.enabled = false;
+        self
+    }
+
+    /// Sets the number of bars kept per stock and interval, unless the interval sets its own.
+    pub fn history_size(mut self, history_size: usize) -> Self {
+        self.config.cache.history_size = history_size;
+        self
     }
-}
\ No newline at end of file
+
+    /// Sets the bar intervals kept in the cache.
+    pub fn intervals(mut self, intervals: Vec<IntervalSettings>) -> Self {
+        self.config.cache.intervals = intervals;
+        self
+    }
+
+    /// Stores bars in `path` and reloads them when the server starts.
+    pub fn storage_path(mut self, path: &Path) -> Self {
+        self.config.storage.path = path.to_path_buf();
+        self.config.storage.enabled = true;
+        self
+    }
+
+    /// Sets the maximum number of undelivered events per connection.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.config.server.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Sets the shape of the bars sent to subscribers.
+    pub fn bar_format(mut self, bar_format: BarFormat) -> Self {
+        self.config.server.bar_format = bar_format;
+        self
+    }
+
+    /// Adds a basket created at startup.
+    pub fn basket(mut self, spec: BasketSpec) -> Self {
+        self.config.baskets.push(spec);
+        self
+    }
+
+    /// Validates the configuration and creates the server.
+    pub fn build(self) -> Result<WebSocketServer, ConfigError> {
+        self.config.validate()?;
+
+        Ok(WebSocketServer::new(self.config))
+    }
+}
+
+impl Default for WebSocketServerBuilder {
+    fn default() -> Self {
+        WebSocketServerBuilder::new()
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::priced_bar;
+use serde_json::Value;
+use stock_messenger::{value_store::BarEvent, OHLCModel, StockInformationCacheInterface};
+
+fn bar(timestamp: u128, stock_interval: u128, price: f64) -> OHLCModel {
+    OHLCModel {
+        min_price: price - 1.0,
+        max_price: price + 1.0,
+        trades: 2,
+        ..priced_bar("AAPL", timestamp, stock_interval, price, price + 0.5, 10.0)
+    }
+}
+
+fn add(stock_cache: &StockInformationCacheInterface, ohlc_model: OHLCModel) -> Vec<OHLCModel> {
+    stock_cache.add_ohlc(ohlc_model).unwrap().iter().map(|v| v.ohlc_model().clone()).collect()
+}
+
+fn stock_cache() -> StockInformationCacheInterface {
+    StockInformationCacheInterface::new(BTreeMap::from([(1, 120), (10, 120), (60, 120)]), 120, true)
+}
+
+#[test]
+fn builds_coarser_bars_from_one_second_bars() {
+    let stock_cache = stock_cache();
+    let mut completed = Vec::new();
+
+    for i in 0..60 {
+        let stored = add(&stock_cache, bar(60_000 + i * 1000, 1, 100.0 + i as f64));
+
+        assert_eq!(stored[0].stock_interval, 1);
+        completed.extend(stored.into_iter().skip(1));
+    }
+
+    assert_eq!(completed.iter().filter(|v| v.stock_interval == 10).count(), 6);
+    assert_eq!(completed.last().unwrap().stock_interval, 60);
+
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 10)[1], OHLCModel {
+        stock_name: "AAPL".to_owned(),
+        price_open: 110.0,
+        price_close: 119.5,
+        min_price: 109.0,
+        max_price: 120.0,
+        volume: 100.0,
+        trades: 20,
+        timestamp: 70_000,
+        stock_interval: 10,
+    });
+
+    let minute = stock_cache.get_latest(&"AAPL".to_owned(), 60).unwrap();
+
+    assert_eq!((minute.price_open, minute.price_close), (100.0, 159.5));
+    assert_eq!((minute.min_price, minute.max_price), (99.0, 160.0));
+    assert_eq!((minute.volume, minute.trades, minute.timestamp), (600.0, 120, 60_000));
+}
+
+#[test]
+fn completes_a_bar_when_the_next_period_starts() {
+    let stock_cache = stock_cache();
+
+    assert_eq!(stock_cache.add_ohlc(bar(1000, 1, 1.0)).unwrap().len(), 1);
+    assert_eq!(stock_cache.add_ohlc(bar(3000, 1, 2.0)).unwrap().len(), 1);
+
+    let stored = add(&stock_cache, bar(12_000, 1, 3.0));
+
+    assert_eq!(stored.len(), 2);
+    assert_eq!((stored[1].timestamp, stored[1].price_open, stored[1].price_close), (0, 1.0, 2.5));
+    assert_eq!((stored[1].volume, stored[1].trades), (20.0, 4));
+}
+
+#[test]
+fn does_not_build_intervals_the_feed_sends() {
+    let stock_cache = stock_cache();
+
+    stock_cache.add_ohlc(bar(0, 10, 1.0)).unwrap();
+
+    for i in 0..10 {
+        assert_eq!(stock_cache.add_ohlc(bar(i * 1000, 1, 1.0)).unwrap().len(), 1);
+    }
+
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 10).len(), 1);
+}
+
+#[test]
+fn a_native_bar_replaces_the_built_one_as_a_new_bar() {
+    let stock_cache = stock_cache();
+
+    for i in 0..=10 {
+        add(&stock_cache, bar(i * 1000, 1, 1.0));
+    }
+
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 10)[0].trades, 20);
+
+    let native = OHLCModel { trades: 5, ..bar(0, 10, 1.0) };
+
+    assert_eq!(stock_cache.add_ohlc(native.clone()).unwrap(), vec![BarEvent::Bar(native.clone())]);
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 10), vec![native]);
+
+    for i in 11..=20 {
+        assert_eq!(stock_cache.add_ohlc(bar(i * 1000, 1, 1.0)).unwrap().len(), 1);
+    }
+
+    let summary: Value = serde_json::from_str(&stock_cache.retrieve_data_events(0)).unwrap();
+
+    assert_eq!(summary["trade_n"].as_i64().unwrap(), 21 * 2 + 5);
+}
+
+#[test]
+fn a_late_bar_adjusts_the_completed_bars() {
+    let stock_cache = stock_cache();
+
+    for i in (0..60).filter(|v| *v != 35) {
+        add(&stock_cache, bar(i * 1000, 1, 1.0));
+    }
+
+    assert_eq!(stock_cache.get_latest(&"AAPL".to_owned(), 60).unwrap().volume, 590.0);
+
+    let late = bar(35_000, 1, 1.0);
+    let events = stock_cache.add_ohlc(late.clone()).unwrap();
+    let ten_seconds = stock_cache.get_history(&"AAPL".to_owned(), 10)[3].clone();
+    let minute = stock_cache.get_latest(&"AAPL".to_owned(), 60).unwrap();
+
+    assert_eq!(events, vec![
+        BarEvent::Bar(late),
+        BarEvent::Correction(ten_seconds.clone()),
+        BarEvent::Correction(minute.clone()),
+    ]);
+    assert_eq!((ten_seconds.timestamp, ten_seconds.volume, ten_seconds.trades), (30_000, 100.0, 20));
+    assert_eq!((minute.timestamp, minute.volume, minute.trades), (0, 600.0, 120));
+
+    for i in 60..=120 {
+        add(&stock_cache, bar(i * 1000, 1, 1.0));
+    }
+
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 60)[0], minute);
+}
@@ -0,0 +1,212 @@
+mod common;
+
+use std::time::SystemTime;
+
+use serde_json::Value;
+use tokio::sync::mpsc::Receiver;
+
+use common::priced_bar;
+use stock_messenger::{
+    config::ServerConfig,
+    value_store::{AlertRule, CrossDirection},
+    websockets::{protocol::ErrorCode, ConnectionService},
+    WebSocketServer,
+};
+
+fn connection_service(mut config: ServerConfig) -> ConnectionService {
+    config.feed.enabled = false;
+    config.cache.aggregate = false;
+
+    WebSocketServer::new(config).connection_service()
+}
+
+fn alerts(events: &mut Receiver<String>) -> Vec<Value> {
+    let mut alerts = Vec::new();
+
+    while let Ok(event) = events.try_recv() {
+        alerts.push(serde_json::from_str::<Value>(&event).unwrap());
+    }
+
+    alerts
+}
+
+fn price_cross(direction: CrossDirection, level: f64) -> AlertRule {
+    AlertRule::PriceCross {
+        symbol: "AAPL".to_owned(),
+        direction,
+        level,
+        interval: None,
+    }
+}
+
+#[test]
+fn price_cross_fires_once() {
+    let connection_service = connection_service(ServerConfig::default());
+    let (id, mut events) = connection_service.add_subscriber();
+
+    // Starts above the level, so the first bar doesn't cross.
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 210.0, 210.0, 1.0)).unwrap();
+
+    let (token, alert_id, rule) = connection_service.add_alert(id, price_cross(CrossDirection::Above, 200.0)).unwrap();
+    let (_, below_id, _) = connection_service.add_alert(id, price_cross(CrossDirection::Below, 200.0)).unwrap();
+
+    assert_eq!(token.len(), 32);
+    assert!(token.bytes().all(|v| v.is_ascii_hexdigit()));
+    assert_eq!(rule.interval(), Some(1));
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 210.0, 190.0, 1.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 2000, 1, 190.0, 199.0, 1.0)).unwrap();
+
+    let fired = alerts(&mut events);
+
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0]["type"], "alert");
+    assert_eq!(fired[0]["alert_id"], below_id);
+    assert_eq!(fired[0]["value"], 190.0);
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 3000, 1, 199.0, 201.0, 1.0)).unwrap();
+
+    let fired = alerts(&mut events);
+
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0]["alert_id"], alert_id);
+    assert_eq!(fired[0]["symbol"], "AAPL");
+    assert_eq!(fired[0]["interval"], 1);
+    assert_eq!(fired[0]["timestamp"], 3000);
+    assert_eq!(fired[0]["rule"]["kind"], "price_cross");
+    assert_eq!(fired[0]["value"], 201.0);
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 4000, 1, 201.0, 190.0, 1.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 5000, 1, 190.0, 210.0, 1.0)).unwrap();
+
+    assert!(alerts(&mut events).is_empty());
+    assert_eq!(connection_service.get_alerts(id), Some((token, Vec::new())));
+}
+
+#[test]
+fn percent_move_and_volume_alerts() {
+    let connection_service = connection_service(ServerConfig::default());
+    let (id, mut events) = connection_service.add_subscriber();
+
+    let (_, move_id, _) = connection_service.add_alert(id, AlertRule::PercentMove {
+        symbol: "AAPL".to_owned(),
+        percent: 2.0,
+        window_secs: 120,
+        interval: Some(60),
+    }).unwrap();
+
+    let (_, volume_id, _) = connection_service.add_alert(id, AlertRule::VolumeAbove {
+        symbol: "AAPL".to_owned(),
+        volume: 100.0,
+        interval: None,
+    }).unwrap();
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 60, 100.0, 101.0, 500.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 60_000, 60, 101.0, 101.5, 500.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 100.0, 100.0, 50.0)).unwrap();
+
+    assert!(alerts(&mut events).is_empty());
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 120_000, 60, 101.5, 98.0, 500.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 100.0, 100.0, 150.0)).unwrap();
+
+    let fired = alerts(&mut events);
+
+    assert_eq!(fired.len(), 2);
+    assert_eq!(fired[0]["alert_id"], move_id);
+    assert_eq!(fired[0]["interval"], 60);
+    assert_eq!(fired[0]["value"], -2.0);
+    assert_eq!(fired[1]["alert_id"], volume_id);
+    assert_eq!(fired[1]["value"], 150.0);
+}
+
+#[test]
+fn alerts_survive_a_reconnect() {
+    let connection_service = connection_service(ServerConfig::default());
+    let (id, _events) = connection_service.add_subscriber();
+
+    let (token, alert_id, _) = connection_service.add_alert(id, price_cross(CrossDirection::Above, 200.0)).unwrap();
+    connection_service.add_alert(id, price_cross(CrossDirection::Below, 100.0)).unwrap();
+
+    connection_service.remove_subscriber(id);
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 190.0, 190.0, 1.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 190.0, 205.0, 1.0)).unwrap();
+
+    let (other_id, mut other_events) = connection_service.add_subscriber();
+
+    assert_eq!(connection_service.get_alerts(other_id), None);
+    assert_eq!(connection_service.resume_session(other_id, "nope"), Err(ErrorCode::UnknownSession));
+
+    let mut wrong_token = token.clone();
+    let last = if wrong_token.ends_with('0') { "1" } else { "0" };
+    wrong_token.replace_range(31.., last);
+
+    assert_eq!(connection_service.resume_session(other_id, &wrong_token), Err(ErrorCode::UnknownSession));
+    assert_eq!(connection_service.resume_session(other_id, &token), Ok((1, 1)));
+
+    let fired = alerts(&mut other_events);
+
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0]["alert_id"], alert_id);
+
+    let (resumed_token, remaining) = connection_service.get_alerts(other_id).unwrap();
+
+    assert_eq!(resumed_token, token);
+    assert_eq!(remaining.len(), 1);
+    assert!(connection_service.remove_alert(other_id, remaining[0].0));
+    assert!(!connection_service.remove_alert(other_id, remaining[0].0));
+
+    // A session without alerts isn't kept once its connection closes.
+    connection_service.remove_subscriber(other_id);
+
+    let (id, _events) = connection_service.add_subscriber();
+
+    assert_eq!(connection_service.resume_session(id, &token), Err(ErrorCode::UnknownSession));
+}
+
+#[test]
+fn detached_sessions_expire() {
+    let mut config = ServerConfig::default();
+    config.server.alert_session_ttl_secs = 5;
+
+    let connection_service = connection_service(config);
+    let (id, _events) = connection_service.add_subscriber();
+    let (token, _, _) = connection_service.add_alert(id, price_cross(CrossDirection::Above, 200.0)).unwrap();
+
+    connection_service.remove_subscriber(id);
+
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+
+    connection_service.sync_data_events(now);
+
+    let (id, _events) = connection_service.add_subscriber();
+
+    assert_eq!(connection_service.resume_session(id, &token), Ok((1, 0)));
+
+    connection_service.remove_subscriber(id);
+    connection_service.sync_data_events(now + 10_000);
+
+    let (id, _events) = connection_service.add_subscriber();
+
+    assert_eq!(connection_service.resume_session(id, &token), Err(ErrorCode::UnknownSession));
+}
+
+#[test]
+fn rejects_alerts_over_the_limits() {
+    let mut config = ServerConfig::default();
+    config.server.max_alerts = 1;
+
+    let connection_service = connection_service(config);
+    let (id, _events) = connection_service.add_subscriber();
+
+    let unknown_interval = AlertRule::VolumeAbove {
+        symbol: "AAPL".to_owned(),
+        volume: 1.0,
+        interval: Some(7),
+    };
+
+    assert_eq!(connection_service.add_alert(id, unknown_interval), Err(ErrorCode::UnknownInterval));
+    assert!(connection_service.add_alert(id, price_cross(CrossDirection::Above, 1.0)).is_ok());
+    assert_eq!(connection_service.add_alert(id, price_cross(CrossDirection::Above, 2.0)), Err(ErrorCode::QuotaExceeded));
+}
@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use stock_messenger::{
+    value_store::{BarFormat, ParseError},
+    OHLCModel,
+    StockInformationCacheInterface,
+};
+
+#[test]
+fn parses_upstream_line() {
+    let ohlc_model = OHLCModel::parse("B;AAPL;1.5;2.5;1.0;3.0;100.0;7;1700000000000;60\n").unwrap();
+
+    assert_eq!(ohlc_model, OHLCModel {
+        stock_name: "AAPL".to_owned(),
+        price_open: 1.5,
+        price_close: 2.5,
+        min_price: 1.0,
+        max_price: 3.0,
+        volume: 100.0,
+        trades: 7,
+        timestamp: 1700000000000,
+        stock_interval: 60,
+    });
+
+    assert_eq!(OHLCModel::parse("B;AAPL;1;1;1;1;1;1;1;1;\r\n").unwrap().stock_interval, 1);
+}
+
+#[test]
+fn rejects_malformed_lines() {
+    let corpus = [
+        ("B;AAPL;1;1;1;1;1;1;1", ParseError::WrongFieldCount { expected: 10, found: 9 }),
+        ("B;AAPL;1;1;1;1;1;1;1;1;1", ParseError::WrongFieldCount { expected: 10, found: 11 }),
+        ("", ParseError::WrongFieldCount { expected: 10, found: 1 }),
+        ("B; ;1;1;1;1;1;1;1;1", ParseError::EmptySymbol),
+        ("B;AAPL;x;1;1;1;1;1;1;1", ParseError::BadNumber { field: "price_open", value: "x".to_owned() }),
+        ("B;AAPL;1;NaN;1;1;1;1;1;1", ParseError::BadNumber { field: "price_close", value: "NaN".to_owned() }),
+        ("B;AAPL;1;1;1;1;1;1.5;1;1", ParseError::BadNumber { field: "trades", value: "1.5".to_owned() }),
+        ("B;AAPL;1;1;1;1;1;1;-1;1", ParseError::BadNumber { field: "timestamp", value: "-1".to_owned() }),
+        ("B;AAPL;1;1;1;1;1;1;1;0", ParseError::UnknownInterval(0)),
+    ];
+
+    for (line, error) in corpus.into_iter() {
+        assert_eq!(OHLCModel::parse(line), Err(error), "line: {:?}", line);
+    }
+}
+
+#[test]
+fn bad_lines_do_not_drop_the_batch() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 120), (60, 120)]), 120, false);
+
+    let ingest_report = stock_cache.add_json(
+        "B;AAPL;1;1;1;1;1;1;1000;1\n\
+         B;AAPL;oops;1;1;1;1;1;2000;1\n\
+         B;MSFT;1;1;1;1;1;1;1000;7\n\
+         B;MSFT;1;1;1;1;1;1;1000;1\n".to_owned()
+    );
+
+    assert_eq!(ingest_report.accepted.len(), 2);
+    assert_eq!(ingest_report.rejected.len(), 2);
+    assert_eq!(ingest_report.rejected[1].1, ParseError::UnknownInterval(7));
+    assert_eq!(stock_cache.rejected_lines(), 2);
+    assert_eq!(stock_cache.get_symbols(), vec!["AAPL".to_owned(), "MSFT".to_owned()]);
+}
+
+#[test]
+fn keeps_configured_intervals_with_their_retention() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(5, 2), (3600, 1)]), 120, false);
+
+    let ingest_report = stock_cache.add_json(
+        "B;AAPL;1;1;1;1;1;1;5000;5\n\
+         B;AAPL;2;2;2;2;2;2;10000;5\n\
+         B;AAPL;3;3;3;3;3;3;15000;5\n\
+         B;AAPL;4;4;4;4;4;4;3600000;3600\n\
+         B;AAPL;5;5;5;5;5;5;60000;60\n\
+         B;AAPL;6;6;6;6;6;6;120000;60\n".to_owned()
+    );
+
+    assert_eq!(ingest_report.accepted.len(), 4);
+    assert_eq!(stock_cache.get_intervals(), vec![5, 3600]);
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 5).iter().map(|v| v.timestamp).collect::<Vec<_>>(), vec![10000, 15000]);
+    assert_eq!(stock_cache.get_latest(&"AAPL".to_owned(), 3600).unwrap().price_open, 4.0);
+    assert_eq!(stock_cache.get_unknown_intervals(), BTreeMap::from([(60, 2)]));
+}
+
+fn escaped_bars() -> Vec<OHLCModel> {
+    ["AAPL", "BRK \"B\"", "C:\\feed\\X", "\u{e9}\t\n"]
+        .iter()
+        .map(|stock_name| OHLCModel {
+            stock_name: stock_name.to_string(),
+            price_open: 1.25,
+            price_close: 2.5,
+            min_price: 0.5,
+            max_price: 3.75,
+            volume: 1234.5,
+            trades: 42,
+            timestamp: 1700000000000,
+            stock_interval: 60,
+        })
+        .collect()
+}
+
+#[test]
+fn v1_bars_round_trip() {
+    for ohlc_model in escaped_bars().iter() {
+        let bar: Value = serde_json::from_str(&ohlc_model.to_json(BarFormat::V1)).unwrap();
+
+        assert_eq!(bar["schema_version"], 1);
+        assert_eq!(bar["type"], "bar");
+        assert_eq!(bar["stock_name"].as_str(), Some(ohlc_model.stock_name.as_str()));
+        assert_eq!(bar["stock_interval"], 60);
+        assert_eq!(bar["timestamp"], 1700000000000u64);
+        assert_eq!(bar["price_open"].as_f64(), Some(ohlc_model.price_open));
+        assert_eq!(bar["price_close"].as_f64(), Some(ohlc_model.price_close));
+        assert_eq!(bar["min_price"].as_f64(), Some(ohlc_model.min_price));
+        assert_eq!(bar["max_price"].as_f64(), Some(ohlc_model.max_price));
+        assert_eq!(bar["volume"].as_f64(), Some(ohlc_model.volume));
+        assert_eq!(bar["trades"], 42);
+        assert_eq!(ohlc_model.to_value().unwrap(), bar);
+    }
+}
+
+#[test]
+fn legacy_bars_round_trip() {
+    for ohlc_model in escaped_bars().iter() {
+        let bar: Value = serde_json::from_str(&ohlc_model.to_json(BarFormat::Legacy)).unwrap();
+
+        assert!(bar.get("schema_version").is_none() && bar.get("v").is_none());
+        assert_eq!(bar["name"].as_str(), Some(ohlc_model.stock_name.as_str()));
+        assert_eq!(bar["stock_interval"], 60);
+        assert_eq!(bar["timestamp"], 1700000000000u64);
+        assert_eq!(bar["price_open"], "1.250000");
+        assert_eq!(bar["price_close"].as_f64(), Some(ohlc_model.price_close));
+        assert_eq!(bar["min_price"].as_f64(), Some(ohlc_model.min_price));
+        assert_eq!(bar["max_price"].as_f64(), Some(ohlc_model.max_price));
+        assert_eq!(bar["volume"].as_f64(), Some(ohlc_model.volume));
+        assert_eq!(bar["trades"], 42);
+    }
+}
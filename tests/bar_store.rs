@@ -0,0 +1,149 @@
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+};
+
+use common::ohlc_model;
+use stock_messenger::{value_store::BarStore, OHLCModel, StockInformationCacheInterface};
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("stock_messenger_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&path);
+
+    path
+}
+
+fn open(path: &Path, segment_bytes: u64, retention_ms: u128) -> (BarStore, Vec<OHLCModel>) {
+    let mut replayed = Vec::new();
+    let bar_store = BarStore::open(path, segment_bytes, retention_ms, false, |v| replayed.push(v)).unwrap();
+
+    (bar_store, replayed)
+}
+
+fn timestamps(bars: &[OHLCModel]) -> Vec<u128> {
+    bars.iter().map(|v| v.timestamp).collect()
+}
+
+#[test]
+fn reloads_appended_bars() {
+    let path = store_path("reload");
+
+    let (mut bar_store, replayed) = open(&path, 1 << 20, u128::MAX / 2);
+    assert!(replayed.is_empty());
+
+    bar_store.append(&[ohlc_model("AAPL", 1000, 1), ohlc_model("AAPL", 2000, 1)]).unwrap();
+    bar_store.append(&[ohlc_model("AAPL", 3000, 1)]).unwrap();
+    drop(bar_store);
+
+    let (_, replayed) = open(&path, 1 << 20, u128::MAX / 2);
+
+    assert_eq!(replayed, vec![ohlc_model("AAPL", 1000, 1), ohlc_model("AAPL", 2000, 1), ohlc_model("AAPL", 3000, 1)]);
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn truncates_a_torn_record() {
+    let path = store_path("torn");
+
+    let (mut bar_store, _) = open(&path, 1 << 20, u128::MAX / 2);
+    bar_store.append(&[ohlc_model("AAPL", 1000, 1), ohlc_model("AAPL", 2000, 1)]).unwrap();
+    drop(bar_store);
+
+    let segment = fs::read_dir(&path).unwrap().next().unwrap().unwrap().path();
+    let valid_len = fs::metadata(&segment).unwrap().len();
+
+    OpenOptions::new().append(true).open(&segment).unwrap().write_all(&[200, 0, 0, 0, 1, 2, 3, 4, b'{']).unwrap();
+
+    let (mut bar_store, replayed) = open(&path, 1 << 20, u128::MAX / 2);
+
+    assert_eq!(timestamps(&replayed), vec![1000, 2000]);
+    assert_eq!(fs::metadata(&segment).unwrap().len(), valid_len);
+
+    bar_store.append(&[ohlc_model("AAPL", 3000, 1)]).unwrap();
+    drop(bar_store);
+
+    let (_, replayed) = open(&path, 1 << 20, u128::MAX / 2);
+
+    assert_eq!(timestamps(&replayed), vec![1000, 2000, 3000]);
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn rotates_segments_and_drops_expired_ones() {
+    let path = store_path("retention");
+
+    let (mut bar_store, _) = open(&path, 400, 10_000);
+
+    for i in 0..30 {
+        bar_store.append(&[ohlc_model("AAPL", i * 1000, 1)]).unwrap();
+    }
+
+    assert!(bar_store.segment_count() > 1);
+
+    let stored = bar_store.query(&"AAPL".to_owned(), 1, 0, 30_000, usize::MAX).unwrap();
+
+    assert_eq!(stored.last().unwrap().timestamp, 29_000);
+    assert!(stored.first().unwrap().timestamp >= 15_000);
+    assert_eq!(timestamps(&bar_store.query(&"AAPL".to_owned(), 1, 0, 27_000, 3).unwrap()), vec![25_000, 26_000, 27_000]);
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn cache_reloads_from_the_store_and_queries_past_its_history() {
+    let path = store_path("cache");
+    let intervals = BTreeMap::from([(1, 3)]);
+    let stock_name = "AAPL".to_owned();
+
+    let stock_cache = StockInformationCacheInterface::new(intervals.clone(), 120, false);
+    stock_cache.open_store(&path, 1 << 20, u128::MAX / 2, true).unwrap();
+
+    for i in 0..10 {
+        stock_cache.add_ohlc(ohlc_model("AAPL", i * 1000, 1)).unwrap();
+    }
+
+    stock_cache.flush_store();
+
+    let restarted = StockInformationCacheInterface::new(intervals, 120, false);
+    restarted.open_store(&path, 1 << 20, u128::MAX / 2, true).unwrap();
+
+    assert_eq!(timestamps(&restarted.get_history(&stock_name, 1)), vec![7000, 8000, 9000]);
+    assert_eq!(timestamps(&restarted.query_range(&stock_name, 1, 2000, 8000, 100)), vec![2000, 3000, 4000, 5000, 6000, 7000, 8000]);
+    assert_eq!(timestamps(&restarted.query_range(&stock_name, 1, 0, 9000, 4)), vec![6000, 7000, 8000, 9000]);
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn closing_the_store_writes_every_stored_bar() {
+    let path = store_path("close");
+    let intervals = BTreeMap::from([(1, 100)]);
+    let stock_name = "AAPL".to_owned();
+
+    let stock_cache = StockInformationCacheInterface::new(intervals.clone(), 120, false);
+    stock_cache.open_store(&path, 1 << 20, u128::MAX / 2, true).unwrap();
+
+    for i in 0..10 {
+        stock_cache.add_ohlc(ohlc_model("AAPL", i * 1000, 1)).unwrap();
+    }
+
+    stock_cache.close_store();
+    stock_cache.close_store();
+    // Nothing writes bars stored once the store is closed.
+    stock_cache.add_ohlc(ohlc_model("AAPL", 10_000, 1)).unwrap();
+    stock_cache.flush_store();
+
+    let restarted = StockInformationCacheInterface::new(intervals, 120, false);
+    restarted.open_store(&path, 1 << 20, u128::MAX / 2, true).unwrap();
+
+    assert_eq!(timestamps(&restarted.get_history(&stock_name, 1)), (0..10).map(|v| v * 1000).collect::<Vec<u128>>());
+
+    fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use common::priced_bar;
+use stock_messenger::{
+    config::ServerConfig,
+    value_store::{BarFilter, BasketSpec, BasketWeighting, EvictionReason},
+    websockets::{protocol::ErrorCode, SubscriptionState},
+    StockInformationCacheInterface,
+    WebSocketServer,
+};
+
+fn basket_spec(name: &str, weighting: BasketWeighting) -> BasketSpec {
+    BasketSpec {
+        name: name.to_owned(),
+        symbols: vec!["AAPL".to_owned(), "MSFT".to_owned()],
+        weighting,
+    }
+}
+
+fn levels(stock_cache: &StockInformationCacheInterface, timestamp: u128) -> BTreeMap<String, Value> {
+    stock_cache
+        .retrieve_basket_events(timestamp)
+        .into_iter()
+        .map(|(topic, event)| (topic, serde_json::from_str::<Value>(&event).unwrap()))
+        .collect()
+}
+
+fn assert_close(value: &Value, expected: f64) {
+    assert!((value.as_f64().unwrap() - expected).abs() < 1e-9, "{} != {}", value, expected);
+}
+
+#[test]
+fn indices_follow_their_weighting() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 10)]), 10, false);
+
+    for (name, weighting) in [("EQ", BasketWeighting::Equal), ("PX", BasketWeighting::Price), ("VAL", BasketWeighting::Value)] {
+        assert!(stock_cache.add_basket(basket_spec(name, weighting)));
+    }
+
+    assert!(!stock_cache.add_basket(basket_spec("EQ", BasketWeighting::Price)));
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 0, 1, 10.0, 10.0, 30.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("MSFT", 0, 1, 100.0, 100.0, 1.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("NVDA", 0, 1, 50.0, 50.0, 1.0)).unwrap();
+
+    let events = levels(&stock_cache, 1000);
+
+    assert_eq!(events.len(), 3);
+
+    let event = &events["basket:EQ"];
+
+    assert_eq!(event["basket"], "EQ");
+    assert_eq!(event["weighting"], "equal");
+    assert_eq!(event["stock_n"], 2);
+    assert_eq!(event["trade_n"], 2);
+    assert_eq!(event["volume_n"], 31.0);
+    assert_eq!(event["market_v_n"], 400.0);
+    assert_eq!(event["level"], 100.0);
+    assert_eq!(event["timestamp"], 1000);
+
+    // AAPL gains 10%, MSFT stays, and AAPL trades 11 * 100 against 100 * 1 for MSFT.
+    stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 11.0, 11.0, 100.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("MSFT", 1000, 1, 100.0, 100.0, 1.0)).unwrap();
+
+    let events = levels(&stock_cache, 2000);
+
+    assert_close(&events["basket:EQ"]["level"], 105.0);
+    assert_close(&events["basket:PX"]["level"], 100.0 * 111.0 / 110.0);
+    assert_close(&events["basket:VAL"]["level"], 100.0 * (1100.0 * 1.1 + 100.0) / 1200.0);
+
+    // A period without bars leaves the levels where they are.
+    let events = levels(&stock_cache, 3000);
+
+    assert_close(&events["basket:EQ"]["level"], 105.0);
+    assert_eq!(events["basket:EQ"]["trade_n"], 0);
+
+    let baskets = stock_cache.get_baskets();
+
+    assert_eq!(baskets.iter().map(|(spec, _)| spec.name.as_str()).collect::<Vec<&str>>(), vec!["EQ", "PX", "VAL"]);
+    assert_close(&Value::from(baskets[0].1), 105.0);
+
+    assert!(stock_cache.remove_basket(&"PX".to_owned()));
+    assert!(!stock_cache.remove_basket(&"PX".to_owned()));
+    assert_eq!(levels(&stock_cache, 4000).len(), 2);
+}
+
+#[test]
+fn corrections_move_the_current_period() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 10)]), 10, false);
+
+    stock_cache.add_basket(basket_spec("EQ", BasketWeighting::Equal));
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 0, 1, 10.0, 10.0, 10.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("MSFT", 0, 1, 100.0, 100.0, 1.0)).unwrap();
+    levels(&stock_cache, 1000);
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 12.0, 12.0, 10.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 11.0, 11.0, 5.0)).unwrap();
+
+    let event = &levels(&stock_cache, 2000)["basket:EQ"];
+
+    assert_eq!(event["trade_n"], 1);
+    assert_eq!(event["volume_n"], 5.0);
+    assert_eq!(event["market_v_n"], 55.0);
+    assert_close(&event["level"], 105.0);
+}
+
+#[test]
+fn rejects_invalid_baskets_in_the_config() {
+    let mut config = ServerConfig {
+        baskets: vec![basket_spec("TECH", BasketWeighting::Equal)],
+        ..ServerConfig::default()
+    };
+
+    assert!(config.validate().is_ok());
+
+    config.baskets.push(basket_spec("TECH", BasketWeighting::Value));
+    assert!(config.validate().is_err());
+
+    config.baskets = vec![BasketSpec { symbols: Vec::new(), ..basket_spec("TECH", BasketWeighting::Equal) }];
+    assert!(config.validate().is_err());
+
+    let config: ServerConfig = toml::from_str(r#"
+        [[baskets]]
+        name = "TECH"
+        symbols = ["AAPL", "MSFT"]
+        weighting = "price"
+    "#).unwrap();
+
+    assert_eq!(config.baskets, vec![basket_spec("TECH", BasketWeighting::Price)]);
+}
+
+#[test]
+fn baskets_are_subscribed_like_the_data_feed() {
+    let mut config = ServerConfig::default();
+    config.feed.enabled = false;
+    config.server.max_baskets = 2;
+    config.baskets = vec![basket_spec("TECH", BasketWeighting::Equal)];
+
+    let server = WebSocketServer::new(config);
+    let connection_service = server.connection_service();
+    let topic = "basket:TECH".to_owned();
+    let other_topic = "basket:MEGA".to_owned();
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    assert_eq!(connection_service.add_stock_subscription(id, &topic, &BarFilter::default()), Ok(SubscriptionState::Active));
+    assert_eq!(events.try_recv().unwrap(), "End of Update");
+
+    // A basket that doesn't exist yet is pending until it is created.
+    assert_eq!(connection_service.add_stock_subscription(id, &other_topic, &BarFilter::default()), Ok(SubscriptionState::Pending));
+
+    assert_eq!(connection_service.add_basket(basket_spec("MEGA", BasketWeighting::Value)), Ok(()));
+    assert_eq!(connection_service.add_basket(basket_spec("MEGA", BasketWeighting::Value)), Ok(()));
+    assert_eq!(connection_service.add_basket(basket_spec("MEGA", BasketWeighting::Price)), Err(ErrorCode::AlreadyExists));
+    assert_eq!(connection_service.add_basket(basket_spec("MORE", BasketWeighting::Price)), Err(ErrorCode::QuotaExceeded));
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "subscription");
+    assert_eq!(event["symbol"], "basket:MEGA");
+    assert_eq!(events.try_recv().unwrap(), "End of Update");
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 10.0, 10.0, 1.0)).unwrap();
+    connection_service.sync_data_events(1000);
+
+    let mut baskets: Vec<String> = (0..2)
+        .map(|_| serde_json::from_str::<Value>(&events.try_recv().unwrap()).unwrap()["basket"].as_str().unwrap().to_owned())
+        .collect();
+
+    baskets.sort();
+
+    assert_eq!(baskets, vec!["MEGA", "TECH"]);
+    assert!(events.try_recv().is_err());
+
+    // The history is replayed to new subscribers.
+    let (other_id, mut other_events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(other_id, &topic, &BarFilter::default()).unwrap();
+
+    let event: Value = serde_json::from_str(&other_events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["timestamp"], 1000);
+    assert_eq!(other_events.try_recv().unwrap(), "End of Update");
+
+    assert!(connection_service.remove_basket(&"TECH".to_owned()));
+    assert!(!connection_service.remove_basket(&"TECH".to_owned()));
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "evicted");
+    assert_eq!(event["symbol"], "basket:TECH");
+    assert_eq!(event["reason"], EvictionReason::Removed.as_str());
+    assert_eq!(connection_service.get_subscriptions(id), vec![other_topic]);
+}
@@ -0,0 +1,71 @@
+//! Fixtures shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::{
+    net::TcpStream,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use tungstenite::{connect, stream::MaybeTlsStream, WebSocket};
+
+use stock_messenger::{OHLCModel, WebSocketServer};
+
+pub type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// A flat bar of `stock_name` at 1.0 with 10 of volume and one trade.
+pub fn ohlc_model(stock_name: &str, timestamp: u128, stock_interval: u128) -> OHLCModel {
+    priced_bar(stock_name, timestamp, stock_interval, 1.0, 1.0, 10.0)
+}
+
+/// A bar of `stock_name` moving from `price_open` to `price_close`, its low and high being
+/// the lower and higher of the two, with one trade.
+pub fn priced_bar(
+    stock_name: &str,
+    timestamp: u128,
+    stock_interval: u128,
+    price_open: f64,
+    price_close: f64,
+    volume: f64,
+) -> OHLCModel {
+    OHLCModel {
+        stock_name: stock_name.to_owned(),
+        price_open,
+        price_close,
+        min_price: price_open.min(price_close),
+        max_price: price_open.max(price_close),
+        volume,
+        trades: 1,
+        timestamp,
+        stock_interval,
+    }
+}
+
+/// Runs `server`, which should listen on port 0, on its own thread.
+pub fn spawn_server(server: WebSocketServer) -> (Arc<WebSocketServer>, JoinHandle<()>) {
+    let server = Arc::new(server);
+    let running = server.clone();
+
+    (server, thread::spawn(move || running.run().unwrap()))
+}
+
+/// Connects to `server` once it is listening, retrying for a few seconds.
+pub fn connect_client(server: &WebSocketServer) -> Client {
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    loop {
+        match server.local_addr() {
+            Some(v) => match connect(format!("ws://{}", v)) {
+                Ok((client, _response)) => return client,
+                Err(e) if Instant::now() >= deadline => panic!("Couldn't connect to the server: {}", e),
+                Err(_) => (),
+            },
+            None if Instant::now() >= deadline => panic!("The server didn't start listening"),
+            None => (),
+        };
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
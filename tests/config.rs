@@ -0,0 +1,249 @@
+use std::{env, fs, path::PathBuf, process};
+
+use stock_messenger::{
+    config::{
+        cli::{flag_name, CliArgs},
+        server_config::{env_name, CONFIG_KEYS},
+        ConfigError,
+        IntervalSettings,
+        ServerConfig,
+    },
+    value_store::BasketSpec,
+};
+
+fn config_path(name: &str, content: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("stock_messenger_{}_{}.toml", name, process::id()));
+    fs::write(&path, content).unwrap();
+
+    path
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|v| v.to_string()).collect()
+}
+
+/// Returns the key of an [`ConfigError::InvalidValue`], panicking on anything else.
+fn invalid_key(result: Result<(), ConfigError>) -> String {
+    match result {
+        Err(ConfigError::InvalidValue { key, .. }) => key,
+        other => panic!("expected an invalid value, got {:?}", other),
+    }
+}
+
+fn basket(name: &str, symbols: &[&str]) -> BasketSpec {
+    BasketSpec {
+        name: name.to_owned(),
+        symbols: symbols.iter().map(|v| v.to_string()).collect(),
+        weighting: Default::default(),
+    }
+}
+
+// The only test reading the environment, the others would see its variables.
+#[test]
+fn flags_override_the_environment_which_overrides_the_file() {
+    let path = config_path("layering", "[server]\nqueue_capacity = 10\nmax_subscriptions = 20\nmax_query_bars = 30\n");
+
+    env::set_var(env_name("server.max_subscriptions"), "21");
+    env::set_var(env_name("server.max_query_bars"), "31");
+
+    let config = ServerConfig::from_args(args(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--server-max-query-bars=32",
+    ])).unwrap();
+
+    assert_eq!(config.server.queue_capacity, 10);
+    assert_eq!(config.server.max_subscriptions, 21);
+    assert_eq!(config.server.max_query_bars, 32);
+    assert_eq!(config.server.max_list_symbols, 100);
+
+    // A malformed variable is reported like a malformed flag.
+    env::set_var(env_name("server.max_subscriptions"), "many");
+
+    let error = ServerConfig::from_args(args(&["--config", path.to_str().unwrap()])).unwrap_err();
+
+    assert_eq!(invalid_key(Err(error)), "server.max_subscriptions");
+
+    // The flags are validated after being applied.
+    env::remove_var(env_name("server.max_subscriptions"));
+    env::remove_var(env_name("server.max_query_bars"));
+
+    let error = ServerConfig::from_args(args(&["--config", path.to_str().unwrap(), "--server-max-query-bars", "0"])).unwrap_err();
+
+    assert_eq!(invalid_key(Err(error)), "server.max_query_bars");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn the_file_rejects_unknown_keys_and_malformed_values() {
+    let path = config_path("unknown_key", "[server]\nqueue_size = 10\n");
+
+    assert!(matches!(ServerConfig::from_file(&path), Err(ConfigError::Parse(..))));
+
+    fs::write(&path, "[server]\nqueue_capacity = \"ten\"\n").unwrap();
+
+    assert!(matches!(ServerConfig::from_file(&path), Err(ConfigError::Parse(..))));
+
+    fs::write(&path, "[cache]\nintervals = [1, { interval = 60, retention = 240 }]\n").unwrap();
+
+    assert_eq!(
+        ServerConfig::from_file(&path).unwrap().cache.intervals,
+        vec![
+            IntervalSettings { interval: 1, retention: None },
+            IntervalSettings { interval: 60, retention: Some(240) },
+        ],
+    );
+
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(ServerConfig::from_file(&path), Err(ConfigError::Io(..))));
+}
+
+#[test]
+fn every_key_can_be_set() {
+    let mut config = ServerConfig::default();
+
+    for key in CONFIG_KEYS.iter() {
+        assert!(!matches!(config.set_value(key, ""), Err(ConfigError::UnknownKey(_))), "{} can't be set", key);
+    }
+
+    assert!(matches!(config.set_value("server.queue_size", "10"), Err(ConfigError::UnknownKey(v)) if v == "server.queue_size"));
+}
+
+#[test]
+fn malformed_values_report_their_key() {
+    let malformed = [
+        ("server.queue_capacity", "many"),
+        ("server.queue_capacity", "-1"),
+        ("server.bar_format", "xml"),
+        ("server.ping_interval_ms", "1.5"),
+        ("feed.enabled", "maybe"),
+        ("cache.intervals", "1,x"),
+        ("cache.intervals", "60:-1"),
+        ("cache.aggregate", "1"),
+        ("cache.session_start", "25:00"),
+        ("storage.fsync", "yes"),
+        ("top_movers.interval", "minute"),
+    ];
+
+    for (key, value) in malformed.iter() {
+        let mut config = ServerConfig::default();
+
+        match config.set_value(key, value) {
+            // A list reports the entry that is malformed.
+            Err(ConfigError::InvalidValue { key: k, value: v, .. }) => assert!(k == *key && value.contains(v.as_str())),
+            other => panic!("{} = {:?} gave {:?}", key, value, other),
+        };
+    }
+
+    let mut config = ServerConfig::default();
+
+    config.set_value("cache.intervals", " 1, 60:240 ,").unwrap();
+    config.set_value("server.admin_token", " ").unwrap();
+
+    assert_eq!(
+        config.cache.intervals,
+        vec![
+            IntervalSettings { interval: 1, retention: None },
+            IntervalSettings { interval: 60, retention: Some(240) },
+        ],
+    );
+    assert_eq!(config.server.admin_token, None);
+}
+
+#[test]
+fn every_validation_failure_reports_its_key() {
+    assert!(ServerConfig::default().validate().is_ok());
+
+    let non_zero = [
+        "server.queue_capacity",
+        "server.max_subscriptions",
+        "server.max_query_bars",
+        "server.max_list_symbols",
+        "server.max_baskets",
+        "server.max_alerts",
+        "server.alert_session_ttl_secs",
+        "server.data_feed_tick_ms",
+        "feed.reconnect_delay_ms",
+        "cache.history_size",
+        "cache.data_history_size",
+        "storage.segment_bytes",
+        "storage.retention_hours",
+        "top_movers.size",
+        "top_movers.window",
+    ];
+
+    for key in non_zero.iter() {
+        let mut config = ServerConfig::default();
+        config.set_value(key, "0").unwrap();
+
+        assert_eq!(invalid_key(config.validate()), *key);
+    }
+
+    let invalid = [
+        ("server.address", "localhost"),
+        ("server.address", ":9002"),
+        ("server.address", "localhost:http"),
+        ("feed.address", "localhost:70000"),
+        ("cache.intervals", ""),
+        ("cache.intervals", "0"),
+        ("cache.intervals", "1:0"),
+        ("cache.intervals", "1,60,1"),
+        ("top_movers.interval", "7"),
+        ("server.ping_interval_ms", "9"),
+    ];
+
+    for (key, value) in invalid.iter() {
+        let mut config = ServerConfig::default();
+        config.set_value(key, value).unwrap();
+
+        let error = config.validate().unwrap_err();
+
+        assert!(error.to_string().contains(key), "{}", error);
+        assert_eq!(invalid_key(Err(error)), *key, "{} = {:?}", key, value);
+    }
+
+    let baskets = [
+        vec![basket("tech", &["AAPL"]), basket("tech", &["MSFT"])],
+        vec![basket("", &["AAPL"])],
+        vec![basket("tech", &[])],
+        vec![basket("tech", &["AAPL", "AAPL"])],
+    ];
+
+    for baskets in baskets.into_iter() {
+        let config = ServerConfig { baskets, ..ServerConfig::default() };
+
+        assert_eq!(invalid_key(config.validate()), "baskets");
+    }
+
+    let mut config = ServerConfig { baskets: vec![basket("tech", &["AAPL"]), basket("banks", &["JPM"])], ..ServerConfig::default() };
+    config.set_value("server.max_baskets", "1").unwrap();
+
+    assert_eq!(invalid_key(config.validate()), "baskets");
+}
+
+#[test]
+fn parses_flags() {
+    let cli_args = CliArgs::parse(args(&[
+        "--config=server.toml",
+        "--server-address",
+        "0.0.0.0:9002",
+        "--cache-max-idle-secs=60",
+    ])).unwrap();
+
+    assert_eq!(cli_args.config_path, Some(PathBuf::from("server.toml")));
+    assert_eq!(
+        cli_args.overrides,
+        vec![
+            ("server.address".to_owned(), "0.0.0.0:9002".to_owned()),
+            ("cache.max_idle_secs".to_owned(), "60".to_owned()),
+        ],
+    );
+    assert_eq!(flag_name("server.max_query_bars"), "server-max-query-bars");
+
+    assert!(matches!(CliArgs::parse(args(&["--help"])), Err(ConfigError::HelpRequested)));
+    assert!(matches!(CliArgs::parse(args(&["--server-queue-size", "10"])), Err(ConfigError::UnknownKey(v)) if v == "--server-queue-size"));
+    assert!(matches!(CliArgs::parse(args(&["server-address"])), Err(ConfigError::UnknownKey(v)) if v == "server-address"));
+    assert!(matches!(CliArgs::parse(args(&["--server-address"])), Err(ConfigError::MissingValue(v)) if v == "--server-address"));
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use common::priced_bar;
+use stock_messenger::{
+    value_store::{BarEvent, BarFilter, BarFormat, BasketSpec, BasketWeighting, ParseError},
+    StockInformationCacheInterface,
+    WebSocketServer,
+};
+
+fn data_feed_volume(stock_cache: &StockInformationCacheInterface) -> f64 {
+    let summary: Value = serde_json::from_str(&stock_cache.retrieve_data_events(0)).unwrap();
+
+    summary["volume_n"].as_f64().unwrap()
+}
+
+#[test]
+fn ignores_resent_bars_and_replaces_corrected_ones() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 10)]), 10, false);
+    let stock_name = "AAPL".to_owned();
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("AAPL", 2000, 1, 1.0, 2.0, 10.0)).unwrap();
+
+    assert!(stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0)).unwrap().is_empty());
+
+    let ingest_report = stock_cache.add_json("B;AAPL;1;2;1;2;10;1;2000;1\n".to_owned());
+
+    assert!(ingest_report.accepted.is_empty());
+    assert_eq!(ingest_report.duplicates, 1);
+
+    assert_eq!(
+        stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 25.0)).unwrap(),
+        vec![BarEvent::Correction(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 25.0))],
+    );
+
+    let history = stock_cache.get_history(&stock_name, 1);
+
+    assert_eq!(history.iter().map(|v| (v.timestamp, v.volume)).collect::<Vec<_>>(), vec![(1000, 25.0), (2000, 10.0)]);
+    assert_eq!(data_feed_volume(&stock_cache), 35.0);
+}
+
+#[test]
+fn corrections_of_a_closed_period_keep_its_counters() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 10)]), 10, false);
+
+    stock_cache.add_basket(BasketSpec {
+        name: "TECH".to_owned(),
+        symbols: vec!["AAPL".to_owned()],
+        weighting: BasketWeighting::Equal,
+    });
+
+    let basket_volume = |timestamp: u128| {
+        let (_, event) = stock_cache.retrieve_basket_events(timestamp).pop().unwrap();
+
+        serde_json::from_str::<Value>(&event).unwrap()["volume_n"].as_f64().unwrap()
+    };
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+
+    let summary: Value = serde_json::from_str(&stock_cache.retrieve_data_events(5000)).unwrap();
+
+    assert_eq!(summary["volume_n"], 10.0);
+    assert_eq!(basket_volume(5000), 10.0);
+
+    // The bar is corrected, but its period was already published.
+    assert_eq!(
+        stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 25.0)).unwrap(),
+        vec![BarEvent::Correction(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 25.0))],
+    );
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 5000, 1, 1.0, 2.0, 5.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("AAPL", 5000, 1, 1.0, 2.0, 7.0)).unwrap();
+
+    let summary: Value = serde_json::from_str(&stock_cache.retrieve_data_events(10_000)).unwrap();
+
+    assert_eq!(summary["volume_n"], 7.0);
+    assert_eq!(basket_volume(10_000), 7.0);
+    assert_eq!(stock_cache.get_history(&"AAPL".to_owned(), 1)[0].volume, 25.0);
+}
+
+#[test]
+fn rejects_bars_older_than_the_kept_history() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 3)]), 10, false);
+
+    for i in 1..=4 {
+        stock_cache.add_ohlc(priced_bar("AAPL", i * 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+    }
+
+    assert_eq!(stock_cache.add_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0)), Err(ParseError::Expired(1000)));
+
+    let ingest_report = stock_cache.add_json("B;AAPL;1;2;1;2;10;1;1000;1\n".to_owned());
+
+    assert!(ingest_report.accepted.is_empty());
+    assert_eq!(ingest_report.rejected.len(), 1);
+
+    let history = stock_cache.get_history(&"AAPL".to_owned(), 1);
+
+    assert_eq!(history.iter().map(|v| v.timestamp).collect::<Vec<_>>(), vec![2000, 3000, 4000]);
+    assert_eq!(data_feed_volume(&stock_cache), 40.0);
+}
+
+#[test]
+fn corrections_adjust_coarser_bars() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 20), (10, 5)]), 10, true);
+    let stock_name = "AAPL".to_owned();
+
+    for i in 0..10 {
+        stock_cache.add_ohlc(priced_bar("AAPL", i * 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+    }
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 10_000, 1, 1.0, 2.0, 10.0)).unwrap();
+
+    let mut corrected = priced_bar("AAPL", 9000, 1, 1.0, 2.0, 40.0);
+    corrected.price_close = 3.0;
+
+    let events = stock_cache.add_ohlc(corrected.clone()).unwrap();
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0], BarEvent::Correction(corrected));
+
+    let bar = stock_cache.get_latest(&stock_name, 10).unwrap();
+
+    assert_eq!(events[1], BarEvent::Correction(bar.clone()));
+    assert_eq!((bar.timestamp, bar.volume, bar.trades, bar.price_close), (0, 130.0, 10, 3.0));
+
+    stock_cache.add_ohlc(priced_bar("AAPL", 11_000, 1, 1.0, 2.0, 10.0)).unwrap();
+    assert_eq!(stock_cache.add_ohlc(priced_bar("AAPL", 10_000, 1, 1.0, 2.0, 5.0)).unwrap().len(), 1);
+
+    for i in 12..20 {
+        stock_cache.add_ohlc(priced_bar("AAPL", i * 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+    }
+
+    let bar = stock_cache.get_latest(&stock_name, 10).unwrap();
+
+    assert_eq!((bar.timestamp, bar.volume), (10_000, 95.0));
+}
+
+#[test]
+fn subscribers_receive_correction_events() {
+    let server = WebSocketServer::builder().without_feed().bar_format(BarFormat::V1).build().unwrap();
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+
+    let (id, mut events) = connection_service.add_subscriber();
+    connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default()).unwrap();
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0)).unwrap();
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 1.0, 2.0, 12.0)).unwrap();
+
+    let mut received = Vec::new();
+
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[1], "End of Update");
+
+    let correction: Value = serde_json::from_str(&received[2]).unwrap();
+
+    assert_eq!(correction["type"], "correction");
+    assert_eq!(correction["volume"], 12.0);
+}
+
+#[test]
+fn legacy_corrections_are_flagged() {
+    let correction: Value = serde_json::from_str(&priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0).to_correction_json(BarFormat::Legacy)).unwrap();
+    let bar: Value = serde_json::from_str(&priced_bar("AAPL", 1000, 1, 1.0, 2.0, 10.0).to_json(BarFormat::Legacy)).unwrap();
+
+    assert_eq!(correction["correction"], true);
+    assert_eq!(correction["name"], "AAPL");
+    assert!(bar.get("correction").is_none());
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::collections::HashSet;
+
+use serde_json::Value;
+
+use common::ohlc_model;
+use stock_messenger::{
+    config::ServerConfig,
+    value_store::{BarFilter, EvictionReason, StockInformationCacheInterface},
+    WebSocketServer,
+};
+
+fn stock_cache() -> StockInformationCacheInterface {
+    StockInformationCacheInterface::new([(1, 100)].into_iter().collect(), 10, false)
+}
+
+fn now_ms() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+#[test]
+fn idle_stocks_are_evicted() {
+    let stock_cache = stock_cache();
+
+    stock_cache.add_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+    stock_cache.add_ohlc(ohlc_model("MSFT", 0, 1)).unwrap();
+
+    let now = now_ms();
+
+    assert!(stock_cache.evict_symbols(now, Some(60_000), None, &HashSet::new()).is_empty());
+
+    let last_update = ["AAPL", "MSFT"]
+        .iter()
+        .map(|v| stock_cache.get_last_update(&v.to_string()).unwrap())
+        .max()
+        .unwrap();
+    let evicted = stock_cache.evict_symbols(last_update + 60_001, Some(60_000), None, &HashSet::new());
+
+    assert_eq!(evicted.len(), 2);
+    assert!(evicted.iter().all(|(_, reason)| *reason == EvictionReason::Idle));
+    assert!(stock_cache.get_symbols().is_empty());
+    assert_eq!(stock_cache.memory_usage(), 0);
+}
+
+#[test]
+fn memory_budget_evicts_least_recently_updated() {
+    let stock_cache = stock_cache();
+
+    for (i, stock_name) in ["A", "B", "C"].iter().enumerate() {
+        for timestamp in 0..10 {
+            stock_cache.add_ohlc(ohlc_model(stock_name, timestamp * 1000, 1)).unwrap();
+        }
+
+        if i < 2 {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    }
+
+    // "A" gets a new bar and becomes the most recently updated.
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    stock_cache.add_ohlc(ohlc_model("A", 10_000, 1)).unwrap();
+
+    let budget = stock_cache.memory_usage() - 1;
+    let evicted = stock_cache.evict_symbols(now_ms(), None, Some(budget), &HashSet::new());
+
+    assert_eq!(evicted, vec![("B".to_owned(), EvictionReason::MemoryBudget)]);
+    assert_eq!(stock_cache.get_symbols(), vec!["A".to_owned(), "C".to_owned()]);
+    assert!(stock_cache.memory_usage() <= budget);
+}
+
+#[test]
+fn memory_budget_evicts_subscribed_stocks_last() {
+    let stock_cache = stock_cache();
+
+    for stock_name in ["A", "B", "C"].iter() {
+        for timestamp in 0..10 {
+            stock_cache.add_ohlc(ohlc_model(stock_name, timestamp * 1000, 1)).unwrap();
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+
+    let subscribed = HashSet::from(["A".to_owned(), "B".to_owned()]);
+    let budget = stock_cache.memory_usage() - 1;
+    let evicted = stock_cache.evict_symbols(now_ms(), None, Some(budget), &subscribed);
+
+    assert_eq!(evicted, vec![("C".to_owned(), EvictionReason::MemoryBudget)]);
+
+    let budget = stock_cache.memory_usage() - 1;
+    let evicted = stock_cache.evict_symbols(now_ms(), None, Some(budget), &subscribed);
+
+    assert_eq!(evicted, vec![("A".to_owned(), EvictionReason::MemoryBudget)]);
+}
+
+#[test]
+fn removed_and_evicted_stocks_notify_subscribers() {
+    let mut config = ServerConfig::default();
+    config.feed.enabled = false;
+    config.cache.max_idle_secs = 3600;
+    config.server.admin_token = Some("secret".to_owned());
+
+    let server = WebSocketServer::new(config);
+    let connection_service = server.connection_service();
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+    connection_service.publish_ohlc(ohlc_model("MSFT", 0, 1)).unwrap();
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &"AAPL".to_owned(), &BarFilter::default()).unwrap();
+    connection_service.add_stock_subscription(id, &"MSFT".to_owned(), &BarFilter::default()).unwrap();
+
+    while events.try_recv().is_ok() {}
+
+    assert!(!connection_service.is_admin(None));
+    assert!(!connection_service.is_admin(Some("wrong")));
+    assert!(connection_service.is_admin(Some("secret")));
+
+    assert!(connection_service.remove_symbol(&"AAPL".to_owned()));
+    assert!(!connection_service.remove_symbol(&"AAPL".to_owned()));
+    assert!(!connection_service.stock_cache().has_key(&"AAPL".to_owned()));
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "evicted");
+    assert_eq!(event["symbol"], "AAPL");
+    assert_eq!(event["reason"], "removed");
+    assert_eq!(connection_service.get_subscriptions(id), vec!["MSFT".to_owned()]);
+
+    connection_service.sync_data_events(now_ms() + 3_600_001);
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["symbol"], "MSFT");
+    assert_eq!(event["reason"], "idle");
+    assert!(connection_service.get_subscriptions(id).is_empty());
+    assert!(connection_service.stock_cache().get_symbols().is_empty());
+}
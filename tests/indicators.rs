@@ -0,0 +1,287 @@
+mod common;
+
+use serde_json::Value;
+
+use common::priced_bar;
+use stock_messenger::{
+    config::IntervalSettings,
+    value_store::{IndicatorSeries, IndicatorSpec},
+    websockets::protocol::{bar_filter, ErrorCode},
+    OHLCModel,
+    WebSocketServer,
+};
+
+const CLOSES: [f64; 12] = [10.0, 11.0, 10.5, 12.0, 12.5, 11.5, 13.0, 12.0, 12.5, 14.0, 13.5, 15.0];
+
+fn bars(closes: &[f64]) -> Vec<OHLCModel> {
+    closes.iter().enumerate().map(|(i, v)| priced_bar("AAPL", i as u128 * 1000, 1, *v, *v, 10.0)).collect()
+}
+
+fn series(spec: IndicatorSpec, closes: &[f64]) -> IndicatorSeries {
+    let mut series = IndicatorSeries::new(spec);
+
+    for ohlc_model in bars(closes).iter() {
+        assert!(series.update(ohlc_model, 100));
+    }
+
+    series
+}
+
+fn values(series: &IndicatorSeries, field: usize) -> Vec<f64> {
+    series.points().iter().map(|v| v.values[field]).collect()
+}
+
+fn assert_close(left: &[f64], right: &[f64]) {
+    assert_eq!(left.len(), right.len(), "{:?} != {:?}", left, right);
+
+    for (l, r) in left.iter().zip(right.iter()) {
+        assert!((l - r).abs() < 1e-9, "{:?} != {:?}", left, right);
+    }
+}
+
+fn sma(closes: &[f64], period: usize) -> Vec<f64> {
+    closes.windows(period).map(|v| v.iter().sum::<f64>() / period as f64).collect()
+}
+
+fn ema(xs: &[f64], period: usize) -> Vec<f64> {
+    let alpha = 2.0 / (period as f64 + 1.0);
+    let mut values = vec![xs[..period].iter().sum::<f64>() / period as f64];
+
+    for x in xs[period..].iter() {
+        let last = *values.last().unwrap();
+        values.push(last + alpha * (x - last));
+    }
+
+    values
+}
+
+#[test]
+fn moving_averages_match_their_definition() {
+    let series_sma = series(IndicatorSpec::Sma { period: 4 }, &CLOSES);
+
+    assert_close(&values(&series_sma, 0), &sma(&CLOSES, 4));
+    assert_eq!(series_sma.points()[0].timestamp, 3000);
+
+    assert_close(&values(&series(IndicatorSpec::Ema { period: 4 }, &CLOSES), 0), &ema(&CLOSES, 4));
+
+    let macd_series = series(IndicatorSpec::Macd { fast: 3, slow: 5, signal: 3 }, &CLOSES);
+    let fast = ema(&CLOSES, 3);
+    let slow = ema(&CLOSES, 5);
+    let macd: Vec<f64> = slow.iter().zip(fast[2..].iter()).map(|(s, f)| f - s).collect();
+    let signal = ema(&macd, 3);
+
+    assert_close(&values(&macd_series, 0), &macd[2..]);
+    assert_close(&values(&macd_series, 1), &signal);
+    assert_close(&values(&macd_series, 2), &macd[2..].iter().zip(signal.iter()).map(|(m, s)| m - s).collect::<Vec<f64>>());
+}
+
+#[test]
+fn rsi_and_bollinger_match_their_definition() {
+    let rsi_series = series(IndicatorSpec::Rsi { period: 3 }, &CLOSES);
+    let changes: Vec<f64> = CLOSES.windows(2).map(|v| v[1] - v[0]).collect();
+
+    let mut gain = changes[..3].iter().map(|v| v.max(0.0)).sum::<f64>() / 3.0;
+    let mut loss = changes[..3].iter().map(|v| (-v).max(0.0)).sum::<f64>() / 3.0;
+    let mut rsi = vec![100.0 - 100.0 / (1.0 + gain / loss)];
+
+    for change in changes[3..].iter() {
+        gain = (gain * 2.0 + change.max(0.0)) / 3.0;
+        loss = (loss * 2.0 + (-change).max(0.0)) / 3.0;
+        rsi.push(100.0 - 100.0 / (1.0 + gain / loss));
+    }
+
+    assert_close(&values(&rsi_series, 0), &rsi);
+    assert_close(&values(&series(IndicatorSpec::Rsi { period: 2 }, &[1.0, 2.0, 3.0]), 0), &[100.0]);
+
+    let bollinger_series = series(IndicatorSpec::Bollinger { period: 5, width: 2.0 }, &CLOSES);
+    let middle = sma(&CLOSES, 5);
+    let deviation: Vec<f64> = CLOSES
+        .windows(5)
+        .zip(middle.iter())
+        .map(|(v, m)| (v.iter().map(|x| (x - m).powi(2)).sum::<f64>() / 5.0).sqrt() * 2.0)
+        .collect();
+
+    assert_close(&values(&bollinger_series, 0), &middle);
+    assert_close(&values(&bollinger_series, 1), &middle.iter().zip(deviation.iter()).map(|(m, d)| m + d).collect::<Vec<f64>>());
+    assert_close(&values(&bollinger_series, 2), &middle.iter().zip(deviation.iter()).map(|(m, d)| m - d).collect::<Vec<f64>>());
+}
+
+#[test]
+fn replaced_and_late_bars_are_applied() {
+    let spec = IndicatorSpec::Ema { period: 3 };
+    let mut series = series(spec.clone(), &CLOSES);
+
+    // A correction of the latest bar redoes the last step.
+    let mut corrected = CLOSES;
+    corrected[11] = 9.0;
+
+    assert!(series.update(&priced_bar("AAPL", 11_000, 1, 9.0, 9.0, 10.0), 100));
+    assert_close(&values(&series, 0), &ema(&corrected, 3));
+
+    // An older bar can't be applied incrementally.
+    corrected[4] = 20.0;
+
+    assert!(!series.update(&priced_bar("AAPL", 4000, 1, 20.0, 20.0, 10.0), 100));
+
+    series.rebuild(bars(&corrected).iter(), 5);
+
+    assert_close(&values(&series, 0), &ema(&corrected, 3)[5..]);
+    assert_eq!(series.points().len(), 5);
+
+    let specs = [
+        (r#"{"kind": "ema", "period": 20}"#, "ema(20)"),
+        (r#"{"kind": "rsi"}"#, "rsi(14)"),
+        (r#"{"kind": "macd"}"#, "macd(12,26,9)"),
+        (r#"{"kind": "bollinger", "width": 2.5}"#, "bollinger(20,2.5)"),
+    ];
+
+    for (json, key) in specs.into_iter() {
+        assert_eq!(serde_json::from_str::<IndicatorSpec>(json).unwrap().key(), key);
+    }
+
+    assert!(IndicatorSpec::Sma { period: 0 }.validate().is_err());
+    assert!(IndicatorSpec::Macd { fast: 26, slow: 12, signal: 9 }.validate().is_err());
+    assert!(IndicatorSpec::Bollinger { period: 20, width: 0.0 }.validate().is_err());
+}
+
+#[test]
+fn subscribers_get_snapshot_and_live_points() {
+    let server = WebSocketServer::builder().without_feed().build().unwrap();
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+    let spec = IndicatorSpec::Sma { period: 3 };
+
+    for ohlc_model in bars(&CLOSES[..5]).into_iter() {
+        connection_service.publish_ohlc(ohlc_model).unwrap();
+    }
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    connection_service.add_indicator_subscription(id, &stock_name, 1, &spec).unwrap();
+    connection_service.add_indicator_subscription(id, &stock_name, 1, &spec).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "indicator_snapshot");
+    assert_eq!(event["indicator"], "sma(3)");
+    assert_eq!(event["interval"], 1);
+    assert_eq!(event["points"].as_array().unwrap().len(), 3);
+    assert_eq!(event["points"][0]["timestamp"], 2000);
+    assert!(events.try_recv().is_err());
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 5000, 1, 11.5, 11.5, 10.0)).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+    let expected = sma(&CLOSES[..6], 3);
+
+    assert_eq!(event["type"], "indicator");
+    assert_eq!(event["point"]["timestamp"], 5000);
+    assert!((event["point"]["value"].as_f64().unwrap() - expected[3]).abs() < 1e-9);
+
+    // A correction republishes the point of the corrected bar.
+    connection_service.publish_ohlc(priced_bar("AAPL", 5000, 1, 14.5, 14.5, 10.0)).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert!((event["point"]["value"].as_f64().unwrap() - 13.0).abs() < 1e-9);
+    assert_eq!(
+        connection_service.get_indicator_subscriptions(id),
+        vec![(stock_name.clone(), 1, "sma(3)".to_owned())],
+    );
+
+    assert!(connection_service.remove_indicator_subscription(id, &stock_name, 1, &spec));
+    assert!(!connection_service.remove_indicator_subscription(id, &stock_name, 1, &spec));
+    assert!(connection_service.stock_cache().get_indicator_points(&stock_name, 1, &"sma(3)".to_owned()).is_empty());
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 6000, 1, 13.0, 13.0, 10.0)).unwrap();
+
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn snapshots_that_dont_fit_are_refused() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .queue_capacity(2)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+    let spec = IndicatorSpec::Sma { period: 3 };
+
+    for ohlc_model in bars(&CLOSES[..5]).into_iter() {
+        connection_service.publish_ohlc(ohlc_model).unwrap();
+    }
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    // The last bar and "End of Update" fill the queue.
+    connection_service.add_stock_subscription(id, &stock_name, &bar_filter(&Some(vec![1]), &Some(1), false)).unwrap();
+
+    assert_eq!(
+        connection_service.add_indicator_subscription(id, &stock_name, 1, &spec),
+        Err(ErrorCode::SnapshotTooLarge),
+    );
+    assert!(connection_service.get_indicator_subscriptions(id).is_empty());
+    assert!(connection_service.stock_cache().get_indicator_points(&stock_name, 1, &"sma(3)".to_owned()).is_empty());
+
+    while events.try_recv().is_ok() {}
+
+    connection_service.add_indicator_subscription(id, &stock_name, 1, &spec).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "indicator_snapshot");
+    assert_eq!(event["points"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn unknown_symbols_and_intervals_are_rejected() {
+    let server = WebSocketServer::builder().without_feed().build().unwrap();
+    let connection_service = server.connection_service();
+    let spec = IndicatorSpec::Ema { period: 3 };
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 1.0, 1.0, 10.0)).unwrap();
+
+    let (id, _events) = connection_service.add_subscriber();
+
+    assert!(connection_service.add_indicator_subscription(id, &"MSFT".to_owned(), 1, &spec).is_err());
+    assert!(connection_service.add_indicator_subscription(id, &"AAPL".to_owned(), 7, &spec).is_err());
+    assert!(connection_service.get_indicator_subscriptions(id).is_empty());
+}
+
+#[test]
+fn periods_longer_than_the_retention_are_rejected() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .intervals(vec![IntervalSettings { interval: 1, retention: Some(20) }])
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 1.0, 1.0, 10.0)).unwrap();
+
+    let (id, _events) = connection_service.add_subscriber();
+
+    assert_eq!(
+        connection_service.add_indicator_subscription(id, &stock_name, 1, &IndicatorSpec::Sma { period: 21 }),
+        Err(ErrorCode::PeriodTooLong),
+    );
+    assert_eq!(
+        connection_service.add_indicator_subscription(id, &stock_name, 1, &IndicatorSpec::Macd { fast: 12, slow: 26, signal: 9 }),
+        Err(ErrorCode::PeriodTooLong),
+    );
+    assert_eq!(
+        connection_service.add_indicator_subscription(id, &stock_name, 1, &IndicatorSpec::Macd { fast: 2, slow: 11, signal: 11 }),
+        Err(ErrorCode::PeriodTooLong),
+    );
+    assert!(connection_service.get_indicator_subscriptions(id).is_empty());
+
+    connection_service.add_indicator_subscription(id, &stock_name, 1, &IndicatorSpec::Sma { period: 20 }).unwrap();
+    connection_service.add_indicator_subscription(id, &stock_name, 1, &IndicatorSpec::Macd { fast: 2, slow: 11, signal: 10 }).unwrap();
+
+    assert_eq!(ErrorCode::PeriodTooLong.as_str(), "period_too_long");
+}
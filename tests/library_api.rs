@@ -0,0 +1,31 @@
+mod common;
+
+use common::priced_bar;
+use stock_messenger::WebSocketServer;
+
+#[test]
+fn published_bars_are_cached() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .history_size(2)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+
+    for (i, price) in [1.0, 2.0, 3.0].iter().enumerate() {
+        connection_service.publish_ohlc(priced_bar("AAPL", i as u128 * 1000, 1, *price, *price, 10.0)).unwrap();
+    }
+
+    let stock_cache = connection_service.stock_cache();
+    let history = stock_cache.get_history(&"AAPL".to_owned(), 1);
+
+    assert_eq!(stock_cache.get_symbols(), vec!["AAPL".to_owned()]);
+    assert_eq!(history.iter().map(|v| v.price_close).collect::<Vec<f64>>(), vec![2.0, 3.0]);
+    assert_eq!(stock_cache.get_latest(&"AAPL".to_owned(), 1).unwrap().timestamp, 2000);
+}
+
+#[test]
+fn builder_rejects_invalid_configuration() {
+    assert!(WebSocketServer::builder().history_size(0).build().is_err());
+}
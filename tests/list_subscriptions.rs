@@ -0,0 +1,70 @@
+mod common;
+
+use serde_json::{json, Value};
+use tungstenite::Message;
+
+use common::{connect_client, spawn_server, Client};
+use stock_messenger::WebSocketServer;
+
+/// Sends a control request and returns its response, skipping the pushed events.
+fn request(client: &mut Client, id: u64, request: Value) -> Value {
+    let mut request = request;
+    request["v"] = json!(1);
+    request["id"] = json!(id);
+
+    client.send(Message::Text(request.to_string())).unwrap();
+
+    loop {
+        let text = match client.read().unwrap() {
+            Message::Text(v) => v,
+            _ => continue,
+        };
+
+        match serde_json::from_str::<Value>(&text) {
+            Ok(v) if v["type"] == "response" && v["id"] == id => return v,
+            _ => continue,
+        };
+    }
+}
+
+#[test]
+fn lists_the_subscriptions_of_the_connection() {
+    let server = WebSocketServer::builder()
+        .server_address("127.0.0.1:0")
+        .without_feed()
+        .build()
+        .unwrap();
+
+    let shutdown = server.shutdown_handle();
+    let (server, server_thread) = spawn_server(server);
+    let mut client = connect_client(&server);
+
+    let response = request(&mut client, 1, json!({ "action": "list_subscriptions" }));
+
+    assert_eq!(response["status"], "ok");
+    assert_eq!(response["result"], json!({ "symbols": [], "indicators": [] }));
+
+    let subscriptions = [
+        json!({ "action": "subscribe", "symbol": "DataFeed" }),
+        json!({ "action": "subscribe", "symbol": "AAPL", "intervals": [60], "depth": 10 }),
+        json!({ "action": "subscribe", "symbol": "MSFT", "session": true }),
+    ];
+
+    for (i, subscription) in subscriptions.into_iter().enumerate() {
+        assert_eq!(request(&mut client, 2 + i as u64, subscription)["status"], "ok");
+    }
+
+    let response = request(&mut client, 5, json!({ "action": "list_subscriptions" }));
+
+    assert_eq!(response["action"], "list_subscriptions");
+    assert_eq!(response["result"]["symbols"], json!(["AAPL", "DataFeed", "MSFT"]));
+
+    assert_eq!(request(&mut client, 6, json!({ "action": "unsubscribe", "symbol": "AAPL" }))["status"], "ok");
+
+    let response = request(&mut client, 7, json!({ "action": "list_subscriptions" }));
+
+    assert_eq!(response["result"]["symbols"], json!(["DataFeed", "MSFT"]));
+
+    shutdown.shutdown();
+    server_thread.join().unwrap();
+}
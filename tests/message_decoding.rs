@@ -0,0 +1,266 @@
+use serde_json::{json, Value};
+
+use stock_messenger::{
+    value_store::{AlertRule, BasketWeighting, IndicatorSpec},
+    websockets::protocol::{
+        decode_message,
+        ClientMessage,
+        ControlAction,
+        ControlRequest,
+        ErrorCode,
+        LegacyRequest,
+        ACTION_NAMES,
+    },
+};
+
+fn control(id: Value, action: ControlAction) -> ClientMessage {
+    ClientMessage::Control(ControlRequest { id, action })
+}
+
+fn subscribe(symbol: &str) -> ControlAction {
+    ControlAction::Subscribe { symbol: symbol.to_owned(), intervals: None, depth: None, session: false }
+}
+
+#[test]
+fn decodes_valid_messages() {
+    let corpus: Vec<(&str, ClientMessage)> = vec![
+        (
+            r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": "AAPL"}"#,
+            control(json!("1"), subscribe("AAPL")),
+        ),
+        (
+            r#"{"v":1,"id":2,"action":"unsubscribe","symbol":"AAPL"}"#,
+            control(json!(2), ControlAction::Unsubscribe { symbol: "AAPL".to_owned() }),
+        ),
+        (
+            r#"{"action": "ping", "id": "x", "v": 1}"#,
+            control(json!("x"), ControlAction::Ping),
+        ),
+        (
+            "{\n\t\"v\": 1,\n\t\"id\": \"3\",\n\t\"action\": \"list_subscriptions\"\n}",
+            control(json!("3"), ControlAction::ListSubscriptions),
+        ),
+        (
+            r#"{"v": 1, "id": "4", "action": "subscribe", "symbol": "BRK B"}"#,
+            control(json!("4"), subscribe("BRK B")),
+        ),
+        (
+            r#"{"v": 1, "id": "5", "action": "subscribe", "symbol": "A,B:C"}"#,
+            control(json!("5"), subscribe("A,B:C")),
+        ),
+        (
+            r#"{"v": 1, "id": "6", "action": "subscribe", "symbol": "{\"quoted\"}"}"#,
+            control(json!("6"), subscribe("{\"quoted\"}")),
+        ),
+        (
+            r#"{"v": 1, "id": "7", "action": "subscribe", "symbol": "ÄBC.DE"}"#,
+            control(json!("7"), subscribe("ÄBC.DE")),
+        ),
+        (
+            r#"{"v": 1, "id": "id, with: punctuation", "action": "ping"}"#,
+            control(json!("id, with: punctuation"), ControlAction::Ping),
+        ),
+        (
+            r#"{"v": 1, "id": "8", "action": "subscribe", "symbol": "AAPL", "meta": {"nested": [1, 2, {"a": "b,c"}]}}"#,
+            control(json!("8"), subscribe("AAPL")),
+        ),
+        (
+            r#"{"v": 1, "id": "9", "action": "subscribe", "symbol": "AAPL", "intervals": [1, 60], "depth": 30}"#,
+            control(json!("9"), ControlAction::Subscribe {
+                symbol: "AAPL".to_owned(),
+                intervals: Some(vec![1, 60]),
+                depth: Some(30),
+                session: false,
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": 10, "action": "query_range", "symbol": "AAPL", "interval": 60, "from": 0, "to": 60000}"#,
+            control(json!(10), ControlAction::QueryRange {
+                symbol: "AAPL".to_owned(),
+                interval: 60,
+                from: 0,
+                to: 60000,
+                limit: None,
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": "11", "action": "remove_symbol", "symbol": "AAPL", "token": "secret"}"#,
+            control(json!("11"), ControlAction::RemoveSymbol {
+                symbol: "AAPL".to_owned(),
+                token: Some("secret".to_owned()),
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": "12", "action": "get_reference", "symbol": "AAPL"}"#,
+            control(json!("12"), ControlAction::GetReference { symbol: "AAPL".to_owned() }),
+        ),
+        (
+            r#"{"v": 1, "id": "13", "action": "reload_reference_data"}"#,
+            control(json!("13"), ControlAction::ReloadReferenceData { token: None }),
+        ),
+        (
+            r#"{"v": 1, "id": "14", "action": "list_symbols", "prefix": "AA", "limit": 5}"#,
+            control(json!("14"), ControlAction::ListSymbols {
+                prefix: Some("AA".to_owned()),
+                contains: None,
+                offset: 0,
+                limit: Some(5),
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": "15", "action": "subscribe_indicator", "symbol": "AAPL", "interval": 60, "indicator": {"kind": "ema", "period": 20}}"#,
+            control(json!("15"), ControlAction::SubscribeIndicator {
+                symbol: "AAPL".to_owned(),
+                interval: 60,
+                indicator: IndicatorSpec::Ema { period: 20 },
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": "16", "action": "create_basket", "name": "TECH", "symbols": ["AAPL", "MSFT"], "token": "t"}"#,
+            control(json!("16"), ControlAction::CreateBasket {
+                name: "TECH".to_owned(),
+                symbols: vec!["AAPL".to_owned(), "MSFT".to_owned()],
+                weighting: BasketWeighting::Equal,
+                token: Some("t".to_owned()),
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": "17", "action": "create_alert", "rule": {"kind": "volume_above", "symbol": "AAPL", "volume": 1000}}"#,
+            control(json!("17"), ControlAction::CreateAlert {
+                rule: AlertRule::VolumeAbove { symbol: "AAPL".to_owned(), volume: 1000.0, interval: None },
+            }),
+        ),
+        (
+            r#"{"v": 1, "id": "18", "action": "resume_session", "token": "abc"}"#,
+            control(json!("18"), ControlAction::ResumeSession { token: "abc".to_owned() }),
+        ),
+        (
+            r#"{"stock": "AAPL"}"#,
+            ClientMessage::Legacy(LegacyRequest::Stock("AAPL".to_owned())),
+        ),
+        (
+            r#"{ "stock" : "DataFeed" }"#,
+            ClientMessage::Legacy(LegacyRequest::Stock("DataFeed".to_owned())),
+        ),
+        (
+            r#"{"subscribe": "BRK B"}"#,
+            ClientMessage::Legacy(LegacyRequest::Subscribe("BRK B".to_owned())),
+        ),
+        (
+            r#"{"unsubscribe": "A,B"}"#,
+            ClientMessage::Legacy(LegacyRequest::Unsubscribe("A,B".to_owned())),
+        ),
+    ];
+
+    for (input, expected) in corpus.into_iter() {
+        assert_eq!(decode_message(input), Ok(expected), "input: {}", input);
+    }
+}
+
+#[test]
+fn rejects_invalid_messages() {
+    let too_long = format!(r#"{{"v": 1, "id": "1", "action": "subscribe", "symbol": "{}"}}"#, "A".repeat(65));
+
+    let corpus: Vec<(&str, ErrorCode, Option<Value>)> = vec![
+        ("", ErrorCode::MalformedRequest, None),
+        ("AAPL", ErrorCode::MalformedRequest, None),
+        ("{\"stock\": \"AAPL\"", ErrorCode::MalformedRequest, None),
+        ("[\"stock\", \"AAPL\"]", ErrorCode::MalformedRequest, None),
+        ("\"stock\"", ErrorCode::MalformedRequest, None),
+        ("null", ErrorCode::MalformedRequest, None),
+        ("{}", ErrorCode::MalformedRequest, None),
+        (r#"{"stock": ["AAPL", "MSFT"]}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stock": {"name": "AAPL"}}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stock": 42}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stock": ""}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stock": "   "}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stock": "AA\nPL"}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stock": "AAPL", "subscribe": "MSFT"}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"stok": "AAPL"}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"action": "ping", "id": "1"}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": "1", "action": "ping", "id": "1"}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 2, "action": "ping", "id": "1"}"#, ErrorCode::UnsupportedVersion, Some(json!("1"))),
+        (r#"{"v": 1, "action": "ping"}"#, ErrorCode::MalformedRequest, None),
+        (r#"{"v": 1, "action": "ping", "id": ""}"#, ErrorCode::MalformedRequest, Some(json!(""))),
+        (r#"{"v": 1, "action": "ping", "id": 1.5}"#, ErrorCode::MalformedRequest, Some(json!(1.5))),
+        (r#"{"v": 1, "action": "ping", "id": {"a": 1}}"#, ErrorCode::MalformedRequest, Some(json!({"a": 1}))),
+        (r#"{"v": 1, "id": "1"}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "dance"}"#, ErrorCode::UnknownAction, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": ["subscribe"]}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe"}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": 5}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": ["AAPL"]}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": ""}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": "AAPL", "intervals": []}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": "AAPL", "intervals": [0]}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": "AAPL", "intervals": 60}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe", "symbol": "AAPL", "depth": -1}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "query_range", "symbol": "AAPL", "interval": 60, "from": 0}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "query_range", "symbol": "AAPL", "interval": 60, "from": 2, "to": 1}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "query_range", "symbol": "AAPL", "interval": 0, "from": 0, "to": 1}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "query_range", "symbol": "AAPL", "interval": 60, "from": 0, "to": 1, "limit": 0}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "remove_symbol", "symbol": ""}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "list_symbols", "limit": 0}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe_indicator", "symbol": "AAPL", "interval": 60}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe_indicator", "symbol": "AAPL", "interval": 60, "indicator": {"kind": "vwma"}}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "subscribe_indicator", "symbol": "AAPL", "interval": 60, "indicator": {"kind": "sma", "period": 0}}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "list_symbols", "offset": -1}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "create_basket", "name": "TECH", "symbols": []}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "create_alert", "rule": {"kind": "percent_move", "symbol": "AAPL", "percent": 0, "window_secs": 60}}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "create_alert", "rule": {"kind": "price_cross", "symbol": "AAPL", "direction": "sideways", "level": 1}}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "resume_session", "token": ""}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "create_basket", "name": "TECH", "symbols": ["AAPL", "AAPL"]}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (r#"{"v": 1, "id": "1", "action": "create_basket", "name": "TECH", "symbols": ["AAPL"], "weighting": "cap"}"#, ErrorCode::MalformedRequest, Some(json!("1"))),
+        (&too_long, ErrorCode::MalformedRequest, Some(json!("1"))),
+    ];
+
+    for (input, code, id) in corpus.into_iter() {
+        let error = decode_message(input).expect_err(input);
+
+        assert_eq!(error.error.code, code, "input: {}", input);
+        assert_eq!(error.id, id, "input: {}", input);
+    }
+}
+
+#[test]
+fn every_action_name_is_known() {
+    let symbol = "AAPL".to_owned();
+    let indicator = IndicatorSpec::Ema { period: 20 };
+    let actions = vec![
+        subscribe("AAPL"),
+        ControlAction::Unsubscribe { symbol: symbol.clone() },
+        ControlAction::ListSubscriptions,
+        ControlAction::Ping,
+        ControlAction::QueryRange { symbol: symbol.clone(), interval: 60, from: 0, to: 1, limit: None },
+        ControlAction::RemoveSymbol { symbol: symbol.clone(), token: None },
+        ControlAction::GetReference { symbol: symbol.clone() },
+        ControlAction::ReloadReferenceData { token: None },
+        ControlAction::ListSymbols { prefix: None, contains: None, offset: 0, limit: None },
+        ControlAction::GetSession { symbol: symbol.clone() },
+        ControlAction::SubscribeIndicator { symbol: symbol.clone(), interval: 60, indicator: indicator.clone() },
+        ControlAction::UnsubscribeIndicator { symbol: symbol.clone(), interval: 60, indicator },
+        ControlAction::CreateBasket { name: "TECH".to_owned(), symbols: vec![symbol.clone()], weighting: BasketWeighting::Equal, token: None },
+        ControlAction::DeleteBasket { name: "TECH".to_owned(), token: None },
+        ControlAction::ListBaskets,
+        ControlAction::CreateAlert { rule: AlertRule::VolumeAbove { symbol, volume: 1000.0, interval: None } },
+        ControlAction::DeleteAlert { alert_id: 1 },
+        ControlAction::ListAlerts,
+        ControlAction::ResumeSession { token: "t".to_owned() },
+    ];
+
+    assert_eq!(actions.len(), ACTION_NAMES.len());
+
+    for action in actions.iter() {
+        assert!(ACTION_NAMES.contains(&action.name()), "{} isn't in ACTION_NAMES", action.name());
+    }
+
+    for name in ACTION_NAMES.iter() {
+        let input = json!({ "v": 1, "id": "1", "action": name }).to_string();
+
+        match decode_message(&input) {
+            Ok(ClientMessage::Control(request)) => assert_eq!(request.action.name(), *name),
+            Ok(_) => panic!("{} decoded as a legacy request", input),
+            Err(e) => assert_eq!(e.error.code, ErrorCode::MalformedRequest, "input: {}", input),
+        };
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use serde_json::Value;
+
+use common::ohlc_model;
+use stock_messenger::{
+    value_store::{BarFilter, BarFormat},
+    websockets::SubscriptionState,
+    WebSocketServer,
+};
+
+#[test]
+fn pending_subscription_goes_live_with_the_first_bar() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .bar_format(BarFormat::V1)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    assert_eq!(
+        connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default()),
+        Ok(SubscriptionState::Pending),
+    );
+    assert_eq!(
+        connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default()),
+        Ok(SubscriptionState::Pending),
+    );
+    assert_eq!(connection_service.get_subscriptions(id), vec![stock_name.clone()]);
+    assert!(events.try_recv().is_err());
+
+    connection_service.publish_ohlc(ohlc_model("MSFT", 0, 1)).unwrap();
+
+    assert!(events.try_recv().is_err());
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "subscription");
+    assert_eq!(event["symbol"], "AAPL");
+    assert_eq!(event["state"], "active");
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "bar");
+    assert_eq!(event["timestamp"], 0);
+    assert_eq!(events.try_recv().unwrap(), "End of Update");
+    assert!(events.try_recv().is_err());
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 1000, 1)).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["timestamp"], 1000);
+    assert_eq!(
+        connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default()),
+        Ok(SubscriptionState::Active),
+    );
+}
+
+#[test]
+fn pending_subscriptions_can_be_dropped() {
+    let server = WebSocketServer::builder().without_feed().build().unwrap();
+    let connection_service = server.connection_service();
+
+    let (id, mut events) = connection_service.add_subscriber();
+    let (other_id, _other_events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &"AAPL".to_owned(), &BarFilter::default()).unwrap();
+    connection_service.add_stock_subscription(other_id, &"AAPL".to_owned(), &BarFilter::default()).unwrap();
+
+    assert!(connection_service.remove_stock_subscription(id, &"AAPL".to_owned()));
+    assert!(!connection_service.remove_stock_subscription(id, &"AAPL".to_owned()));
+
+    connection_service.remove_subscriber(other_id);
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+
+    assert!(events.try_recv().is_err());
+    assert!(connection_service.get_subscribers(&"AAPL".to_owned()).is_empty());
+}
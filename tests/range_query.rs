@@ -0,0 +1,39 @@
+mod common;
+
+use common::ohlc_model;
+use stock_messenger::{websockets::protocol::ErrorCode, OHLCModel, ServerConfig, WebSocketServer};
+
+#[test]
+fn returns_bars_between_timestamps() {
+    let mut config = ServerConfig::default();
+
+    config.feed.enabled = false;
+    config.server.max_query_bars = 3;
+
+    let server = WebSocketServer::builder().config(config).build().unwrap();
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+
+    for i in 0..10 {
+        connection_service.publish_ohlc(OHLCModel { price_close: (i * 60_000) as f64, ..ohlc_model("AAPL", i * 60_000, 60) }).unwrap();
+    }
+
+    let timestamps = |bars: Vec<OHLCModel>| bars.iter().map(|v| v.timestamp).collect::<Vec<u128>>();
+
+    assert_eq!(
+        timestamps(connection_service.query_range(&stock_name, 60, 60_000, 120_000, None).unwrap()),
+        vec![60_000, 120_000],
+    );
+    assert_eq!(
+        timestamps(connection_service.query_range(&stock_name, 60, 0, 540_000, Some(2)).unwrap()),
+        vec![480_000, 540_000],
+    );
+    assert_eq!(
+        timestamps(connection_service.query_range(&stock_name, 60, 0, 540_000, Some(100)).unwrap()),
+        vec![420_000, 480_000, 540_000],
+    );
+    assert!(connection_service.query_range(&stock_name, 60, 600_000, 700_000, None).unwrap().is_empty());
+
+    assert_eq!(connection_service.query_range(&stock_name, 7, 0, 1, None), Err(ErrorCode::UnknownInterval));
+    assert_eq!(connection_service.query_range(&"MSFT".to_owned(), 60, 0, 1, None), Err(ErrorCode::UnknownSymbol));
+}
@@ -0,0 +1,152 @@
+use std::{fs, process};
+
+use serde_json::Value;
+
+use stock_messenger::{
+    value_store::{reference_data, BarFilter, BarFormat, ReferenceData, SymbolInfo},
+    OHLCModel,
+    WebSocketServer,
+};
+
+#[test]
+fn parses_csv_with_quotes_and_any_column_order() {
+    let content = "exchange,symbol,name,extra\r\n\
+                   NASDAQ,AAPL,Apple Inc.,x\n\
+                   \n\
+                   NYSE,BRK B,\"Berkshire Hathaway, \"\"B\"\"\",\n\
+                   ,MSFT\n";
+
+    let symbols = reference_data::parse_csv(content).unwrap();
+
+    assert_eq!(symbols, vec![
+        SymbolInfo {
+            symbol: "AAPL".to_owned(),
+            name: Some("Apple Inc.".to_owned()),
+            exchange: Some("NASDAQ".to_owned()),
+            ..SymbolInfo::default()
+        },
+        SymbolInfo {
+            symbol: "BRK B".to_owned(),
+            name: Some("Berkshire Hathaway, \"B\"".to_owned()),
+            exchange: Some("NYSE".to_owned()),
+            ..SymbolInfo::default()
+        },
+        SymbolInfo {
+            symbol: "MSFT".to_owned(),
+            ..SymbolInfo::default()
+        },
+    ]);
+
+    assert!(reference_data::parse_csv("name\nApple").is_err());
+    assert!(reference_data::parse_csv("symbol,name\n,Apple").is_err());
+    assert!(reference_data::parse_csv("symbol,name\nAAPL,\"Apple").is_err());
+}
+
+#[test]
+fn loads_and_reloads_files() {
+    let path = std::env::temp_dir().join(format!("stock_messenger_reference_{}.json", process::id()));
+    let reference_data = ReferenceData::new();
+
+    assert!(reference_data.reload().is_err());
+
+    fs::write(&path, r#"[{"symbol": "AAPL", "name": "Apple Inc.", "currency": "USD"}]"#).unwrap();
+
+    assert_eq!(reference_data.load(&path).unwrap(), 1);
+    assert_eq!(reference_data.get(&"AAPL".to_owned()).unwrap().currency.as_deref(), Some("USD"));
+
+    fs::write(&path, r#"[{"symbol": "AAPL", "sector": "Technology"}, {"symbol": "MSFT"}]"#).unwrap();
+
+    assert_eq!(reference_data.reload().unwrap(), 2);
+    assert_eq!(reference_data.get(&"AAPL".to_owned()).unwrap().currency, None);
+    assert_eq!(reference_data.get(&"AAPL".to_owned()).unwrap().sector.as_deref(), Some("Technology"));
+
+    fs::write(&path, "not json").unwrap();
+
+    assert!(reference_data.reload().is_err());
+    assert_eq!(reference_data.len(), 2);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn subscription_snapshot_starts_with_reference_data() {
+    let path = std::env::temp_dir().join(format!("stock_messenger_reference_{}.csv", process::id()));
+    fs::write(&path, "symbol,name,currency\nAAPL,Apple Inc.,USD\n").unwrap();
+
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .bar_format(BarFormat::V1)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    connection_service.reference_data().load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    for stock_name in ["AAPL", "MSFT"] {
+        connection_service.publish_ohlc(OHLCModel {
+            stock_name: stock_name.to_owned(),
+            stock_interval: 1,
+            ..OHLCModel::new()
+        }).unwrap();
+    }
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &"AAPL".to_owned(), &BarFilter::default()).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "reference");
+    assert_eq!(event["symbol"], "AAPL");
+    assert_eq!(event["reference"]["name"], "Apple Inc.");
+    assert_eq!(event["reference"]["currency"], "USD");
+    assert!(event["reference"].get("sector").is_none());
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "bar");
+    assert_eq!(events.try_recv().unwrap(), "End of Update");
+
+    connection_service.add_stock_subscription(id, &"MSFT".to_owned(), &BarFilter::default()).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "bar");
+}
+
+#[test]
+fn legacy_snapshot_gets_reference_data_too() {
+    let path = std::env::temp_dir().join(format!("stock_messenger_reference_legacy_{}.csv", process::id()));
+    fs::write(&path, "symbol,name\nAAPL,Apple Inc.\n").unwrap();
+
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .bar_format(BarFormat::Legacy)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    connection_service.reference_data().load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    connection_service.publish_ohlc(OHLCModel {
+        stock_name: "AAPL".to_owned(),
+        stock_interval: 1,
+        ..OHLCModel::new()
+    }).unwrap();
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &"AAPL".to_owned(), &BarFilter::default()).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "reference");
+    assert_eq!(event["reference"]["name"], "Apple Inc.");
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["name"], "AAPL");
+    assert_eq!(events.try_recv().unwrap(), "End of Update");
+}
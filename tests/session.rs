@@ -0,0 +1,164 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use common::priced_bar;
+use stock_messenger::{
+    config::ServerConfig,
+    value_store::{BarFilter, SessionBoundary},
+    StockInformationCacheInterface,
+    WebSocketServer,
+};
+
+const DAY_MS: u128 = 24 * 3_600_000;
+const HOUR_MS: u128 = 3_600_000;
+
+fn new_stock_cache() -> StockInformationCacheInterface {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 100), (60, 100)]), 10, false);
+    stock_cache.set_session_boundary("13:30".parse().unwrap());
+
+    stock_cache
+}
+
+#[test]
+fn parses_session_boundaries() {
+    let boundary: SessionBoundary = "13:30".parse().unwrap();
+
+    assert_eq!(boundary.to_string(), "13:30");
+    assert_eq!(SessionBoundary::default().to_string(), "00:00");
+    assert!("24:00".parse::<SessionBoundary>().is_err());
+    assert!("9".parse::<SessionBoundary>().is_err());
+
+    let open = 10 * DAY_MS + 13 * HOUR_MS + 30 * 60_000;
+
+    assert_eq!(boundary.session_start(open), open);
+    assert_eq!(boundary.session_start(open + 5 * HOUR_MS), open);
+    assert_eq!(boundary.session_start(open - 1), open - DAY_MS);
+}
+
+#[test]
+fn computes_vwap_and_twap_over_the_session() {
+    let stock_cache = new_stock_cache();
+    let name = "AAPL".to_owned();
+    let open = 10 * DAY_MS + 13 * HOUR_MS + 30 * 60_000;
+
+    assert!(stock_cache.get_session(&name).is_none());
+
+    stock_cache.add_ohlc(priced_bar("AAPL", open, 1, 10.0, 10.0, 100.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("AAPL", open + 1000, 1, 20.0, 20.0, 300.0)).unwrap();
+    // Coarser bars cover the same trades and aren't counted again.
+    stock_cache.add_ohlc(priced_bar("AAPL", open, 60, 50.0, 50.0, 400.0)).unwrap();
+
+    let session_info = stock_cache.get_session(&name).unwrap();
+
+    assert_eq!(session_info.session_start, open);
+    assert_eq!(session_info.interval, 1);
+    assert_eq!(session_info.vwap, Some(17.5));
+    assert_eq!(session_info.twap, Some(15.0));
+    assert_eq!(session_info.volume, 400.0);
+    assert_eq!(session_info.bars, 2);
+    assert_eq!(session_info.last_timestamp, open + 1000);
+
+    // A correction replaces the contribution of the bar.
+    stock_cache.add_ohlc(priced_bar("AAPL", open + 1000, 1, 20.0, 20.0, 100.0)).unwrap();
+
+    let session_info = stock_cache.get_session(&name).unwrap();
+
+    assert_eq!(session_info.vwap, Some(15.0));
+    assert_eq!(session_info.bars, 2);
+
+    // Late bars of the previous session are ignored, the next session starts over.
+    stock_cache.add_ohlc(priced_bar("AAPL", open - 1000, 1, 100.0, 100.0, 100.0)).unwrap();
+
+    assert_eq!(stock_cache.get_session(&name).unwrap().vwap, Some(15.0));
+
+    stock_cache.add_ohlc(priced_bar("AAPL", open + DAY_MS, 1, 30.0, 30.0, 10.0)).unwrap();
+
+    let session_info = stock_cache.get_session(&name).unwrap();
+
+    assert_eq!(session_info.session_start, open + DAY_MS);
+    assert_eq!(session_info.vwap, Some(30.0));
+    assert_eq!(session_info.bars, 1);
+}
+
+#[test]
+fn weights_the_twap_by_the_time_each_close_was_in_effect() {
+    let stock_cache = new_stock_cache();
+    let name = "AAPL".to_owned();
+    let open = 10 * DAY_MS + 13 * HOUR_MS + 30 * 60_000;
+    let twap = |stock_cache: &StockInformationCacheInterface| stock_cache.get_session(&name).unwrap().twap.unwrap();
+
+    stock_cache.add_ohlc(priced_bar("AAPL", open, 1, 10.0, 10.0, 100.0)).unwrap();
+    stock_cache.add_ohlc(priced_bar("AAPL", open + 10_000, 1, 40.0, 40.0, 100.0)).unwrap();
+
+    // 10 for 10 seconds, then 40 for its own second.
+    assert!((twap(&stock_cache) - 140.0 / 11.0).abs() < 1e-9);
+
+    // A late bar splits the time of the close before it.
+    stock_cache.add_ohlc(priced_bar("AAPL", open + 9000, 1, 20.0, 20.0, 100.0)).unwrap();
+
+    assert!((twap(&stock_cache) - 150.0 / 11.0).abs() < 1e-9);
+
+    // A correction keeps the time of the bar.
+    stock_cache.add_ohlc(priced_bar("AAPL", open + 10_000, 1, 30.0, 30.0, 100.0)).unwrap();
+
+    assert!((twap(&stock_cache) - 140.0 / 11.0).abs() < 1e-9);
+}
+
+#[test]
+fn session_survives_a_snapshot() {
+    let stock_cache = new_stock_cache();
+    let name = "AAPL".to_owned();
+
+    for i in 0..5 {
+        stock_cache.add_ohlc(priced_bar("AAPL", i * 1000, 1, 10.0 + i as f64, 10.0 + i as f64, 10.0)).unwrap();
+    }
+
+    let restored = new_stock_cache();
+    restored.restore(stock_cache.snapshot());
+
+    assert_eq!(restored.get_session(&name), stock_cache.get_session(&name));
+}
+
+#[test]
+fn subscribers_can_ask_for_session_updates() {
+    let mut config = ServerConfig::default();
+    config.feed.enabled = false;
+    config.cache.session_start = "13:30".parse().unwrap();
+
+    let server = WebSocketServer::new(config);
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 1, 10.0, 10.0, 100.0)).unwrap();
+
+    let (id, mut events) = connection_service.add_subscriber();
+    let (other_id, mut other_events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &stock_name, &BarFilter { session: true, ..BarFilter::default() }).unwrap();
+    connection_service.add_stock_subscription(other_id, &stock_name, &BarFilter::default()).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["type"], "session");
+    assert_eq!(event["session"]["vwap"], 10.0);
+    assert_eq!(event["session"]["session_start"].as_u64(), Some(0));
+
+    while events.try_recv().is_ok() {}
+    while other_events.try_recv().is_ok() {}
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 1000, 1, 20.0, 20.0, 100.0)).unwrap();
+
+    let bar: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(bar["timestamp"], 1000);
+    assert_eq!(event["type"], "session");
+    assert_eq!(event["session"]["vwap"], 15.0);
+    assert_eq!(event["session"]["twap"], 15.0);
+
+    assert!(other_events.try_recv().is_ok());
+    assert!(other_events.try_recv().is_err());
+}
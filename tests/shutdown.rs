@@ -0,0 +1,54 @@
+mod common;
+
+use serde_json::Value;
+use tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+use common::{connect_client, ohlc_model, spawn_server};
+use stock_messenger::WebSocketServer;
+
+#[test]
+fn shutdown_sends_close_frame_and_joins() {
+    let server = WebSocketServer::builder()
+        .server_address("127.0.0.1:0")
+        .without_feed()
+        .build()
+        .unwrap();
+
+    let shutdown = server.shutdown_handle();
+    let connection_service = server.connection_service();
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+
+    let (server, server_thread) = spawn_server(server);
+    let mut client = connect_client(&server);
+
+    client.send(Message::Text("{\"stock\": \"AAPL\"}".to_owned())).unwrap();
+
+    loop {
+        match client.read().unwrap() {
+            Message::Text(v) if v == "End of Update" => break,
+            _ => continue,
+        };
+    }
+
+    // Queued right before the shutdown, the bar still goes out ahead of the close frame.
+    connection_service.publish_ohlc(ohlc_model("AAPL", 1000, 1)).unwrap();
+    shutdown.shutdown();
+
+    let mut timestamps = Vec::new();
+
+    let close_code = loop {
+        match client.read().unwrap() {
+            Message::Text(v) => timestamps.push(serde_json::from_str::<Value>(&v).unwrap()["timestamp"].clone()),
+            Message::Close(Some(frame)) => break frame.code,
+            Message::Close(None) => panic!("Close frame without code"),
+            _ => continue,
+        };
+    };
+
+    assert_eq!(timestamps, vec![Value::from(1000)]);
+    assert_eq!(close_code, CloseCode::Away);
+
+    server_thread.join().unwrap();
+    assert!(server.local_addr().is_none());
+}
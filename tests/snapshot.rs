@@ -0,0 +1,79 @@
+mod common;
+
+use std::{collections::BTreeMap, fs, process};
+
+use common::ohlc_model;
+use stock_messenger::{
+    value_store::{BarFilter, BarFormat, BasketSpec, BasketWeighting},
+    StockInformationCacheInterface,
+};
+
+fn stock_cache() -> StockInformationCacheInterface {
+    StockInformationCacheInterface::new(BTreeMap::from([(1, 5), (60, 5)]), 10, false)
+}
+
+#[test]
+fn restores_a_saved_cache() {
+    let path = std::env::temp_dir().join(format!("stock_messenger_snapshot_{}.json", process::id()));
+    let data_feed = "DataFeed".to_owned();
+
+    let original = stock_cache();
+
+    for i in 0..8 {
+        original.add_ohlc(ohlc_model("MSFT", i * 1000, 1)).unwrap();
+        original.add_ohlc(ohlc_model("AAPL", i * 60_000, 60)).unwrap();
+    }
+
+    original.retrieve_data_events(1000);
+    original.retrieve_data_events(2000);
+    original.add_ohlc(ohlc_model("AAPL", 8 * 60_000, 60)).unwrap();
+    original.add_basket(BasketSpec {
+        name: "TECH".to_owned(),
+        symbols: vec!["AAPL".to_owned(), "MSFT".to_owned()],
+        weighting: BasketWeighting::Value,
+    });
+
+    original.save_snapshot(&path).unwrap();
+
+    let restored = stock_cache();
+    restored.load_snapshot(&path).unwrap();
+
+    for (name, interval) in [("MSFT", 1), ("AAPL", 60), ("AAPL", 1)] {
+        assert_eq!(
+            restored.get_history(&name.to_owned(), interval),
+            original.get_history(&name.to_owned(), interval),
+        );
+    }
+
+    assert_eq!(restored.get_symbols(), original.get_symbols());
+    assert_eq!(
+        restored.get_vec_of_stock(&data_feed, BarFormat::V1, &BarFilter::default()),
+        original.get_vec_of_stock(&data_feed, BarFormat::V1, &BarFilter::default()),
+    );
+    assert_eq!(restored.retrieve_data_events(3000), original.retrieve_data_events(3000));
+    assert_eq!(
+        restored.get_baskets().into_iter().map(|(spec, _)| spec).collect::<Vec<_>>(),
+        original.get_baskets().into_iter().map(|(spec, _)| spec).collect::<Vec<_>>(),
+    );
+
+    let smaller = StockInformationCacheInterface::new(BTreeMap::from([(60, 2)]), 10, false);
+    smaller.load_snapshot(&path).unwrap();
+
+    assert_eq!(smaller.get_history(&"AAPL".to_owned(), 60).len(), 2);
+    assert!(smaller.get_history(&"MSFT".to_owned(), 1).is_empty());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rejects_missing_and_invalid_snapshots() {
+    let path = std::env::temp_dir().join(format!("stock_messenger_invalid_snapshot_{}.json", process::id()));
+    let stock_cache = stock_cache();
+
+    assert!(stock_cache.load_snapshot(&path).is_err());
+
+    fs::write(&path, "{\"snapshot_version\": 1, \"stocks\": [").unwrap();
+    assert!(stock_cache.load_snapshot(&path).is_err());
+
+    fs::remove_file(&path).unwrap();
+}
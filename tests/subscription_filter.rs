@@ -0,0 +1,220 @@
+mod common;
+
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+
+use common::ohlc_model;
+use stock_messenger::{
+    config::ServerConfig,
+    value_store::{BarFilter, BarFormat},
+    websockets::protocol::ErrorCode,
+    WebSocketServer,
+};
+
+fn bar_filter(intervals: &[u128], depth: Option<usize>) -> BarFilter {
+    BarFilter {
+        intervals: Some(intervals.iter().cloned().collect::<BTreeSet<u128>>()),
+        depth,
+        session: false,
+    }
+}
+
+fn intervals(events: &[String]) -> Vec<(u64, u64)> {
+    events
+        .iter()
+        .filter_map(|v| serde_json::from_str::<Value>(v).ok())
+        .map(|v| (v["stock_interval"].as_u64().unwrap(), v["timestamp"].as_u64().unwrap()))
+        .collect()
+}
+
+#[test]
+fn snapshot_and_live_bars_follow_the_filter() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .bar_format(BarFormat::V1)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+
+    for i in 0..5 {
+        connection_service.publish_ohlc(ohlc_model("AAPL", i * 60_000, 60)).unwrap();
+        connection_service.publish_ohlc(ohlc_model("AAPL", i * 60_000, 1)).unwrap();
+    }
+
+    let (id, mut events) = connection_service.add_subscriber();
+    let stock_name = "AAPL".to_owned();
+
+    connection_service.add_stock_subscription(id, &stock_name, &bar_filter(&[60], Some(2))).unwrap();
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 300_000, 1)).unwrap();
+    connection_service.publish_ohlc(ohlc_model("AAPL", 300_000, 60)).unwrap();
+
+    let mut received = Vec::new();
+
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+
+    assert_eq!(received[2], "End of Update");
+    assert_eq!(intervals(&received), vec![(60, 180_000), (60, 240_000), (60, 300_000)]);
+}
+
+#[test]
+fn rejects_unconfigured_intervals() {
+    let server = WebSocketServer::builder().without_feed().build().unwrap();
+    let connection_service = server.connection_service();
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+
+    let (id, _events) = connection_service.add_subscriber();
+
+    assert_eq!(
+        connection_service.add_stock_subscription(id, &"AAPL".to_owned(), &bar_filter(&[1, 7], None)),
+        Err(ErrorCode::UnknownInterval),
+    );
+    assert!(connection_service.get_subscriptions(id).is_empty());
+}
+
+#[test]
+fn snapshots_are_queued_whole_or_refused() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .queue_capacity(4)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+
+    for i in 0..5 {
+        connection_service.publish_ohlc(ohlc_model("AAPL", i * 1000, 1)).unwrap();
+    }
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    assert_eq!(
+        connection_service.add_stock_subscription(id, &stock_name, &bar_filter(&[1], None)),
+        Err(ErrorCode::SnapshotTooLarge),
+    );
+    assert!(connection_service.get_subscriptions(id).is_empty());
+    assert!(events.try_recv().is_err());
+
+    connection_service.add_stock_subscription(id, &stock_name, &bar_filter(&[1], Some(3))).unwrap();
+
+    let mut received = Vec::new();
+
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+
+    assert_eq!(received.len(), 4);
+    assert_eq!(received[3], "End of Update");
+
+    // A full queue can't take the snapshot of a pending subscription either.
+    let (other_id, mut other_events) = connection_service.add_subscriber();
+    let other_stock_name = "MSFT".to_owned();
+
+    connection_service.add_stock_subscription(other_id, &stock_name, &bar_filter(&[1], Some(3))).unwrap();
+    connection_service.add_stock_subscription(other_id, &other_stock_name, &BarFilter::default()).unwrap();
+    connection_service.publish_ohlc(ohlc_model(&other_stock_name, 0, 1)).unwrap();
+
+    assert_eq!(connection_service.get_subscriptions(other_id), vec![stock_name.clone()]);
+
+    let mut received = Vec::new();
+
+    while let Ok(event) = other_events.try_recv() {
+        received.push(event);
+    }
+
+    assert_eq!(received.len(), 4);
+    assert!(received.iter().all(|v| !v.contains("MSFT")));
+}
+
+#[test]
+fn one_connection_receives_all_of_its_subscriptions() {
+    let server = WebSocketServer::builder()
+        .without_feed()
+        .bar_format(BarFormat::V1)
+        .build()
+        .unwrap();
+
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+    let other_stock_name = "MSFT".to_owned();
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+    connection_service.publish_ohlc(ohlc_model(&other_stock_name, 0, 1)).unwrap();
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default()).unwrap();
+    connection_service.add_stock_subscription(id, &other_stock_name, &bar_filter(&[1], None)).unwrap();
+
+    assert_eq!(connection_service.get_subscriptions(id), vec![stock_name.clone(), other_stock_name.clone()]);
+
+    let mut received = Vec::new();
+
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+
+    assert_eq!(received.iter().filter(|v| *v == "End of Update").count(), 2);
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 1000, 1)).unwrap();
+    connection_service.publish_ohlc(ohlc_model(&other_stock_name, 1000, 1)).unwrap();
+
+    let symbols: Vec<String> = std::iter::from_fn(|| events.try_recv().ok())
+        .map(|v| serde_json::from_str::<Value>(&v).unwrap()["stock_name"].as_str().unwrap().to_owned())
+        .collect();
+
+    assert_eq!(symbols, vec![stock_name.clone(), other_stock_name.clone()]);
+
+    assert!(connection_service.remove_stock_subscription(id, &stock_name));
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 2000, 1)).unwrap();
+    connection_service.publish_ohlc(ohlc_model(&other_stock_name, 2000, 1)).unwrap();
+
+    let event: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(event["stock_name"], "MSFT");
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn subscriptions_are_limited_per_connection() {
+    let mut config = ServerConfig::default();
+    config.feed.enabled = false;
+    config.server.max_subscriptions = 2;
+
+    let server = WebSocketServer::new(config);
+    let connection_service = server.connection_service();
+    let stock_name = "AAPL".to_owned();
+    let data_feed = "DataFeed".to_owned();
+
+    connection_service.publish_ohlc(ohlc_model("AAPL", 0, 1)).unwrap();
+
+    let (id, _events) = connection_service.add_subscriber();
+    let (other_id, _other_events) = connection_service.add_subscriber();
+
+    connection_service.add_stock_subscription(id, &stock_name, &BarFilter::default()).unwrap();
+    connection_service.add_stock_subscription(id, &"MSFT".to_owned(), &BarFilter::default()).unwrap();
+
+    // Pending subscriptions count too.
+    assert_eq!(
+        connection_service.add_stock_subscription(id, &data_feed, &BarFilter::default()),
+        Err(ErrorCode::QuotaExceeded),
+    );
+    assert_eq!(ErrorCode::QuotaExceeded.as_str(), "quota_exceeded");
+
+    // Changing the filter of a subscription doesn't take another one.
+    connection_service.add_stock_subscription(id, &stock_name, &bar_filter(&[1], Some(1))).unwrap();
+    connection_service.add_stock_subscription(other_id, &data_feed, &BarFilter::default()).unwrap();
+
+    assert!(connection_service.remove_stock_subscription(id, &"MSFT".to_owned()));
+
+    connection_service.add_stock_subscription(id, &data_feed, &BarFilter::default()).unwrap();
+
+    assert_eq!(connection_service.get_subscriptions(id), vec![stock_name, data_feed]);
+}
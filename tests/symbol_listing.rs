@@ -0,0 +1,55 @@
+mod common;
+
+use common::ohlc_model;
+use stock_messenger::{config::ServerConfig, WebSocketServer};
+
+fn names(symbols: &[(String, u128)]) -> Vec<&str> {
+    symbols.iter().map(|(v, _)| v.as_str()).collect()
+}
+
+#[test]
+fn lists_searches_and_pages_symbols() {
+    let mut config = ServerConfig::default();
+    config.feed.enabled = false;
+    config.server.max_list_symbols = 3;
+
+    let server = WebSocketServer::new(config);
+    let connection_service = server.connection_service();
+
+    for stock_name in ["MSFT", "AAPL", "AAL", "BRK B", "aapx", "GOOG"] {
+        connection_service.publish_ohlc(ohlc_model(stock_name, 0, 1)).unwrap();
+    }
+
+    let (total, symbols) = connection_service.list_symbols(None, None, 0, None);
+
+    assert_eq!(total, 6);
+    assert_eq!(names(&symbols), vec!["AAL", "AAPL", "BRK B"]);
+
+    let (total, symbols) = connection_service.list_symbols(None, None, 3, Some(10));
+
+    assert_eq!(total, 6);
+    assert_eq!(names(&symbols), vec!["GOOG", "MSFT", "aapx"]);
+
+    let (total, symbols) = connection_service.list_symbols(Some("aap"), None, 0, None);
+
+    assert_eq!(total, 2);
+    assert_eq!(names(&symbols), vec!["AAPL", "aapx"]);
+
+    let (_, symbols) = connection_service.list_symbols(None, Some("k b"), 0, None);
+
+    assert_eq!(names(&symbols), vec!["BRK B"]);
+
+    let (_, symbols) = connection_service.list_symbols(Some("A"), Some("L"), 0, Some(1));
+
+    assert_eq!(names(&symbols), vec!["AAL"]);
+
+    let (total, symbols) = connection_service.list_symbols(Some("ZZ"), None, 0, None);
+
+    assert_eq!(total, 0);
+    assert!(symbols.is_empty());
+
+    let last_update = connection_service.stock_cache().get_last_update(&"AAL".to_owned()).unwrap();
+
+    assert!(last_update > 0);
+    assert_eq!(connection_service.list_symbols(Some("AAL"), None, 0, None).1, vec![("AAL".to_owned(), last_update)]);
+}
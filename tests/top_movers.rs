@@ -0,0 +1,116 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use common::priced_bar;
+use stock_messenger::{
+    config::ServerConfig,
+    value_store::{BarFilter, MoversWindow},
+    websockets::SubscriptionState,
+    OHLCModel,
+    StockInformationCacheInterface,
+    WebSocketServer,
+};
+
+fn symbols(value: &Value) -> Vec<&str> {
+    value.as_array().unwrap().iter().map(|v| v["symbol"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn ranks_moves_over_the_window() {
+    let stock_cache = StockInformationCacheInterface::new(BTreeMap::from([(1, 10), (60, 10)]), 10, false);
+
+    assert!(stock_cache.top_movers(0).is_none());
+
+    stock_cache.set_movers_window(MoversWindow { stock_interval: 60, bars: 2, size: 2 });
+
+    // Outside of the window ending at 240s, which starts at 120s.
+    stock_cache.add_ohlc(OHLCModel { trades: 100, ..priced_bar("AAPL", 60_000, 60, 1.0, 100.0, 1000.0) }).unwrap();
+
+    stock_cache.add_ohlc(OHLCModel { trades: 5, ..priced_bar("AAPL", 120_000, 60, 10.0, 11.0, 10.0) }).unwrap();
+    stock_cache.add_ohlc(OHLCModel { trades: 5, ..priced_bar("AAPL", 180_000, 60, 11.0, 12.0, 10.0) }).unwrap();
+    stock_cache.add_ohlc(OHLCModel { trades: 20, ..priced_bar("MSFT", 180_000, 60, 100.0, 90.0, 5.0) }).unwrap();
+    stock_cache.add_ohlc(priced_bar("NVDA", 120_000, 60, 50.0, 55.0, 100.0)).unwrap();
+    stock_cache.add_ohlc(OHLCModel { trades: 3, ..priced_bar("TSLA", 180_000, 60, 20.0, 20.0, 1.0) }).unwrap();
+    stock_cache.add_ohlc(OHLCModel { trades: 3, ..priced_bar("AMZN", 120_000, 60, 40.0, 39.0, 2.0) }).unwrap();
+    // Only bars of the window interval count.
+    stock_cache.add_ohlc(OHLCModel { trades: 99, ..priced_bar("META", 180_000, 1, 1.0, 9.0, 99.0) }).unwrap();
+
+    let top_movers: Value = serde_json::from_str(&stock_cache.top_movers(240_000).unwrap().to_json()).unwrap();
+
+    assert_eq!(top_movers["interval"], 60);
+    assert_eq!(top_movers["window"], 2);
+    assert_eq!(top_movers["timestamp"], 240_000);
+    assert_eq!(top_movers["stock_n"], 5);
+
+    assert_eq!(symbols(&top_movers["gainers"]), vec!["AAPL", "NVDA"]);
+    assert_eq!(symbols(&top_movers["losers"]), vec!["MSFT", "AMZN"]);
+    assert_eq!(symbols(&top_movers["most_traded"]), vec!["MSFT", "AAPL"]);
+    assert_eq!(symbols(&top_movers["highest_volume"]), vec!["NVDA", "AAPL"]);
+
+    let aapl = &top_movers["gainers"][0];
+
+    assert_eq!(aapl["change_pct"], 20.0);
+    assert_eq!(aapl["price"], 12.0);
+    assert_eq!(aapl["trades"], 10);
+    assert_eq!(aapl["volume"], 20.0);
+
+    // Once every bar is out of the window nothing is ranked.
+    let top_movers = stock_cache.top_movers(600_000).unwrap();
+
+    assert_eq!(top_movers.stock_n, 0);
+    assert!(top_movers.gainers.is_empty());
+}
+
+#[test]
+fn top_movers_are_published_on_the_tick() {
+    let mut config = ServerConfig::default();
+    config.feed.enabled = false;
+    config.top_movers.interval = Some(60);
+    config.top_movers.window = 5;
+
+    assert!(config.validate().is_ok());
+
+    let server = WebSocketServer::new(config);
+    let connection_service = server.connection_service();
+    let topic = "TopMovers".to_owned();
+
+    connection_service.publish_ohlc(priced_bar("AAPL", 0, 60, 10.0, 11.0, 1.0)).unwrap();
+
+    // Nobody subscribed, nothing is ranked.
+    connection_service.sync_data_events(60_000);
+
+    let (id, mut events) = connection_service.add_subscriber();
+
+    assert_eq!(connection_service.add_stock_subscription(id, &topic, &BarFilter::default()), Ok(SubscriptionState::Active));
+
+    let snapshot: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(snapshot["interval"], 60);
+    assert_eq!(events.try_recv().unwrap(), "End of Update");
+
+    connection_service.publish_ohlc(priced_bar("MSFT", 60_000, 60, 10.0, 9.0, 1.0)).unwrap();
+    connection_service.sync_data_events(120_000);
+
+    let top_movers: Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+
+    assert_eq!(top_movers["timestamp"], 120_000);
+    assert_eq!(symbols(&top_movers["gainers"]), vec!["AAPL"]);
+    assert_eq!(symbols(&top_movers["losers"]), vec!["MSFT"]);
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn rejects_an_unconfigured_window_interval() {
+    let mut config = ServerConfig::default();
+    config.top_movers.interval = Some(7);
+
+    assert!(config.validate().is_err());
+
+    config.set_value("top_movers.interval", "").unwrap();
+
+    assert!(config.validate().is_ok());
+    assert_eq!(config.top_movers.movers_window(&config.cache).stock_interval, 1);
+}